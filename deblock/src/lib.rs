@@ -0,0 +1,411 @@
+//! Deblocking filter (H.263 Annex J)
+//!
+//! The filter smooths the four samples straddling each 8x8 block edge of a
+//! reconstructed picture, with a strength chosen by the quantizer of the
+//! macroblocks on either side of the edge.
+//!
+//! This crate is `no_std`, and does not allocate.
+
+#![no_std]
+
+use wide::{i32x8, CmpLt};
+
+/// The filter strength to use for each `QUANT` value.
+///
+/// This is H.263 (2005/01) Table J.2. Index 0 is not a valid quantizer.
+const STRENGTH: [u8; 32] = [
+    0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5, 6, 6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11,
+    12, 12, 12,
+];
+
+/// The strongest filter in `STRENGTH`.
+const MAX_STRENGTH: i32 = 12;
+
+/// Look up the Table J.2 filter strength for a `QUANT` value.
+///
+/// Quantizers above 31 are clamped to 31, and the invalid quantizer 0 yields a
+/// strength of 0, which disables the filter.
+pub fn strength_for_quant(quant: u8) -> u8 {
+    STRENGTH[quant.min(31) as usize]
+}
+
+/// The `UpDownRamp` function of H.263 Annex J.3.
+///
+/// This passes small differences through unchanged, but attenuates larger
+/// ones (which are likely to be real picture content) back down to zero.
+fn up_down_ramp(x: i32, strength: i32) -> i32 {
+    x.signum() * 0.max(x.abs() - 0.max(2 * (x.abs() - strength)))
+}
+
+/// Filter the four samples at `positions`, which run across a block edge
+/// with the edge between the second and third.
+///
+/// `strength` is clamped to the range of `STRENGTH`; a strength of 0 leaves
+/// the samples untouched.
+fn filter_edge(samples: &mut [u8], positions: [usize; 4], strength: i32) {
+    let strength = strength.clamp(0, MAX_STRENGTH);
+    if strength == 0 {
+        return;
+    }
+
+    let [a, b, c, d] = positions.map(|p| samples[p] as i32);
+
+    let d1 = up_down_ramp((a - 4 * b + 4 * c - d) / 8, strength);
+    let d2 = ((a - d) / 4).clamp(-(d1 / 2).abs(), (d1 / 2).abs());
+
+    for (position, value) in positions.iter().zip([a - d2, b + d1, c - d1, d + d2]) {
+        samples[*position] = value.clamp(0, 255) as u8;
+    }
+}
+
+/// Filter the four samples of a single block edge, with the edge between the
+/// second and third sample.
+///
+/// This is the filter that `deblock_frame` applies to every edge, and
+/// `strength` is a filter strength from Table J.2, such as one returned by
+/// `strength_for_quant`, not a quantizer. It is clamped to the range of that
+/// table, and a strength of 0 leaves the samples untouched.
+pub fn deblock_edge(samples: &mut [u8; 4], strength: u8) {
+    filter_edge(samples, [0, 1, 2, 3], strength as i32);
+}
+
+/// Divide each lane by `2^shift`, rounding towards zero like `/` does.
+fn div_pow2(x: i32x8, shift: i32) -> i32x8 {
+    let negative_bias = (x >> 31) & i32x8::splat((1 << shift) - 1);
+
+    (x + negative_bias) >> shift
+}
+
+/// Filter eight block edges at once, with the same `strength`.
+///
+/// Each of the four rows of `samples` holds one of the four samples of eight
+/// independent edges, which run down the columns; the edges lie between the
+/// second and third row. This is the layout of a horizontal block edge, eight
+/// samples wide. The results are identical to calling `deblock_edge` on each
+/// column.
+pub fn deblock_edge_x8(samples: &mut [[u8; 8]; 4], strength: u8) {
+    let strength = (strength as i32).clamp(0, MAX_STRENGTH);
+    if strength == 0 {
+        return;
+    }
+
+    let [a, b, c, d] = samples.map(|row| i32x8::new(row.map(|sample| sample as i32)));
+    let zero = i32x8::splat(0);
+
+    // `up_down_ramp`, with the sign applied separately as there is no
+    // `signum` for vectors.
+    let x = div_pow2(a - b * 4 + c * 4 - d, 3);
+    let magnitude = x.abs();
+    let ramped = zero.max(magnitude - zero.max((magnitude - strength) * 2));
+    let d1 = x.cmp_lt(zero).blend(zero - ramped, ramped);
+
+    let limit = div_pow2(d1, 1).abs();
+    let d2 = div_pow2(a - d, 2).max(zero - limit).min(limit);
+
+    let max = i32x8::splat(255);
+    for (row, value) in samples.iter_mut().zip([a - d2, b + d1, c - d1, d + d2]) {
+        let value = value.max(zero).min(max).to_array();
+        *row = value.map(|sample| sample as u8);
+    }
+}
+
+/// Determine the filter strength for an edge between two macroblocks.
+///
+/// The quantizer of the macroblock `after` the edge is used if it was coded,
+/// otherwise that of the macroblock `before` it. Edges between two uncoded
+/// macroblocks, and edges whose strength would be 0, are not filtered, which
+/// is signalled with `None`.
+fn edge_strength(quantizers: &[u8], coded: &[bool], before: usize, after: usize) -> Option<i32> {
+    let macroblock = if coded[after] {
+        after
+    } else if coded[before] {
+        before
+    } else {
+        return None;
+    };
+
+    Some(strength_for_quant(quantizers[macroblock]) as i32).filter(|&strength| strength > 0)
+}
+
+/// Apply the deblocking filter to one plane of a reconstructed picture.
+///
+/// `mb_size` is the size of a macroblock within this plane; 16 for luma and
+/// 8 for chroma. `quantizers` and `coded` hold the `QUANT` value each
+/// macroblock was reconstructed with and whether it carried any coefficients,
+/// in raster order, for `mb_per_line` macroblocks per row.
+///
+/// Horizontal block edges are filtered first, then vertical edges. Edges
+/// within two samples of the picture border are left alone. If no coded
+/// macroblock has a nonzero filter strength, the plane is not touched at all.
+pub fn deblock_plane(
+    samples: &mut [u8],
+    samples_per_row: usize,
+    mb_size: usize,
+    mb_per_line: usize,
+    quantizers: &[u8],
+    coded: &[bool],
+) {
+    let any_filtered = quantizers
+        .iter()
+        .zip(coded)
+        .any(|(&quantizer, &coded)| coded && strength_for_quant(quantizer) > 0);
+    if !any_filtered {
+        return;
+    }
+
+    let height = samples.len() / samples_per_row;
+    let macroblock_at = |x: usize, y: usize| (y / mb_size) * mb_per_line + x / mb_size;
+
+    for y in (8..height.saturating_sub(1)).step_by(8) {
+        for x in 0..samples_per_row {
+            let strength = edge_strength(
+                quantizers,
+                coded,
+                macroblock_at(x, y - 1),
+                macroblock_at(x, y),
+            );
+
+            if let Some(strength) = strength {
+                let position = y * samples_per_row + x;
+                filter_edge(
+                    samples,
+                    [
+                        position - 2 * samples_per_row,
+                        position - samples_per_row,
+                        position,
+                        position + samples_per_row,
+                    ],
+                    strength,
+                );
+            }
+        }
+    }
+
+    for y in 0..height {
+        for x in (8..samples_per_row.saturating_sub(1)).step_by(8) {
+            let strength = edge_strength(
+                quantizers,
+                coded,
+                macroblock_at(x - 1, y),
+                macroblock_at(x, y),
+            );
+
+            if let Some(strength) = strength {
+                let position = y * samples_per_row + x;
+                filter_edge(
+                    samples,
+                    [position - 2, position - 1, position, position + 1],
+                    strength,
+                );
+            }
+        }
+    }
+}
+
+/// Apply the deblocking filter to all three planes of a reconstructed 4:2:0
+/// picture.
+///
+/// `luma_samples_per_row` is the width of the picture; the chroma planes are
+/// half as wide, rounded up, and there is one macroblock for every 16 luma
+/// samples of each row. As in Annex J.3, chroma edges take their strength
+/// from the same `quantizers` and `coded` flags as luma edges.
+pub fn deblock_frame(
+    luma: &mut [u8],
+    chroma_b: &mut [u8],
+    chroma_r: &mut [u8],
+    luma_samples_per_row: usize,
+    quantizers: &[u8],
+    coded: &[bool],
+) {
+    let mb_per_line = luma_samples_per_row.div_ceil(16);
+    let chroma_samples_per_row = luma_samples_per_row.div_ceil(2);
+
+    deblock_plane(
+        luma,
+        luma_samples_per_row,
+        16,
+        mb_per_line,
+        quantizers,
+        coded,
+    );
+    for chroma in [chroma_b, chroma_r] {
+        deblock_plane(
+            chroma,
+            chroma_samples_per_row,
+            8,
+            mb_per_line,
+            quantizers,
+            coded,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        deblock_edge, deblock_edge_x8, deblock_frame, deblock_plane, filter_edge,
+        strength_for_quant,
+    };
+
+    #[test]
+    fn small_steps_are_smoothed() {
+        let mut samples = [100, 100, 110, 110];
+        filter_edge(&mut samples, [0, 1, 2, 3], 4);
+
+        assert_eq!([101, 103, 107, 109], samples);
+    }
+
+    #[test]
+    fn large_steps_are_kept() {
+        let mut samples = [20, 20, 220, 220];
+        filter_edge(&mut samples, [0, 1, 2, 3], 12);
+
+        assert_eq!([20, 20, 220, 220], samples);
+    }
+
+    #[test]
+    fn uncoded_edges_are_not_filtered() {
+        // Two 8x8 chroma macroblocks, side by side.
+        let mut samples = [0; 16 * 8];
+        for row in samples.chunks_mut(16) {
+            row[8..].fill(6);
+        }
+
+        let mut filtered = samples;
+        deblock_plane(&mut filtered, 16, 8, 2, &[8, 8], &[false, false]);
+        assert_eq!(samples, filtered);
+
+        deblock_plane(&mut filtered, 16, 8, 2, &[8, 8], &[false, true]);
+        for row in filtered.chunks(16) {
+            assert_eq!([0, 1, 2, 4, 5, 6], row[5..11]);
+        }
+    }
+
+    #[test]
+    fn zero_strength_is_a_no_op() {
+        let mut samples = [100, 100, 110, 110];
+        filter_edge(&mut samples, [0, 1, 2, 3], 0);
+        assert_eq!([100, 100, 110, 110], samples);
+
+        let mut samples = [0; 16 * 8];
+        for row in samples.chunks_mut(16) {
+            row[8..].fill(6);
+        }
+
+        // `QUANT` 0 is out of range, and maps to no filtering.
+        let mut filtered = samples;
+        deblock_plane(&mut filtered, 16, 8, 2, &[0, 0], &[true, true]);
+        assert_eq!(samples, filtered);
+    }
+
+    #[test]
+    fn quant_strengths() {
+        assert_eq!(0, strength_for_quant(0));
+        assert_eq!(1, strength_for_quant(1));
+        assert_eq!(12, strength_for_quant(31));
+        assert_eq!(12, strength_for_quant(32));
+    }
+
+    #[test]
+    fn strength_is_clamped() {
+        let mut strongest = [100, 100, 110, 110];
+        filter_edge(&mut strongest, [0, 1, 2, 3], 12);
+
+        let mut clamped = [100, 100, 110, 110];
+        filter_edge(&mut clamped, [0, 1, 2, 3], 200);
+        assert_eq!(strongest, clamped);
+    }
+
+    #[test]
+    fn frame_filters_all_planes() {
+        // Two macroblocks, side by side, with a step between them.
+        let mut luma = [0; 32 * 16];
+        for row in luma.chunks_mut(32) {
+            row[16..].fill(6);
+        }
+        let mut chroma_b = [0; 16 * 8];
+        for row in chroma_b.chunks_mut(16) {
+            row[8..].fill(6);
+        }
+        let mut chroma_r = [10; 16 * 8];
+        for row in chroma_r.chunks_mut(16) {
+            row[8..].fill(4);
+        }
+
+        let (mut planewise_luma, mut planewise_b, mut planewise_r) = (luma, chroma_b, chroma_r);
+        deblock_plane(&mut planewise_luma, 32, 16, 2, &[8, 8], &[true, true]);
+        deblock_plane(&mut planewise_b, 16, 8, 2, &[8, 8], &[true, true]);
+        deblock_plane(&mut planewise_r, 16, 8, 2, &[8, 8], &[true, true]);
+
+        let (original_luma, original_b, original_r) = (luma, chroma_b, chroma_r);
+        deblock_frame(
+            &mut luma,
+            &mut chroma_b,
+            &mut chroma_r,
+            32,
+            &[8, 8],
+            &[true, true],
+        );
+
+        assert_eq!(planewise_luma, luma);
+        assert_eq!(planewise_b, chroma_b);
+        assert_eq!(planewise_r, chroma_r);
+        assert_ne!(original_luma, luma);
+        assert_ne!(original_b, chroma_b);
+        assert_ne!(original_r, chroma_r);
+    }
+
+    #[test]
+    fn simd_edges_match_scalar() {
+        let mut seed = 0x1234_5678u32;
+        let mut edges = [[0; 8]; 4];
+        for strength in 0..=14 {
+            for round in 0..64 {
+                // A simple LCG, with every other round biased towards small
+                // steps across the edge, which the filter actually changes.
+                for lane in 0..8 {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    let base = (seed >> 24) as u8;
+                    for row in edges.iter_mut() {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        row[lane] = if round % 2 == 0 {
+                            base.saturating_add((seed >> 28) as u8)
+                        } else {
+                            (seed >> 24) as u8
+                        };
+                    }
+                }
+
+                let mut expected = edges;
+                for lane in 0..8 {
+                    let mut column = [0, 1, 2, 3].map(|row| expected[row][lane]);
+                    deblock_edge(&mut column, strength);
+                    for (row, sample) in expected.iter_mut().zip(column) {
+                        row[lane] = sample;
+                    }
+                }
+
+                let mut actual = edges;
+                deblock_edge_x8(&mut actual, strength);
+                assert_eq!(expected, actual, "strength {}, input {:?}", strength, edges);
+            }
+        }
+
+        // The extremes, where the intermediate values are largest.
+        for strength in [1, 12] {
+            for edge in [
+                [0, 255, 0, 255],
+                [255, 0, 255, 0],
+                [0, 0, 255, 255],
+                [255, 255, 0, 0],
+            ] {
+                let mut expected = edge;
+                deblock_edge(&mut expected, strength);
+
+                let mut actual = edge.map(|sample| [sample; 8]);
+                deblock_edge_x8(&mut actual, strength);
+                assert_eq!(expected.map(|sample| [sample; 8]), actual);
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Times the IDCT over dense and sparse blocks.
+//!
+//! Run with `cargo bench --bench idct`.
+
+use h263_rs::transform::idct_8x8;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Fill a block with pseudorandom coefficients of up to `magnitude`, keeping
+/// only the first `nonzero` of them in row-major order.
+fn random_block(seed: &mut u32, magnitude: i32, nonzero: usize) -> [i16; 64] {
+    let mut block = [0; 64];
+    for coefficient in block.iter_mut().take(nonzero) {
+        *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        *coefficient = ((*seed >> 8) as i32 % (magnitude + 1)) as i16;
+    }
+
+    block
+}
+
+fn time(name: &str, blocks: &[[i16; 64]]) {
+    let start = Instant::now();
+    let mut checksum = 0i64;
+    for _ in 0..100 {
+        for block in blocks {
+            let mut block = *block;
+            idct_8x8(black_box(&mut block));
+            checksum += block[29] as i64;
+        }
+    }
+
+    println!("{}: {:?} (checksum {})", name, start.elapsed(), checksum);
+}
+
+fn main() {
+    let mut seed = 1;
+    let dense: Vec<_> = (0..1000)
+        .map(|_| random_block(&mut seed, 300, 64))
+        .collect();
+    let sparse: Vec<_> = (0..1000).map(|_| random_block(&mut seed, 300, 8)).collect();
+
+    time("dense", &dense);
+    time("sparse", &sparse);
+}
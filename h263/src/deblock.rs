@@ -0,0 +1,5 @@
+//! Deblocking filter (H.263 Annex J), for use outside of the decoder.
+//!
+//! These are re-exported from the `h263-rs-deblock` crate, which is `no_std`.
+
+pub use crate::decoder::{deblock_edge, deblock_edge_x8, deblock_frame, strength_for_quant};
@@ -1,10 +1,24 @@
 //! H.263 video decoder.
 
+#[cfg(feature = "async")]
+mod async_decode;
 mod cpu;
+mod features;
 mod picture;
+mod pool;
 mod state;
+mod stream;
 mod types;
 
-pub use picture::DecodedPicture;
-pub use state::H263State;
-pub use types::DecoderOption;
+pub use cpu::{deblock_edge, deblock_edge_x8, deblock_frame, idct_8x8, strength_for_quant};
+pub use features::{supported_features, FeatureSet};
+pub use picture::{
+    ColorConfig, ColorMatrix, ColorRange, DecodedFrame, DecodedPicture, PictureMeta, Rect,
+};
+pub use pool::PicturePool;
+pub use state::{DecoderBuilder, H263State};
+pub use stream::H263Stream;
+pub use types::{DecoderOption, DecoderOptionBuilder, QuantizedBlock};
+
+#[cfg(test)]
+pub(crate) use state::tests as state_tests;
@@ -0,0 +1,226 @@
+//! Asynchronous picture decoding
+
+use crate::decoder::picture::DecodedPicture;
+use crate::decoder::state::H263State;
+use crate::error::Result;
+use crate::parser::H263Reader;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The number of bytes requested from an asynchronous source at a time.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Find the first byte-aligned picture start code in `data`, starting the
+/// search at byte offset `from`.
+///
+/// Standard pictures are recognized by a group number of zero; Sorenson
+/// pictures by a version of zero or one.
+fn find_picture_start_code(data: &[u8], from: usize, is_sorenson: bool) -> Option<usize> {
+    let trailer_mask = if is_sorenson { 0xF8 } else { 0xFC };
+
+    data.get(from..)?
+        .windows(3)
+        .position(|w| w[0] == 0 && w[1] == 0 && w[2] & trailer_mask == 0x80)
+        .map(|pos| pos + from)
+}
+
+/// A future that returns control to the executor once before completing.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+
+            Poll::Pending
+        }
+    }
+}
+
+impl H263State {
+    /// Read and decode the next picture from an asynchronous source.
+    ///
+    /// This behaves like `decode_next_picture`, except that bits are read
+    /// from `reader` without blocking. Data is buffered until either the next
+    /// picture start code or the end of the source is seen; bytes read past
+    /// the end of the current picture are retained by this decoder for the
+    /// next call. For this reason, the same source must be used for every
+    /// call, and picture start codes in it must be byte-aligned.
+    ///
+    /// Control is returned to the executor after every read and once more
+    /// before each picture is decoded. The decode itself is not interrupted.
+    ///
+    /// If the picture fails to decode, it is discarded, and the next call
+    /// will continue with the picture after it.
+    pub async fn decode_next_picture_async<R>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<&DecodedPicture>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if self.last_error().is_some() {
+            return Ok(None);
+        }
+
+        loop {
+            let result = if self.is_flushing_reorder_queue() {
+                Ok(None)
+            } else {
+                self.decode_next_in_order_async(reader).await
+            };
+
+            if let Some(result) = self.reorder_decoded(result) {
+                return self.finish_decode(result);
+            }
+        }
+    }
+
+    /// Read and decode the next picture from an asynchronous source, in
+    /// decode order.
+    ///
+    /// Yields the temporal reference of the decoded picture, or `None` if the
+    /// source ended before a picture could be found.
+    async fn decode_next_in_order_async<R>(&mut self, reader: &mut R) -> Result<Option<u16>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if let Some(this_tr) = self.release_held_picture() {
+            return Ok(Some(this_tr));
+        }
+
+        let is_sorenson = self.is_sorenson();
+        let mut scan_from = 1;
+        let picture_length = loop {
+            if let Some(next) = find_picture_start_code(&self.async_buffer, scan_from, is_sorenson)
+            {
+                break next;
+            }
+
+            let old_length = self.async_buffer.len();
+            scan_from = old_length.saturating_sub(2).max(1);
+
+            self.async_buffer.resize(old_length + READ_CHUNK_SIZE, 0);
+            let read = reader.read(&mut self.async_buffer[old_length..]).await;
+            self.async_buffer
+                .truncate(old_length + *read.as_ref().unwrap_or(&0));
+            let read_length = read?;
+
+            if read_length == 0 {
+                break self.async_buffer.len();
+            }
+        };
+
+        YieldNow(false).await;
+
+        let buffer = std::mem::take(&mut self.async_buffer);
+        let result = self.decode_next_in_order(
+            &mut H263Reader::from_source(&buffer[..picture_length]),
+            &mut None,
+        );
+        self.async_buffer = buffer;
+        self.async_buffer.drain(..picture_length);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state::tests::{write_flat_iframe, write_uncoded_pframe, BitWriter};
+    use crate::decoder::{DecoderOption, H263State};
+    use crate::parser::H263Reader;
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    /// An asynchronous source that hands out a few bytes at a time, and is
+    /// only ready on every other poll.
+    struct TrickleReader {
+        data: Vec<u8>,
+        position: usize,
+        ready: bool,
+    }
+
+    impl AsyncRead for TrickleReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+
+                return Poll::Pending;
+            }
+
+            let end = (self.position + 7).min(self.data.len());
+            let count = (end - self.position).min(buf.remaining());
+            buf.put_slice(&self.data[self.position..self.position + count]);
+            self.position += count;
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let mut context = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn async_matches_sync() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_uncoded_pframe(&mut w, 1);
+        write_flat_iframe(&mut w, 2, 0xC0);
+        let data = w.into_bytes();
+
+        let mut sync_state = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut sync_reader = H263Reader::from_source(&data[..]);
+        let mut async_state = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut async_reader = TrickleReader {
+            data: data.clone(),
+            position: 0,
+            ready: false,
+        };
+
+        for _ in 0..3 {
+            let expected = sync_state
+                .decode_next_picture(&mut sync_reader)
+                .unwrap()
+                .unwrap()
+                .as_luma()
+                .to_vec();
+            let actual = block_on(async_state.decode_next_picture_async(&mut async_reader))
+                .unwrap()
+                .unwrap()
+                .as_luma()
+                .to_vec();
+
+            assert_eq!(expected, actual);
+        }
+
+        assert!(
+            block_on(async_state.decode_next_picture_async(&mut async_reader))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -5,7 +5,8 @@ mod idct;
 mod mvd_pred;
 mod rle;
 
-pub use gather::gather;
-pub use idct::idct_channel;
-pub use mvd_pred::{mv_decode, predict_candidate};
-pub use rle::inverse_rle;
+pub use gather::{gather, gather_bidirectional};
+pub use h263_rs_deblock::{deblock_edge, deblock_edge_x8, deblock_frame, strength_for_quant};
+pub use idct::{idct_8x8, idct_channel};
+pub use mvd_pred::{mv_decode, pb_vectors, predict_candidate};
+pub use rle::{inverse_rle, quantized_levels};
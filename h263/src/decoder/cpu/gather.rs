@@ -1,8 +1,9 @@
 //! Intra block data collection
 
 use crate::decoder::picture::DecodedPicture;
-use crate::error::Error;
+use crate::error::{Error, Warning};
 use crate::types::{MacroblockType, MotionVector};
+use std::ops::Range;
 
 /// Read a sample from the pixel array at a given position.
 ///
@@ -33,24 +34,26 @@ fn read_sample(
 /// Linear interpolation between two values by 0 or 50%.
 fn lerp(sample_a: u8, sample_b: u8, middle: bool) -> u8 {
     if middle {
-        ((sample_a as u16 + sample_b as u16 + 1) / 2) as u8
+        (sample_a as u16 + sample_b as u16).div_ceil(2) as u8
     } else {
         sample_a
     }
 }
 
-/// Copy pixel data from a pixel array, motion-compensate it, and fill a block
-/// with the given data.
+/// Copy pixel data from a pixel array and motion-compensate it into an 8x8
+/// block of samples, in row-major (x + y*8) order.
 ///
-/// Target block and source pixel array are written to in row-major (x + y*8)
-/// order.
-fn gather_block(
+/// Only the samples of the block that lie within the pixel array are filled;
+/// the rest are left at zero.
+///
+/// Also yields `true` if the motion vector referenced samples outside of the
+/// pixel array, which were clamped to its edges.
+fn predict_block(
     pixel_array: &[u8],
     samples_per_row: usize,
     pos: (usize, usize),
     mv: MotionVector,
-    target: &mut [u8],
-) {
+) -> ([[u8; 8]; 8], bool) {
     let ((x_delta, x_interp), (y_delta, y_interp)) = mv.into_lerp_parameters();
 
     let x = pos.0 as isize + x_delta as isize;
@@ -60,11 +63,17 @@ fn gather_block(
     let block_cols = (samples_per_row as isize - pos.0 as isize).clamp(0, 8);
     let block_rows = (array_height as isize - pos.1 as isize).clamp(0, 8);
 
+    let is_clamped = x < 0
+        || y < 0
+        || x + block_cols + x_interp as isize > samples_per_row as isize
+        || y + block_rows + y_interp as isize > array_height as isize;
+
+    let mut block = [[0; 8]; 8];
+
     if !x_interp && !y_interp {
         for (j, v) in (y..y + block_rows).enumerate() {
             for (i, u) in (x..x + block_cols).enumerate() {
-                target[pos.0 + i + ((pos.1 + j) * samples_per_row)] =
-                    read_sample(pixel_array, samples_per_row, array_height, (u, v));
+                block[j][i] = read_sample(pixel_array, samples_per_row, array_height, (u, v));
             }
         }
     } else {
@@ -88,17 +97,217 @@ fn gather_block(
                         + 2) // for proper rounding
                         / 4) as u8;
 
-                    target[pos.0 + i + ((pos.1 + j) * samples_per_row)] = sample;
+                    block[j][i] = sample;
                 } else {
                     let sample_mid_0 = lerp(sample_0_0, sample_1_0, x_interp);
                     let sample_mid_1 = lerp(sample_0_1, sample_1_1, x_interp);
 
-                    target[pos.0 + i + ((pos.1 + j) * samples_per_row)] =
-                        lerp(sample_mid_0, sample_mid_1, y_interp);
+                    block[j][i] = lerp(sample_mid_0, sample_mid_1, y_interp);
                 }
             }
         }
     }
+
+    (block, is_clamped)
+}
+
+/// Write an 8x8 block of samples into a pixel array at a given position.
+///
+/// Samples that would fall outside of the pixel array are dropped.
+fn write_block(
+    block: &[[u8; 8]; 8],
+    samples_per_row: usize,
+    pos: (usize, usize),
+    target: &mut [u8],
+) {
+    let array_height = target.len() / samples_per_row;
+    let block_cols = samples_per_row.saturating_sub(pos.0).min(8);
+    let block_rows = array_height.saturating_sub(pos.1).min(8);
+
+    for (j, row) in block.iter().enumerate().take(block_rows) {
+        let start = pos.0 + (pos.1 + j) * samples_per_row;
+        target[start..start + block_cols].copy_from_slice(&row[..block_cols]);
+    }
+}
+
+/// Copy pixel data from a pixel array, motion-compensate it, and fill a block
+/// with the given data.
+///
+/// Target block and source pixel array are written to in row-major (x + y*8)
+/// order.
+///
+/// Yields `true` if the motion vector referenced samples outside of the pixel
+/// array, which were clamped to its edges.
+fn gather_block(
+    pixel_array: &[u8],
+    samples_per_row: usize,
+    pos: (usize, usize),
+    mv: MotionVector,
+    target: &mut [u8],
+) -> bool {
+    let (block, is_clamped) = predict_block(pixel_array, samples_per_row, pos, mv);
+    write_block(&block, samples_per_row, pos, target);
+
+    is_clamped
+}
+
+/// The weights of the prediction using a block's own motion vector.
+///
+/// This is H.263 (2005/01) Figure F.2.
+#[rustfmt::skip]
+const OBMC_CURRENT_WEIGHTS: [[u16; 8]; 8] = [
+    [4, 5, 5, 5, 5, 5, 5, 4],
+    [5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 6, 6, 6, 6, 5, 5],
+    [5, 5, 6, 6, 6, 6, 5, 5],
+    [5, 5, 6, 6, 6, 6, 5, 5],
+    [5, 5, 6, 6, 6, 6, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5],
+    [4, 5, 5, 5, 5, 5, 5, 4],
+];
+
+/// The weights of the predictions using the motion vectors of the blocks
+/// above and below.
+///
+/// This is H.263 (2005/01) Figure F.3.
+#[rustfmt::skip]
+const OBMC_VERTICAL_WEIGHTS: [[u16; 8]; 8] = [
+    [2, 2, 2, 2, 2, 2, 2, 2],
+    [1, 1, 2, 2, 2, 2, 1, 1],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 2, 2, 2, 2, 1, 1],
+    [2, 2, 2, 2, 2, 2, 2, 2],
+];
+
+/// The weights of the predictions using the motion vectors of the blocks to
+/// the left and right.
+///
+/// This is H.263 (2005/01) Figure F.4.
+#[rustfmt::skip]
+const OBMC_HORIZONTAL_WEIGHTS: [[u16; 8]; 8] = [
+    [2, 1, 1, 1, 1, 1, 1, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 2, 1, 1, 1, 1, 2, 2],
+    [2, 1, 1, 1, 1, 1, 1, 2],
+];
+
+/// The motion vectors that contribute to the overlapped motion compensation
+/// of one luma block.
+struct OverlappedVectors {
+    current: MotionVector,
+    above: MotionVector,
+    below: MotionVector,
+    left: MotionVector,
+    right: MotionVector,
+}
+
+/// Motion-compensate a luma block with overlapped block motion compensation,
+/// as per H.263 (2005/01) F.3, and fill it into the target pixel array.
+///
+/// Each sample is a weighted average of the predictions made with the block's
+/// own motion vector, that of the block above or below it (whichever is
+/// nearer), and that of the block to its left or right (likewise).
+///
+/// Yields `true` if any of the motion vectors referenced samples outside of
+/// the pixel array, which were clamped to its edges.
+fn gather_overlapped_block(
+    pixel_array: &[u8],
+    samples_per_row: usize,
+    pos: (usize, usize),
+    mvs: &OverlappedVectors,
+    target: &mut [u8],
+) -> bool {
+    let (current, mut is_clamped) = predict_block(pixel_array, samples_per_row, pos, mvs.current);
+    let mut remote = |mv| {
+        let (block, remote_clamped) = predict_block(pixel_array, samples_per_row, pos, mv);
+        is_clamped |= remote_clamped;
+
+        block
+    };
+    let above = remote(mvs.above);
+    let below = remote(mvs.below);
+    let left = remote(mvs.left);
+    let right = remote(mvs.right);
+
+    let mut block = [[0; 8]; 8];
+    for (j, row) in block.iter_mut().enumerate() {
+        let vertical = if j < 4 { &above } else { &below };
+
+        for (i, sample) in row.iter_mut().enumerate() {
+            let horizontal = if i < 4 { &left } else { &right };
+
+            *sample = ((current[j][i] as u16 * OBMC_CURRENT_WEIGHTS[j][i]
+                + vertical[j][i] as u16 * OBMC_VERTICAL_WEIGHTS[j][i]
+                + horizontal[j][i] as u16 * OBMC_HORIZONTAL_WEIGHTS[j][i]
+                + 4)
+                / 8) as u8;
+        }
+    }
+
+    write_block(&block, samples_per_row, pos, target);
+
+    is_clamped
+}
+
+/// Determine the motion vectors for the overlapped motion compensation of
+/// luma block `block` of macroblock `mb`.
+///
+/// Per H.263 (2005/01) F.3, neighboring blocks in intra macroblocks, or past
+/// the edge of the picture, contribute the current block's motion vector
+/// instead of their own. Uncoded macroblocks already have zero motion
+/// vectors. The macroblock below is not used; its blocks also contribute the
+/// current motion vector.
+fn overlapped_vectors(
+    mb_types: &[MacroblockType],
+    mvs: &[[MotionVector; 4]],
+    mb_per_line: usize,
+    mb: usize,
+    block: usize,
+) -> OverlappedVectors {
+    let current = mvs[mb][block];
+    let neighbor = |neighbor_mb: Option<usize>, neighbor_block: usize| match neighbor_mb {
+        Some(neighbor_mb) if !mb_types[neighbor_mb].is_intra() => mvs[neighbor_mb][neighbor_block],
+        _ => current,
+    };
+    let column = mb % mb_per_line;
+
+    let (above, below) = if block < 2 {
+        (
+            neighbor(mb.checked_sub(mb_per_line), block + 2),
+            mvs[mb][block + 2],
+        )
+    } else {
+        (mvs[mb][block - 2], current)
+    };
+
+    let (left, right) = if block.is_multiple_of(2) {
+        let left_mb = if column > 0 { Some(mb - 1) } else { None };
+
+        (neighbor(left_mb, block + 1), mvs[mb][block + 1])
+    } else {
+        let right_mb = if column + 1 < mb_per_line {
+            Some(mb + 1)
+        } else {
+            None
+        };
+
+        (mvs[mb][block - 1], neighbor(right_mb, block - 1))
+    };
+
+    OverlappedVectors {
+        current,
+        above,
+        below,
+        left,
+        right,
+    }
 }
 
 /// Copy pixels from a previously decoded reference picture into a new picture.
@@ -113,12 +322,27 @@ fn gather_block(
 ///
 /// For `INTRA` coded macroblocks, the returned set of blocks will be all
 /// zeroes.
+///
+/// If `is_overlapped`, luma blocks are predicted with the overlapped block
+/// motion compensation of Annex F, Advanced Prediction mode.
+///
+/// If `is_luma_only`, the chroma planes of `new_picture` are left untouched.
+///
+/// Motion vectors that point outside of the reference picture are clamped to
+/// its edges. Unless `is_unrestricted`, as with Annexes D and F, such vectors
+/// are out of range, and a `Warning` for the affected macroblock is added to
+/// `warnings`.
+#[allow(clippy::too_many_arguments)]
 pub fn gather(
     mb_types: &[MacroblockType],
     reference_picture: Option<&DecodedPicture>,
     mvs: &[[MotionVector; 4]],
     mb_per_line: usize,
+    is_overlapped: bool,
+    is_unrestricted: bool,
+    is_luma_only: bool,
     new_picture: &mut DecodedPicture,
+    warnings: &mut Vec<Warning>,
 ) -> Result<(), Error> {
     for (i, (mb_type, mv)) in mb_types.iter().zip(mvs.iter()).enumerate() {
         if mb_type.is_inter() {
@@ -126,55 +350,286 @@ pub fn gather(
             let luma_samples_per_row = reference_picture.luma_samples_per_row();
             let pos = ((i % mb_per_line) * 16, (i / mb_per_line) * 16);
 
-            gather_block(
-                reference_picture.as_luma(),
-                luma_samples_per_row,
-                pos,
-                mv[0],
-                new_picture.as_luma_mut(),
-            );
-            gather_block(
-                reference_picture.as_luma(),
-                luma_samples_per_row,
-                (pos.0 + 8, pos.1),
-                mv[1],
-                new_picture.as_luma_mut(),
-            );
-            gather_block(
-                reference_picture.as_luma(),
-                luma_samples_per_row,
-                (pos.0, pos.1 + 8),
-                mv[2],
-                new_picture.as_luma_mut(),
-            );
-            gather_block(
-                reference_picture.as_luma(),
+            let mut is_clamped = false;
+            for (block, offset) in [(0, 0), (8, 0), (0, 8), (8, 8)].iter().enumerate() {
+                let block_pos = (pos.0 + offset.0, pos.1 + offset.1);
+
+                is_clamped |= if is_overlapped {
+                    gather_overlapped_block(
+                        reference_picture.as_luma(),
+                        luma_samples_per_row,
+                        block_pos,
+                        &overlapped_vectors(mb_types, mvs, mb_per_line, i, block),
+                        new_picture.as_luma_mut(),
+                    )
+                } else {
+                    gather_block(
+                        reference_picture.as_luma(),
+                        luma_samples_per_row,
+                        block_pos,
+                        mv[block],
+                        new_picture.as_luma_mut(),
+                    )
+                };
+            }
+
+            if !is_luma_only {
+                let mv_chr = (mv[0] + mv[1] + mv[2] + mv[3]).average_sum_of_mvs();
+                let chroma_samples_per_row = reference_picture.chroma_samples_per_row();
+                let chroma_pos = ((i % mb_per_line) * 8, (i / mb_per_line) * 8);
+
+                is_clamped |= gather_block(
+                    reference_picture.as_chroma_b(),
+                    chroma_samples_per_row,
+                    (chroma_pos.0, chroma_pos.1),
+                    mv_chr,
+                    new_picture.as_chroma_b_mut(),
+                );
+                is_clamped |= gather_block(
+                    reference_picture.as_chroma_r(),
+                    chroma_samples_per_row,
+                    (chroma_pos.0, chroma_pos.1),
+                    mv_chr,
+                    new_picture.as_chroma_r_mut(),
+                );
+            }
+
+            if is_clamped && !is_unrestricted {
+                warnings.push(Warning::MotionVectorClamped { macroblock: i });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Predict a B-block of a PB frame from both of its anchor pictures, and fill
+/// the block with it.
+///
+/// Samples whose backward motion vector points inside of the co-located
+/// P-macroblock, which spans `macroblock` columns and rows of the backward
+/// pixel array, are predicted from the average of the forward and backward
+/// predictions. All other samples are predicted forward only. This is H.263
+/// (2005/01) G.5.
+#[allow(clippy::too_many_arguments)]
+fn gather_bidirectional_block(
+    forward_array: &[u8],
+    backward_array: &[u8],
+    samples_per_row: usize,
+    pos: (usize, usize),
+    macroblock: (Range<isize>, Range<isize>),
+    forward_mv: MotionVector,
+    backward_mv: MotionVector,
+    target: &mut [u8],
+) {
+    let (mut block, _) = predict_block(forward_array, samples_per_row, pos, forward_mv);
+    let (backward, _) = predict_block(backward_array, samples_per_row, pos, backward_mv);
+    let ((x_delta, _), (y_delta, _)) = backward_mv.into_lerp_parameters();
+
+    for (j, (row, backward_row)) in block.iter_mut().zip(backward.iter()).enumerate() {
+        let v = (pos.1 + j) as isize + y_delta as isize;
+
+        for (i, (sample, backward_sample)) in row.iter_mut().zip(backward_row).enumerate() {
+            let u = (pos.0 + i) as isize + x_delta as isize;
+
+            if macroblock.0.contains(&u) && macroblock.1.contains(&v) {
+                *sample = ((*sample as u16 + *backward_sample as u16) / 2) as u8;
+            }
+        }
+    }
+
+    write_block(&block, samples_per_row, pos, target);
+}
+
+/// Motion-compensate the B-picture of a PB frame.
+///
+/// `forward_picture` is the previous reference picture, and
+/// `backward_picture` is the P-picture decoded alongside the B-picture. `mvs`
+/// holds the forward and backward motion vectors of each B-macroblock's
+/// luma blocks, in raster order.
+///
+/// If `is_luma_only`, the chroma planes of `new_picture` are left untouched.
+///
+/// Motion vectors that point outside of either picture are clamped to its
+/// edges.
+pub fn gather_bidirectional(
+    forward_picture: &DecodedPicture,
+    backward_picture: &DecodedPicture,
+    mvs: &[([MotionVector; 4], [MotionVector; 4])],
+    mb_per_line: usize,
+    is_luma_only: bool,
+    new_picture: &mut DecodedPicture,
+) {
+    let luma_samples_per_row = forward_picture.luma_samples_per_row();
+    let chroma_samples_per_row = forward_picture.chroma_samples_per_row();
+
+    for (i, (forward_mv, backward_mv)) in mvs.iter().enumerate() {
+        let pos = ((i % mb_per_line) * 16, (i / mb_per_line) * 16);
+        let macroblock = (
+            pos.0 as isize..pos.0 as isize + 16,
+            pos.1 as isize..pos.1 as isize + 16,
+        );
+
+        for (block, offset) in [(0, 0), (8, 0), (0, 8), (8, 8)].iter().enumerate() {
+            gather_bidirectional_block(
+                forward_picture.as_luma(),
+                backward_picture.as_luma(),
                 luma_samples_per_row,
-                (pos.0 + 8, pos.1 + 8),
-                mv[3],
+                (pos.0 + offset.0, pos.1 + offset.1),
+                macroblock.clone(),
+                forward_mv[block],
+                backward_mv[block],
                 new_picture.as_luma_mut(),
             );
+        }
 
-            let mv_chr = (mv[0] + mv[1] + mv[2] + mv[3]).average_sum_of_mvs();
-            let chroma_samples_per_row = reference_picture.chroma_samples_per_row();
-            let chroma_pos = ((i % mb_per_line) * 8, (i / mb_per_line) * 8);
+        if is_luma_only {
+            continue;
+        }
 
-            gather_block(
-                reference_picture.as_chroma_b(),
-                chroma_samples_per_row,
-                (chroma_pos.0, chroma_pos.1),
-                mv_chr,
-                new_picture.as_chroma_b_mut(),
-            );
-            gather_block(
-                reference_picture.as_chroma_r(),
-                chroma_samples_per_row,
-                (chroma_pos.0, chroma_pos.1),
-                mv_chr,
-                new_picture.as_chroma_r_mut(),
-            );
+        let [f1, f2, f3, f4] = *forward_mv;
+        let [b1, b2, b3, b4] = *backward_mv;
+        let forward_chr = (f1 + f2 + f3 + f4).average_sum_of_mvs();
+        let backward_chr = (b1 + b2 + b3 + b4).average_sum_of_mvs();
+        let chroma_pos = ((i % mb_per_line) * 8, (i / mb_per_line) * 8);
+        let chroma_macroblock = (
+            chroma_pos.0 as isize..chroma_pos.0 as isize + 8,
+            chroma_pos.1 as isize..chroma_pos.1 as isize + 8,
+        );
+
+        gather_bidirectional_block(
+            forward_picture.as_chroma_b(),
+            backward_picture.as_chroma_b(),
+            chroma_samples_per_row,
+            chroma_pos,
+            chroma_macroblock.clone(),
+            forward_chr,
+            backward_chr,
+            new_picture.as_chroma_b_mut(),
+        );
+        gather_bidirectional_block(
+            forward_picture.as_chroma_r(),
+            backward_picture.as_chroma_r(),
+            chroma_samples_per_row,
+            chroma_pos,
+            chroma_macroblock,
+            forward_chr,
+            backward_chr,
+            new_picture.as_chroma_r_mut(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::cpu::gather::{gather, gather_bidirectional};
+    use crate::decoder::picture::tests::blank_picture;
+    use crate::error::Warning;
+    use crate::types::{HalfPel, MacroblockType, MotionVector, SourceFormat};
+
+    #[test]
+    fn motion_vector_past_right_edge() {
+        let mut reference = blank_picture(SourceFormat::SubQcif);
+        for (i, luma) in reference.as_luma_mut().iter_mut().enumerate() {
+            *luma = (i % 128) as u8;
+        }
+
+        let mb_types = vec![MacroblockType::Inter; 8 * 6];
+        let mut mvs = vec![[MotionVector::zero(); 4]; 8 * 6];
+        mvs[7] = [MotionVector::from((HalfPel::from_unit(2), HalfPel::zero())); 4];
+
+        let mut picture = blank_picture(SourceFormat::SubQcif);
+        let mut warnings = Vec::new();
+        gather(
+            &mb_types,
+            Some(&reference),
+            &mvs,
+            8,
+            false,
+            false,
+            false,
+            &mut picture,
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![Warning::MotionVectorClamped { macroblock: 7 }],
+            warnings
+        );
+
+        // The last column is repeated to fill the one past the edge.
+        for y in 0..16 {
+            let row = &picture.as_luma()[y * 128 + 112..y * 128 + 128];
+            let expected: Vec<u8> = (113..128).chain(std::iter::once(127)).collect();
+            assert_eq!(&expected[..], row);
         }
     }
 
-    Ok(())
+    #[test]
+    fn unrestricted_motion_vector_is_not_reported() {
+        let reference = blank_picture(SourceFormat::SubQcif);
+
+        let mb_types = vec![MacroblockType::Inter; 8 * 6];
+        let mut mvs = vec![[MotionVector::zero(); 4]; 8 * 6];
+        mvs[7] = [MotionVector::from((HalfPel::from_unit(2), HalfPel::zero())); 4];
+
+        let mut picture = blank_picture(SourceFormat::SubQcif);
+        let mut warnings = Vec::new();
+        gather(
+            &mb_types,
+            Some(&reference),
+            &mvs,
+            8,
+            false,
+            true,
+            false,
+            &mut picture,
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert_eq!(Vec::<Warning>::new(), warnings);
+    }
+
+    #[test]
+    fn luma_only_skips_chroma_prediction() {
+        let mut reference = blank_picture(SourceFormat::SubQcif);
+        reference.as_luma_mut().fill(50);
+        reference.as_chroma_b_mut().fill(60);
+        reference.as_chroma_r_mut().fill(70);
+
+        let mb_types = vec![MacroblockType::Inter; 8 * 6];
+        let mvs = vec![[MotionVector::zero(); 4]; 8 * 6];
+
+        let mut picture = blank_picture(SourceFormat::SubQcif);
+        picture.as_chroma_b_mut().fill(1);
+        picture.as_chroma_r_mut().fill(1);
+        gather(
+            &mb_types,
+            Some(&reference),
+            &mvs,
+            8,
+            false,
+            false,
+            true,
+            &mut picture,
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        assert!(picture.as_luma().iter().all(|&l| l == 50));
+        assert!(picture.as_chroma_b().iter().all(|&c| c == 1));
+        assert!(picture.as_chroma_r().iter().all(|&c| c == 1));
+
+        let b_mvs = vec![([MotionVector::zero(); 4], [MotionVector::zero(); 4]); 8 * 6];
+        let mut b_picture = blank_picture(SourceFormat::SubQcif);
+        b_picture.as_chroma_b_mut().fill(1);
+        b_picture.as_chroma_r_mut().fill(1);
+        gather_bidirectional(&reference, &picture, &b_mvs, 8, true, &mut b_picture);
+
+        assert!(b_picture.as_luma().iter().all(|&l| l == 50));
+        assert!(b_picture.as_chroma_b().iter().all(|&c| c == 1));
+        assert!(b_picture.as_chroma_r().iter().all(|&c| c == 1));
+    }
 }
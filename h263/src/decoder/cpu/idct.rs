@@ -1,6 +1,6 @@
 //! Inverse discrete cosine transform
 
-use std::cmp::{max, min};
+use wide::f32x8;
 
 /*
 use lazy_static::lazy_static;
@@ -50,7 +50,7 @@ const BASIS_TABLE: [[f32; 8]; 8] = [
 /// Performs a one-dimensional IDCT on the input, using some lookup tables
 /// for the scaling of the DC component, and for the cosine values to be used.
 #[inline(never)]
-fn idct_1d(input: &[f32; 8], output: &mut [f32; 8]) {
+fn idct_1d_scalar(input: &[f32; 8], output: &mut [f32; 8]) {
     *output = [0.0; 8];
     for (i, out) in output.iter_mut().enumerate() {
         // Do your magic, autovectorizer! Thanks...
@@ -60,11 +60,19 @@ fn idct_1d(input: &[f32; 8], output: &mut [f32; 8]) {
     }
 }
 
+/// Performs eight one-dimensional IDCTs at once, across the rows of `input`.
+///
+/// Rows whose bit is clear in `row_mask` are assumed to be all zeroes, and
+/// are skipped.
 #[inline(never)]
-fn idct_1d_8xt(input: &[[f32; 8]; 8], output: &mut [[f32; 8]; 8]) {
+fn idct_1d_8xt_scalar(input: &[[f32; 8]; 8], row_mask: u8, output: &mut [[f32; 8]; 8]) {
     *output = [[0.0; 8]; 8];
 
     for (freq, input_row) in input.iter().enumerate() {
+        if row_mask & (1 << freq) == 0 {
+            continue;
+        }
+
         let basis = &BASIS_TABLE[freq];
         for (b, output_row) in basis.iter().zip(output.iter_mut()) {
             // Do your magic, autovectorizer! Thanks...
@@ -75,6 +83,130 @@ fn idct_1d_8xt(input: &[[f32; 8]; 8], output: &mut [[f32; 8]; 8]) {
     }
 }
 
+/// Performs a one-dimensional IDCT on the input, as `idct_1d_scalar` does,
+/// summing the contribution of each frequency to all outputs at once.
+///
+/// The products are summed in the same order as in `idct_1d_scalar`, so the
+/// output is exactly the same.
+#[inline(never)]
+fn idct_1d_simd(input: &[f32; 8], output: &mut [f32; 8]) {
+    let mut sum = f32x8::ZERO;
+    for (coefficient, basis) in input.iter().zip(BASIS_TABLE.iter()) {
+        sum += f32x8::new(*basis) * *coefficient;
+    }
+
+    *output = sum.to_array();
+}
+
+/// Performs eight one-dimensional IDCTs at once, as `idct_1d_8xt_scalar`
+/// does, with one vector per row.
+///
+/// The output is exactly the same as that of `idct_1d_8xt_scalar`.
+#[inline(never)]
+fn idct_1d_8xt_simd(input: &[[f32; 8]; 8], row_mask: u8, output: &mut [[f32; 8]; 8]) {
+    let mut sums = [f32x8::ZERO; 8];
+
+    for (freq, input_row) in input.iter().enumerate() {
+        if row_mask & (1 << freq) == 0 {
+            continue;
+        }
+
+        let input_row = f32x8::new(*input_row);
+        for (b, sum) in BASIS_TABLE[freq].iter().zip(sums.iter_mut()) {
+            *sum += input_row * *b;
+        }
+    }
+
+    for (sum, output_row) in sums.iter().zip(output.iter_mut()) {
+        *output_row = sum.to_array();
+    }
+}
+
+/// Whether the target has vector registers for `idct_block` to use.
+///
+/// On other targets, the vector types fall back to plain arrays, which would
+/// only be slower than the scalar loops.
+const USE_SIMD: bool = cfg!(any(
+    target_feature = "sse2",
+    target_feature = "neon",
+    target_feature = "simd128"
+));
+
+/// Transform a single block out of the frequency domain.
+///
+/// Bit `u * 8 + v` of `mask` must be set if `block[u][v]` is nonzero. Sparse
+/// blocks take faster paths that yield exactly the same result as the full
+/// transform: a DC-only block is flat, and `u`s with no nonzero coefficients,
+/// or only a DC one, skip their one-dimensional IDCT.
+fn idct_block(block: &[[f32; 8]; 8], mask: u64, output: &mut [[f32; 8]; 8]) {
+    if mask == 1 {
+        *output = [[block[0][0] * BASIS_TABLE[0][0] * BASIS_TABLE[0][0]; 8]; 8];
+        return;
+    }
+
+    let mut idct_intermediate: [[f32; 8]; 8] = [[0.0; 8]; 8];
+    let mut row_mask = 0;
+
+    for (row, intermediate) in idct_intermediate.iter_mut().enumerate() {
+        match (mask >> (row * 8)) as u8 {
+            0 => continue,
+            1 => {
+                for (out, basis) in intermediate.iter_mut().zip(BASIS_TABLE[0].iter()) {
+                    *out = block[row][0] * basis;
+                }
+            }
+            _ if USE_SIMD => idct_1d_simd(&block[row], intermediate),
+            _ => idct_1d_scalar(&block[row], intermediate),
+        }
+
+        row_mask |= 1 << row;
+    }
+
+    if USE_SIMD {
+        idct_1d_8xt_simd(&idct_intermediate, row_mask, output);
+    } else {
+        idct_1d_8xt_scalar(&idct_intermediate, row_mask, output);
+    }
+}
+
+/// Transform a single 8x8 block of coefficients out of the frequency domain,
+/// in place.
+///
+/// `coefficients` holds dezigzagged transform coefficients in row-major
+/// (u + v*8) order, and is overwritten with the spatial samples they encode,
+/// in the same order. Samples are rounded to the nearest integer and clipped
+/// to the range -256 to 255, exactly as the decoder does before adding them
+/// to the prediction.
+pub fn idct_8x8(coefficients: &mut [i16; 64]) {
+    let mut block = [[0.0; 8]; 8];
+    let mut mask = 0;
+    for (i, coefficient) in coefficients.iter().enumerate() {
+        if *coefficient != 0 {
+            block[i / 8][i % 8] = *coefficient as f32;
+            mask |= 1 << i;
+        }
+    }
+
+    if mask == 0 {
+        return;
+    }
+
+    // The 2D IDCT is separable, and thus commutes with transposition, so the
+    // order of the indices of `block` does not matter.
+    let mut output = [[0.0; 8]; 8];
+    idct_block(&block, mask, &mut output);
+
+    for (sample, idct) in coefficients.iter_mut().zip(output.iter().flatten()) {
+        *sample = round_sample(*idct);
+    }
+}
+
+/// Scale, round and clip a raw IDCT output value to a sample difference.
+#[inline]
+fn round_sample(idct: f32) -> i16 {
+    ((idct / 4.0 + idct.signum() * 0.5) as i16).clamp(-256, 255)
+}
+
 /// Given a list of reconstructed IDCT levels, transform it out of the
 /// frequency domain.
 ///
@@ -83,6 +215,10 @@ fn idct_1d_8xt(input: &[[f32; 8]; 8], output: &mut [[f32; 8]; 8]) {
 /// order. It must have a width equal to `samples_per_line` and dimensions
 /// divisible by 8.
 ///
+/// `block_masks` holds a bitmask of the nonzero coefficients of each block,
+/// as filled in by `inverse_rle`; bit `u * 8 + v` is set if `block[u][v]`
+/// may be nonzero. Blocks with no bits set are skipped entirely.
+///
 /// The `output` of this IDCT is represented as an arbitrarily-sized list of
 /// `u8`s, also in row-major order and formatted in the same way as
 /// `block_levels`. If this is an INTER block and predicted pixel data already
@@ -92,6 +228,7 @@ fn idct_1d_8xt(input: &[[f32; 8]; 8], output: &mut [[f32; 8]; 8]) {
 /// zeroes.
 pub fn idct_channel(
     block_levels: &[[[f32; 8]; 8]],
+    block_masks: &[u64],
     output: &mut [u8],
     blk_per_line: usize,
     output_samples_per_line: usize,
@@ -101,7 +238,6 @@ pub fn idct_channel(
 
     // Taking advantage of the separability of the 2D IDCT, and
     // decomposing it into two subsequent orthogonal series of 1D IDCTs.
-    let mut idct_intermediate: [[f32; 8]; 8] = [[0.0; 8]; 8];
     let mut idct_output: [[f32; 8]; 8] = [[0.0; 8]; 8];
 
     for y_base in 0..blk_height {
@@ -111,13 +247,15 @@ pub fn idct_channel(
                 continue;
             }
 
-            let block = &block_levels[block_id];
-
-            for row in 0..8 {
-                idct_1d(&block[row], &mut idct_intermediate[row]);
+            if block_masks[block_id] == 0 {
+                continue;
             }
 
-            idct_1d_8xt(&idct_intermediate, &mut idct_output);
+            idct_block(
+                &block_levels[block_id],
+                block_masks[block_id],
+                &mut idct_output,
+            );
 
             for (y_offset, idct_row) in idct_output.iter().enumerate() {
                 for (x_offset, idct) in idct_row.iter().enumerate() {
@@ -132,14 +270,94 @@ pub fn idct_channel(
                         continue;
                     }
 
-                    let clipped_idct =
-                        min(255, max(-256, (idct / 4.0 + idct.signum() * 0.5) as i16));
+                    let clipped_idct = round_sample(*idct);
                     let mocomp_pixel = output[x + (y * output_samples_per_line)] as u16 as i16;
 
                     output[x + (y * output_samples_per_line)] =
-                        min(255, max(0, clipped_idct + mocomp_pixel)) as u8;
+                        (clipped_idct + mocomp_pixel).clamp(0, 255) as u8;
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::cpu::idct::{
+        idct_1d_8xt_scalar, idct_1d_8xt_simd, idct_1d_scalar, idct_1d_simd, idct_block,
+    };
+
+    /// Fill a block with pseudorandom coefficients of up to `magnitude`.
+    fn random_block(seed: &mut u32, magnitude: i32) -> [[f32; 8]; 8] {
+        let mut block = [[0.0; 8]; 8];
+        for coefficient in block.iter_mut().flatten() {
+            *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *coefficient = ((*seed >> 8) as i32 % (magnitude + 1)) as f32;
+        }
+
+        block
+    }
+
+    /// One of the implementations of `idct_1d_8xt`.
+    type Idct1d8xt = fn(&[[f32; 8]; 8], u8, &mut [[f32; 8]; 8]);
+
+    /// Transform a block with the given one-dimensional IDCTs.
+    fn idct_with(
+        block: &[[f32; 8]; 8],
+        idct_1d: fn(&[f32; 8], &mut [f32; 8]),
+        idct_1d_8xt: Idct1d8xt,
+    ) -> [[f32; 8]; 8] {
+        let mut intermediate = [[0.0; 8]; 8];
+        for (row, intermediate) in block.iter().zip(intermediate.iter_mut()) {
+            idct_1d(row, intermediate);
+        }
+
+        let mut output = [[0.0; 8]; 8];
+        idct_1d_8xt(&intermediate, 0xFF, &mut output);
+
+        output
+    }
+
+    fn assert_sparse_matches_full(block: [[f32; 8]; 8], mask: u64) {
+        let mut sparse = [[0.0; 8]; 8];
+        let mut full = [[0.0; 8]; 8];
+        idct_block(&block, mask, &mut sparse);
+        idct_block(&block, u64::MAX, &mut full);
+
+        assert_eq!(full, sparse);
+    }
+
+    #[test]
+    fn sparse_idct_matches_full() {
+        let mut dc_only = [[0.0; 8]; 8];
+        dc_only[0][0] = 1024.0;
+        assert_sparse_matches_full(dc_only, 1);
+
+        for u in 0..8 {
+            for v in 0..8 {
+                let mut single = [[0.0; 8]; 8];
+                single[u][v] = -75.0;
+                assert_sparse_matches_full(single, 1 << (u * 8 + v));
+
+                let mut with_dc = single;
+                with_dc[0][0] = 600.0;
+                assert_sparse_matches_full(with_dc, 1 | 1 << (u * 8 + v));
+            }
+        }
+    }
+
+    #[test]
+    fn simd_idct_matches_scalar() {
+        let mut seed = 1;
+        for &magnitude in &[0, 1, 5, 300, 2047] {
+            for _ in 0..100 {
+                let block = random_block(&mut seed, magnitude);
+
+                assert_eq!(
+                    idct_with(&block, idct_1d_scalar, idct_1d_8xt_scalar),
+                    idct_with(&block, idct_1d_simd, idct_1d_8xt_simd)
+                );
+            }
+        }
+    }
+}
@@ -34,7 +34,7 @@ pub fn predict_candidate(
     let col_index = current_mb % mb_per_line;
     let mv1_pred = match index {
         0 | 2 if col_index == 0 => MotionVector::zero(),
-        0 | 2 => predictor_vectors[current_mb as usize - 1][index + 1],
+        0 | 2 => predictor_vectors[current_mb - 1][index + 1],
         1 | 3 => current_predictors[index - 1],
         _ => unreachable!(),
     };
@@ -132,3 +132,38 @@ pub fn mv_decode(
 
     (out_x, out_y).into()
 }
+
+/// Derive the forward and backward motion vectors of a B-block within a PB
+/// frame.
+///
+/// This is H.263 (2005/01) G.4. `mv` is the vector of the co-located P-block,
+/// `mvdb` is the delta vector sent for the B-block, and `trb` and `trd` are
+/// the temporal distances from the previous reference picture to the B- and
+/// P-pictures respectively.
+pub fn pb_vectors(
+    mv: MotionVector,
+    mvdb: MotionVector,
+    trb: i16,
+    trd: i16,
+) -> (MotionVector, MotionVector) {
+    let component = |mv: HalfPel, mvdb: HalfPel| {
+        let (mv, mvdb) = (mv.into_unit(), mvdb.into_unit());
+        let forward = trb * mv / trd + mvdb;
+        let backward = if mvdb == 0 {
+            (trb - trd) * mv / trd
+        } else {
+            forward - mv
+        };
+
+        (HalfPel::from_unit(forward), HalfPel::from_unit(backward))
+    };
+
+    let ((mv_x, mv_y), (mvdb_x, mvdb_y)) = (mv.into(), mvdb.into());
+    let (forward_x, backward_x) = component(mv_x, mvdb_x);
+    let (forward_y, backward_y) = component(mv_y, mvdb_y);
+
+    (
+        (forward_x, forward_y).into(),
+        (backward_x, backward_y).into(),
+    )
+}
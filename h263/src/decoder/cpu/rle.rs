@@ -1,7 +1,7 @@
 //! Block run decompression
 
+use crate::decoder::DecoderOption;
 use crate::types::Block;
-use std::cmp::{max, min};
 
 const DEZIGZAG_MAPPING: [(u8, u8); 64] = [
     (0, 0),
@@ -79,19 +79,30 @@ const DEZIGZAG_MAPPING: [(u8, u8); 64] = [
 ///
 /// This function assumes `levels` has already been initialized to zero. If the
 /// levels array is reused, you must reinitialize it again.
+///
+/// `masks` holds a bitmask for each block in `levels`, which is updated to
+/// mark every coefficient this function writes (see `idct_channel`). It must
+/// also start out zeroed.
+///
+/// Coefficients are reconstructed according to clause 6.2.1, unless the
+/// `UNADJUSTED_RECONSTRUCTION` decoder option is in force.
 pub fn inverse_rle(
     encoded_block: &Block,
     levels: &mut [[[f32; 8]; 8]],
+    masks: &mut [u64],
     pos: (usize, usize),
     blk_per_line: usize,
     quant: u8,
+    decoder_options: DecoderOption,
 ) {
     let mut zigzag_index = 0;
     let block_id = pos.0 / 8 + (pos.1 / 8 * blk_per_line);
     let block = &mut levels[block_id];
+    let mask = &mut masks[block_id];
 
     if let Some(dc) = encoded_block.intradc {
         block[0][0] = dc.into_level().into();
+        *mask |= 1;
         zigzag_index += 1;
     }
 
@@ -104,13 +115,114 @@ pub fn inverse_rle(
 
         let (zig_x, zig_y) = DEZIGZAG_MAPPING[zigzag_index];
         let dequantized_level = quant as i16 * ((2 * tcoef.level.abs()) + 1);
-        let parity = if quant % 2 == 1 { 0 } else { -1 };
+        let parity = if quant % 2 == 1
+            || decoder_options.contains(DecoderOption::UNADJUSTED_RECONSTRUCTION)
+        {
+            0
+        } else {
+            -1
+        };
+
+        block[zig_x as usize][zig_y as usize] = (tcoef.level.signum()
+            * (dequantized_level + parity))
+            .clamp(-2048, 2047)
+            .into();
+        *mask |= 1 << (zig_x * 8 + zig_y);
+        zigzag_index += 1;
+    }
+}
+
+/// Inverse RLE and dezigzag encoded block coefficient data, without
+/// dequantizing it.
+///
+/// The result is a row-major (x + y*8) list of coded levels. An intra DC
+/// coefficient is reported as its reconstruction level divided by 8, which is
+/// its `INTRADC` value, except that the code `0xFF` is reported as 128.
+pub fn quantized_levels(encoded_block: &Block) -> [i16; 64] {
+    let mut levels = [0; 64];
+    let mut zigzag_index = 0;
+
+    if let Some(dc) = encoded_block.intradc {
+        levels[0] = dc.into_level() >> 3;
+        zigzag_index += 1;
+    }
+
+    for tcoef in encoded_block.tcoef.iter() {
+        zigzag_index += tcoef.run as usize;
+
+        if zigzag_index >= DEZIGZAG_MAPPING.len() {
+            break;
+        }
 
-        block[zig_x as usize][zig_y as usize] = min(
-            2047,
-            max(-2048, tcoef.level.signum() * (dequantized_level + parity)),
-        )
-        .into();
+        let (zig_x, zig_y) = DEZIGZAG_MAPPING[zigzag_index];
+        levels[zig_x as usize + zig_y as usize * 8] = tcoef.level;
         zigzag_index += 1;
     }
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::cpu::rle::{inverse_rle, quantized_levels};
+    use crate::decoder::DecoderOption;
+    use crate::types::{Block, IntraDc, TCoefficient};
+
+    fn reconstruct(quant: u8, decoder_options: DecoderOption) -> [f32; 3] {
+        let block = Block {
+            intradc: None,
+            tcoef: [1, -2, 200]
+                .iter()
+                .map(|&level| TCoefficient {
+                    is_short: true,
+                    run: 0,
+                    level,
+                })
+                .collect(),
+        };
+        let mut levels = [[[0.0; 8]; 8]];
+        let mut masks = [0];
+        inverse_rle(
+            &block,
+            &mut levels,
+            &mut masks,
+            (0, 0),
+            1,
+            quant,
+            decoder_options,
+        );
+        assert_eq!(0b11 | 1 << 8, masks[0]);
+
+        [levels[0][0][0], levels[0][1][0], levels[0][0][1]]
+    }
+
+    #[test]
+    fn reconstruction_variants() {
+        let standard = DecoderOption::empty();
+        let unadjusted = DecoderOption::UNADJUSTED_RECONSTRUCTION;
+
+        // Odd quantizers reconstruct the same either way.
+        assert_eq!([15.0, -25.0, 2005.0], reconstruct(5, standard));
+        assert_eq!([15.0, -25.0, 2005.0], reconstruct(5, unadjusted));
+
+        // Even quantizers differ by one, and both saturate.
+        assert_eq!([17.0, -29.0, 2047.0], reconstruct(6, standard));
+        assert_eq!([18.0, -30.0, 2047.0], reconstruct(6, unadjusted));
+    }
+
+    #[test]
+    fn quantized_intra_dc() {
+        let dc = |code| {
+            let block = Block {
+                intradc: IntraDc::from_u8(code),
+                tcoef: Vec::new(),
+            };
+
+            quantized_levels(&block)[0]
+        };
+
+        assert_eq!(1, dc(0x01));
+        assert_eq!(254, dc(0xFE));
+        assert_eq!(128, dc(0xFF));
+    }
 }
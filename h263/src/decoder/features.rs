@@ -0,0 +1,92 @@
+//! Decoder capability reporting
+
+bitflags! {
+    /// Bitstream features that a decoder may or may not implement.
+    ///
+    /// Most of these correspond to annexes of ITU-T Recommendation H.263
+    /// (01/2005), which decoders are not required to support in full.
+    pub struct FeatureSet : u32 {
+        /// The baseline H.263 bitstream syntax without any optional modes.
+        const BASELINE = 0b1;
+
+        /// The Sorenson Spark bitstream variant.
+        const SORENSON_SPARK = 0b10;
+
+        /// Annex D, Unrestricted Motion Vector mode.
+        const UNRESTRICTED_MOTION_VECTORS = 0b100;
+
+        /// Annex E, Syntax-based Arithmetic Coding mode.
+        const SYNTAX_BASED_ARITHMETIC_CODING = 0b1000;
+
+        /// Annex F, Advanced Prediction mode.
+        const ADVANCED_PREDICTION = 0b10000;
+
+        /// Annex G, PB-frames mode.
+        const PB_FRAMES = 0b100000;
+
+        /// Annex I, Advanced INTRA Coding mode.
+        const ADVANCED_INTRA_CODING = 0b1000000;
+
+        /// Annex J, Deblocking Filter mode.
+        const DEBLOCKING_FILTER = 0b10000000;
+
+        /// Annex K, Slice Structured mode.
+        const SLICE_STRUCTURED = 0b100000000;
+
+        /// Annex N, Reference Picture Selection mode.
+        const REFERENCE_PICTURE_SELECTION = 0b1000000000;
+
+        /// Annex O, Temporal, SNR, and Spatial Scalability mode.
+        const SCALABILITY = 0b10000000000;
+
+        /// Annex P, Reference Picture Resampling mode.
+        const REFERENCE_PICTURE_RESAMPLING = 0b100000000000;
+
+        /// Annex Q, Reduced-Resolution Update mode.
+        const REDUCED_RESOLUTION_UPDATE = 0b1000000000000;
+
+        /// Annex R, Independent Segment Decoding mode.
+        const INDEPENDENT_SEGMENT_DECODING = 0b10000000000000;
+
+        /// Annex S, Alternative INTER VLC mode.
+        const ALTERNATIVE_INTER_VLC = 0b100000000000000;
+
+        /// Annex T, Modified Quantization mode.
+        const MODIFIED_QUANTIZATION = 0b1000000000000000;
+
+        /// Annex M, Improved PB-frames mode.
+        const IMPROVED_PB_FRAMES = 0b10000000000000000;
+    }
+}
+
+/// Determine which bitstream features this decoder implements.
+///
+/// Bitstreams which make use of features outside of this set will likely
+/// fail to decode, or decode incorrectly.
+pub fn supported_features() -> FeatureSet {
+    FeatureSet::BASELINE
+        | FeatureSet::SORENSON_SPARK
+        | FeatureSet::UNRESTRICTED_MOTION_VECTORS
+        | FeatureSet::ADVANCED_PREDICTION
+        | FeatureSet::PB_FRAMES
+        | FeatureSet::DEBLOCKING_FILTER
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::features::{supported_features, FeatureSet};
+
+    #[test]
+    fn baseline_is_supported() {
+        let features = supported_features();
+
+        assert!(features.contains(FeatureSet::BASELINE));
+        assert!(features.contains(FeatureSet::SORENSON_SPARK));
+        assert!(features.contains(FeatureSet::UNRESTRICTED_MOTION_VECTORS));
+        assert!(features.contains(FeatureSet::ADVANCED_PREDICTION));
+        assert!(features.contains(FeatureSet::PB_FRAMES));
+        assert!(!features.contains(FeatureSet::IMPROVED_PB_FRAMES));
+        assert!(!features.contains(FeatureSet::SYNTAX_BASED_ARITHMETIC_CODING));
+        assert!(features.contains(FeatureSet::DEBLOCKING_FILTER));
+    }
+}
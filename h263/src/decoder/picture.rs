@@ -1,6 +1,16 @@
 //! Decoded picture type
 
-use crate::types::{Picture, SourceFormat};
+use crate::decoder::pool::PicturePool;
+use crate::types::{
+    CustomPictureClock, CustomPictureFormat, Picture, PictureTypeCode, PixelAspectRatio,
+    SourceFormat,
+};
+
+/// The Annex L function type (`FTYPE`) of an Annex W picture message.
+const FTYPE_PICTURE_MESSAGE: u8 = 14;
+
+/// The Annex W message type (`MTYPE`) of arbitrary binary data.
+const MTYPE_ARBITRARY_BINARY_DATA: u8 = 0;
 
 /// A decoded picture.
 pub struct DecodedPicture {
@@ -23,6 +33,172 @@ pub struct DecodedPicture {
     ///
     /// May be rounded up to the nearest pixel.
     chroma_samples_per_row: usize,
+
+    /// Arbitrary binary data carried in the picture's supplemental
+    /// enhancement information.
+    embedded_still: Option<Vec<u8>>,
+
+    /// The pool to return this picture's planes to when it is dropped.
+    pool: Option<PicturePool>,
+}
+
+/// A rectangular region of a picture, in luma samples.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Rect {
+    /// The column of the leftmost samples in the region.
+    pub x: usize,
+
+    /// The row of the topmost samples in the region.
+    pub y: usize,
+
+    /// The number of columns in the region.
+    pub width: usize,
+
+    /// The number of rows in the region.
+    pub height: usize,
+}
+
+/// A summary of a decoded picture, without its sample data.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PictureMeta {
+    /// The temporal reference of the picture.
+    ///
+    /// This counts picture clock ticks, modulo 256, or modulo 1024 when a
+    /// custom picture clock is in force. Callers computing presentation
+    /// timestamps should accumulate the wrapping difference between
+    /// successive temporal references (e.g. `tr.wrapping_sub(prev) % 256`)
+    /// rather than use the value directly. B-pictures may be displayed before
+    /// the picture preceding them in the bitstream, and thus step backwards.
+    pub temporal_reference: u16,
+
+    /// The custom picture clock frequency in force, if any.
+    ///
+    /// If `None`, then the temporal reference counts ticks of the standard
+    /// 30000/1001hz picture clock.
+    pub custom_pcf: Option<CustomPictureClock>,
+
+    /// The type of the picture.
+    pub picture_type: PictureTypeCode,
+
+    /// The width of the picture, in luma samples.
+    pub width: u16,
+
+    /// The height of the picture, in luma samples.
+    pub height: u16,
+}
+
+/// The matrix used to derive the color difference signals of a picture from
+/// its RGB primaries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMatrix {
+    /// ITU-R BT.601, as used by standard-definition video.
+    Bt601,
+
+    /// ITU-R BT.709, as used by high-definition video.
+    Bt709,
+}
+
+/// The range of sample values that represent black through white (or full
+/// color difference) in a picture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorRange {
+    /// Studio ("TV") range, where luma runs from 16 to 235, and chroma from
+    /// 16 to 240.
+    Studio,
+
+    /// Full ("PC" or JPEG) range, where every sample runs from 0 to 255.
+    Full,
+}
+
+/// How the samples of a picture are to be converted to RGB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorConfig {
+    /// The color matrix of the picture.
+    pub matrix: ColorMatrix,
+
+    /// The sample range of the picture.
+    pub range: ColorRange,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            matrix: ColorMatrix::Bt601,
+            range: ColorRange::Studio,
+        }
+    }
+}
+
+/// The outcome of decoding a picture while an output interval is in force.
+///
+/// See `H263State::set_output_interval`.
+pub enum DecodedFrame<'a> {
+    /// The picture was decoded and is due for output.
+    Picture(&'a DecodedPicture),
+
+    /// The picture was decoded to keep the reference pictures up to date, but
+    /// is not due for output.
+    Skipped {
+        /// The temporal reference of the skipped picture.
+        temporal_reference: u16,
+    },
+}
+
+/// Continue a CRC-32 (as used by zlib and PNG) over more bytes.
+///
+/// `crc` is the checksum of the bytes so far, starting at 0.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+/// Reassemble the first arbitrary binary data picture message from a
+/// picture's `PSUPP` bytes.
+///
+/// `PSUPP` is a sequence of Annex L functions, each an `FTYPE` and `DSIZE`
+/// nibble followed by `DSIZE` bytes. Annex W picture messages start with a
+/// `CONT`, `EBIT` and `MTYPE` byte; `CONT` indicates that the message
+/// continues in the next picture message.
+fn decode_embedded_still(mut psupp: &[u8]) -> Option<Vec<u8>> {
+    let mut still: Option<Vec<u8>> = None;
+
+    while let [function_header, rest @ ..] = psupp {
+        let ftype = function_header >> 4;
+        let dsize = (function_header & 0xF) as usize;
+        let data = rest.get(..dsize)?;
+        psupp = &rest[dsize..];
+
+        if ftype != FTYPE_PICTURE_MESSAGE {
+            continue;
+        }
+
+        if let [message_header, payload @ ..] = data {
+            let is_continued = message_header & 0x80 != 0;
+            let mtype = message_header & 0xF;
+
+            if mtype != MTYPE_ARBITRARY_BINARY_DATA {
+                continue;
+            }
+
+            still
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(payload);
+
+            if !is_continued {
+                return still;
+            }
+        }
+    }
+
+    still
 }
 
 impl DecodedPicture {
@@ -35,18 +211,33 @@ impl DecodedPicture {
     ///
     /// Invalid source formats will fail to generate a decoded picture.
     pub fn new(picture_header: Picture, format: SourceFormat) -> Option<Self> {
+        Self::new_in(picture_header, format, None)
+    }
+
+    /// Construct a new `DecodedPicture` whose buffers are drawn from, and
+    /// later returned to, a given `PicturePool`.
+    ///
+    /// If `pool` is `None`, this behaves exactly like `new`.
+    pub fn new_in(
+        picture_header: Picture,
+        format: SourceFormat,
+        pool: Option<&PicturePool>,
+    ) -> Option<Self> {
         let (w, h) = format.into_width_and_height()?;
+        let allocate = |len| match pool {
+            Some(pool) => pool.take(len),
+            None => vec![0; len],
+        };
+
         let luma_samples = w as usize * h as usize;
-        let mut luma = Vec::new();
-        luma.resize(luma_samples, 0);
+        let luma = allocate(luma_samples);
 
         let chroma_w = (w as f32 / 2.0).ceil() as usize;
         let chroma_h = (h as f32 / 2.0).ceil() as usize;
         let chroma_samples = chroma_w * chroma_h;
-        let mut chroma_b = Vec::new();
-        chroma_b.resize(chroma_samples, 0);
-        let mut chroma_r = Vec::new();
-        chroma_r.resize(chroma_samples, 0);
+        let chroma_b = allocate(chroma_samples);
+        let chroma_r = allocate(chroma_samples);
+        let embedded_still = decode_embedded_still(&picture_header.extra);
 
         Some(Self {
             picture_header,
@@ -55,6 +246,8 @@ impl DecodedPicture {
             chroma_b,
             chroma_r,
             chroma_samples_per_row: chroma_w,
+            embedded_still,
+            pool: pool.cloned(),
         })
     }
 
@@ -68,6 +261,55 @@ impl DecodedPicture {
         self.format
     }
 
+    /// Get the shape of this picture's pixels, as a width to height ratio.
+    ///
+    /// Custom picture formats give this with their `PAR` code, or explicitly
+    /// if it is extended. The standard picture formats (sub-QCIF through
+    /// 16CIF) always have 12:11 pixels. Formats that don't indicate a known
+    /// pixel aspect ratio, such as those with reserved `PAR` codes, have
+    /// square pixels.
+    pub fn pixel_aspect_ratio(&self) -> (u16, u16) {
+        let par = match self.format {
+            SourceFormat::Extended(cpf) => cpf.pixel_aspect_ratio,
+            SourceFormat::Reserved => PixelAspectRatio::Square,
+            _ => PixelAspectRatio::Par12_11,
+        };
+
+        match par {
+            PixelAspectRatio::Square | PixelAspectRatio::Reserved(_) => (1, 1),
+            PixelAspectRatio::Par12_11 => (12, 11),
+            PixelAspectRatio::Par10_11 => (10, 11),
+            PixelAspectRatio::Par16_11 => (16, 11),
+            PixelAspectRatio::Par40_33 => (40, 33),
+            PixelAspectRatio::Extended {
+                par_width,
+                par_height,
+            } => (par_width as u16, par_height as u16),
+        }
+    }
+
+    /// Get the color representation of this picture's samples.
+    ///
+    /// H.263 bitstreams, Sorenson Spark included, cannot signal a color
+    /// representation, so this is always the default `ColorConfig`: BT.601
+    /// studio range, as with the ITU-R BT.601 sources H.263 was designed for.
+    pub fn color_config(&self) -> ColorConfig {
+        ColorConfig::default()
+    }
+
+    /// Summarize this picture.
+    pub fn meta(&self) -> PictureMeta {
+        let (width, height) = self.format.into_width_and_height().unwrap_or((0, 0));
+
+        PictureMeta {
+            temporal_reference: self.picture_header.temporal_reference,
+            custom_pcf: self.picture_header.custom_pcf,
+            picture_type: self.picture_header.picture_type,
+            width,
+            height,
+        }
+    }
+
     /// Get the luma data for this picture.
     ///
     /// Raw luma data is stored in row-major (x + y*samples_per_row) order with
@@ -141,4 +383,542 @@ impl DecodedPicture {
     pub fn as_yuv(&self) -> (&[u8], &[u8], &[u8]) {
         (&self.luma, &self.chroma_b, &self.chroma_r)
     }
+
+    /// Copy this picture, drawing the planes of the copy from a given pool.
+    pub(crate) fn copy_in(&self, pool: Option<&PicturePool>) -> Self {
+        let mut copy = Self::new_in(self.picture_header.clone(), self.format, pool)
+            .expect("a decoded picture has a valid format");
+        copy.luma.copy_from_slice(&self.luma);
+        copy.chroma_b.copy_from_slice(&self.chroma_b);
+        copy.chroma_r.copy_from_slice(&self.chroma_r);
+
+        copy
+    }
+
+    /// Mutably borrow the YUV data in this picture.
+    pub fn as_yuv_mut(&mut self) -> (&mut [u8], &mut [u8], &mut [u8]) {
+        (&mut self.luma, &mut self.chroma_b, &mut self.chroma_r)
+    }
+
+    /// Get the raw bytes of a still image embedded in this picture.
+    ///
+    /// H.263 has no dedicated syntax for still images; they are instead
+    /// carried as an Annex W arbitrary binary data picture message in the
+    /// picture's supplemental enhancement information. The first such
+    /// message is reassembled from its continuation segments and yielded
+    /// without further interpretation. If `None`, then the picture carries
+    /// no such message.
+    pub fn embedded_still(&self) -> Option<&[u8]> {
+        self.embedded_still.as_deref()
+    }
+
+    /// Compute a fingerprint of the samples of this picture.
+    ///
+    /// This is the CRC-32 (as used by zlib and PNG) of the luma, chroma-B and
+    /// chroma-R planes, concatenated in that order. Pictures with identical
+    /// samples always have the same checksum, so expected output can be
+    /// stored as checksums rather than whole pictures.
+    pub fn checksum(&self) -> u32 {
+        [&self.luma, &self.chroma_b, &self.chroma_r]
+            .iter()
+            .fold(0, |crc, plane| crc32(crc, plane))
+    }
+
+    /// Copy a region of this picture into a new picture.
+    ///
+    /// The new picture has a custom source format the size of `rect`, with
+    /// this picture's pixel aspect ratio, and keeps the 4:2:0 layout of its
+    /// planes. Each of its chroma samples is taken from the chroma sample of
+    /// this picture that covers the top-left luma sample of its 2x2 group, so
+    /// regions at odd offsets still have correctly sized and aligned chroma.
+    ///
+    /// Panics if `rect` is empty or does not lie within this picture.
+    pub fn crop(&self, rect: Rect) -> DecodedPicture {
+        let (width, height) = self.format.into_width_and_height().unwrap();
+        assert!(
+            rect.width > 0
+                && rect.height > 0
+                && rect.x + rect.width <= width as usize
+                && rect.y + rect.height <= height as usize,
+            "crop region {:?} does not lie within the picture",
+            rect
+        );
+
+        let pixel_aspect_ratio = match self.format {
+            SourceFormat::Extended(cpf) => cpf.pixel_aspect_ratio,
+            _ => PixelAspectRatio::Par12_11,
+        };
+        let format = SourceFormat::Extended(CustomPictureFormat {
+            pixel_aspect_ratio,
+            picture_width_indication: rect.width as u16,
+            picture_height_indication: rect.height as u16,
+        });
+
+        let mut picture_header = self.picture_header.clone();
+        picture_header.format = Some(format);
+
+        let mut cropped = DecodedPicture::new(picture_header, format).unwrap();
+
+        let luma_samples_per_row = self.luma_samples_per_row();
+        for (row, cropped_row) in cropped.luma.chunks_mut(rect.width).enumerate() {
+            let start = rect.x + (rect.y + row) * luma_samples_per_row;
+            cropped_row.copy_from_slice(&self.luma[start..start + rect.width]);
+        }
+
+        let chroma_samples_per_row = cropped.chroma_samples_per_row;
+        for (plane, cropped_plane) in [
+            (&self.chroma_b, &mut cropped.chroma_b),
+            (&self.chroma_r, &mut cropped.chroma_r),
+        ] {
+            for (row, cropped_row) in cropped_plane.chunks_mut(chroma_samples_per_row).enumerate() {
+                let source_row = (rect.y + row * 2) / 2 * self.chroma_samples_per_row;
+                for (column, sample) in cropped_row.iter_mut().enumerate() {
+                    *sample = plane[source_row + (rect.x + column * 2) / 2];
+                }
+            }
+        }
+
+        cropped
+    }
+
+    /// Check that this picture's planes are consistent with its format.
+    ///
+    /// The luma plane must hold exactly as many samples as the picture's
+    /// dimensions call for, and both chroma planes must be half its width and
+    /// height, rounded up. Any 8-bit value is a valid sample, so the samples
+    /// themselves are not inspected. Yields a description of the first
+    /// inconsistency found, if any.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let (w, h) = self
+            .format
+            .into_width_and_height()
+            .ok_or("picture format has no dimensions")?;
+        let chroma_w = (w as usize).div_ceil(2);
+        let chroma_h = (h as usize).div_ceil(2);
+
+        if self.luma.len() != w as usize * h as usize {
+            return Err("luma plane size does not match picture dimensions");
+        }
+
+        if self.chroma_samples_per_row != chroma_w {
+            return Err("chroma row length is not half the luma row length");
+        }
+
+        if self.chroma_b.len() != chroma_w * chroma_h {
+            return Err("chroma-blue plane size is not half the luma size");
+        }
+
+        if self.chroma_r.len() != chroma_w * chroma_h {
+            return Err("chroma-red plane size is not half the luma size");
+        }
+
+        Ok(())
+    }
+
+    /// Visualize the luma changes between a previous picture and this one.
+    ///
+    /// This yields an RGBA 8888 image with one pixel per luma sample. Pixels
+    /// whose luma is unchanged are mid-gray; changed pixels are tinted red,
+    /// more strongly the larger the change.
+    ///
+    /// Panics if the two pictures do not have the same dimensions.
+    pub fn diff_rgba(&self, prev: &DecodedPicture) -> Vec<u8> {
+        assert_eq!(
+            self.format().into_width_and_height(),
+            prev.format().into_width_and_height(),
+            "cannot diff pictures of different dimensions"
+        );
+
+        let mut rgba = Vec::with_capacity(self.luma.len() * 4);
+
+        for (&new, &old) in self.luma.iter().zip(prev.luma.iter()) {
+            let magnitude = (new as i16 - old as i16).unsigned_abs();
+            let tint = (magnitude * 127).div_ceil(255) as u8;
+
+            rgba.extend_from_slice(&[128 + tint, 128 - tint, 128 - tint, 255]);
+        }
+
+        rgba
+    }
+
+    /// Count how many luma samples of this picture take each value.
+    ///
+    /// The counts are accumulated into four interleaved histograms, which
+    /// are summed at the end, so that runs of identical samples do not stall
+    /// on a single counter.
+    pub fn luma_histogram(&self) -> [u32; 256] {
+        let mut partial = [[0u32; 256]; 4];
+        let mut chunks = self.luma.chunks_exact(4);
+
+        for chunk in &mut chunks {
+            partial[0][chunk[0] as usize] += 1;
+            partial[1][chunk[1] as usize] += 1;
+            partial[2][chunk[2] as usize] += 1;
+            partial[3][chunk[3] as usize] += 1;
+        }
+
+        for &sample in chunks.remainder() {
+            partial[0][sample as usize] += 1;
+        }
+
+        let mut histogram = [0; 256];
+        for (value, count) in histogram.iter_mut().enumerate() {
+            *count = partial.iter().map(|p| p[value]).sum();
+        }
+
+        histogram
+    }
+
+    /// Determine which macroblocks differ between a previous picture and
+    /// this one.
+    ///
+    /// This yields one flag per 16x16 macroblock, in raster order, which is
+    /// set if any luma or chroma sample within that macroblock changed.
+    /// Macroblocks along the right and bottom edges may be partial. Each
+    /// macroblock row is compared as a whole slice, so that the comparison
+    /// can use the platform's vectorized `memcmp`.
+    ///
+    /// Panics if the two pictures do not have the same dimensions.
+    pub fn changed_blocks(&self, prev: &DecodedPicture) -> Vec<bool> {
+        assert_eq!(
+            self.format().into_width_and_height(),
+            prev.format().into_width_and_height(),
+            "cannot compare pictures of different dimensions"
+        );
+
+        let luma_row = self.luma_samples_per_row();
+        let chroma_row = self.chroma_samples_per_row;
+        let mb_per_line = luma_row.div_ceil(16);
+        let mb_lines = (self.luma.len() / luma_row.max(1)).div_ceil(16);
+        let mut changed = vec![false; mb_per_line * mb_lines];
+
+        let mut compare = |new: &[u8], old: &[u8], row_len: usize, mb_size: usize| {
+            for (y, (new, old)) in new.chunks(row_len).zip(old.chunks(row_len)).enumerate() {
+                let mb_line = &mut changed[(y / mb_size) * mb_per_line..];
+
+                for (mb_x, (new, old)) in new.chunks(mb_size).zip(old.chunks(mb_size)).enumerate() {
+                    mb_line[mb_x] |= new != old;
+                }
+            }
+        };
+
+        compare(&self.luma, &prev.luma, luma_row, 16);
+        compare(&self.chroma_b, &prev.chroma_b, chroma_row, 8);
+        compare(&self.chroma_r, &prev.chroma_r, chroma_row, 8);
+
+        changed
+    }
+}
+
+impl Drop for DecodedPicture {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.recycle(std::mem::take(&mut self.luma));
+            pool.recycle(std::mem::take(&mut self.chroma_b));
+            pool.recycle(std::mem::take(&mut self.chroma_r));
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use crate::decoder::picture::crc32;
+    use crate::decoder::state::tests::{write_flat_macroblocks, write_sorenson_header, BitWriter};
+    use crate::decoder::{ColorConfig, ColorMatrix, ColorRange, Rect};
+    use crate::decoder::{DecodedPicture, DecoderOption, H263State};
+    use crate::parser::H263Reader;
+    use crate::types::{
+        CustomPictureFormat, Picture, PictureOption, PictureTypeCode, PixelAspectRatio,
+        SourceFormat,
+    };
+
+    /// Construct a black I-frame of the given format.
+    pub fn blank_picture(format: SourceFormat) -> DecodedPicture {
+        let header = Picture {
+            version: None,
+            temporal_reference: 0,
+            format: Some(format),
+            options: PictureOption::empty(),
+            has_plusptype: false,
+            has_opptype: false,
+            custom_pcf: None,
+            picture_type: PictureTypeCode::IFrame,
+            motion_vector_range: None,
+            slice_submode: None,
+            scalability_layer: None,
+            reference_picture_selection_mode: None,
+            prediction_reference: None,
+            backchannel_message: None,
+            reference_picture_resampling: None,
+            quantizer: 1,
+            multiplex_bitstream: None,
+            pb_reference: None,
+            pb_quantizer: None,
+            extra: Vec::new(),
+        };
+
+        DecodedPicture::new(header, format).unwrap()
+    }
+
+    #[test]
+    fn crop() {
+        let format = SourceFormat::Extended(CustomPictureFormat {
+            pixel_aspect_ratio: PixelAspectRatio::Square,
+            picture_width_indication: 16,
+            picture_height_indication: 16,
+        });
+        let mut picture = blank_picture(format);
+        for (i, luma) in picture.as_luma_mut().iter_mut().enumerate() {
+            *luma = i as u8;
+        }
+        for (i, chroma) in picture.as_chroma_b_mut().iter_mut().enumerate() {
+            *chroma = i as u8;
+        }
+        for (i, chroma) in picture.as_chroma_r_mut().iter_mut().enumerate() {
+            *chroma = 100 + i as u8;
+        }
+
+        let cropped = picture.crop(Rect {
+            x: 4,
+            y: 4,
+            width: 8,
+            height: 8,
+        });
+        assert_eq!(Some((8, 8)), cropped.format().into_width_and_height());
+        assert_eq!(8, cropped.luma_samples_per_row());
+        assert_eq!(4, cropped.chroma_samples_per_row());
+
+        let expected_luma: Vec<u8> = (4..12)
+            .flat_map(|y| (4..12).map(move |x| (x + y * 16) as u8))
+            .collect();
+        assert_eq!(&expected_luma[..], cropped.as_luma());
+
+        let expected_chroma: Vec<u8> = (2..6)
+            .flat_map(|y| (2..6).map(move |x| (x + y * 8) as u8))
+            .collect();
+        assert_eq!(&expected_chroma[..], cropped.as_chroma_b());
+        assert_eq!(100 + expected_chroma[5], cropped.as_chroma_r()[5]);
+
+        // An odd offset and size still yields one chroma sample per 2x2 group.
+        let cropped = picture.crop(Rect {
+            x: 3,
+            y: 1,
+            width: 5,
+            height: 3,
+        });
+        assert_eq!(3, cropped.chroma_samples_per_row());
+        assert_eq!(&[1, 2, 3, 9, 10, 11][..], cropped.as_chroma_b());
+    }
+
+    #[test]
+    fn diff_rgba() {
+        let mut picture = blank_picture(SourceFormat::SubQcif);
+        for (i, luma) in picture.as_luma_mut().iter_mut().enumerate() {
+            *luma = ((i % 128) * 2) as u8;
+        }
+
+        let gray = picture.diff_rgba(&picture);
+        assert_eq!(128 * 96 * 4, gray.len());
+        assert!(gray.chunks(4).all(|p| p == [128, 128, 128, 255]));
+
+        // Move a white square four pixels to the right.
+        let mut before = blank_picture(SourceFormat::SubQcif);
+        let mut after = blank_picture(SourceFormat::SubQcif);
+        for y in 16..32 {
+            before.as_luma_mut()[y * 128 + 16..y * 128 + 32].fill(255);
+            after.as_luma_mut()[y * 128 + 20..y * 128 + 36].fill(255);
+        }
+
+        let diff = after.diff_rgba(&before);
+        for (i, pixel) in diff.chunks(4).enumerate() {
+            let (x, y) = (i % 128, i / 128);
+            let moved = (16..32).contains(&y) && ((16..20).contains(&x) || (32..36).contains(&x));
+
+            if moved {
+                assert_eq!([255, 1, 1, 255], pixel);
+            } else {
+                assert_eq!([128, 128, 128, 255], pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn embedded_still() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        write_flat_macroblocks(&mut w, 0xFF);
+        write_sorenson_header(
+            &mut w,
+            1,
+            0,
+            &[
+                0x10, // do nothing
+                0xE4, 0x80, 0xFF, 0xD8, 0xFF, // first message segment
+                0xE2, 0x01, 0x55, // arbitrary text, ignored
+                0xE3, 0x00, 0xFF, 0xD9, // last message segment
+            ],
+        );
+        write_flat_macroblocks(&mut w, 0xFF);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let normal = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(None, normal.embedded_still());
+
+        let still = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            Some(&[0xFF, 0xD8, 0xFF, 0xFF, 0xD9][..]),
+            still.embedded_still()
+        );
+    }
+
+    #[test]
+    fn color_config_defaults_to_bt601_studio_range() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        write_flat_macroblocks(&mut w, 0x40);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            ColorConfig {
+                matrix: ColorMatrix::Bt601,
+                range: ColorRange::Studio,
+            },
+            picture.color_config()
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        write_flat_macroblocks(&mut w, 0x40);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(Ok(()), picture.validate());
+
+        let mut picture = blank_picture(SourceFormat::SubQcif);
+        assert_eq!(Ok(()), picture.validate());
+
+        picture.chroma_r.pop();
+        assert_eq!(
+            Err("chroma-red plane size is not half the luma size"),
+            picture.validate()
+        );
+
+        picture.luma.push(0);
+        assert_eq!(
+            Err("luma plane size does not match picture dimensions"),
+            picture.validate()
+        );
+    }
+
+    #[test]
+    fn pixel_aspect_ratio() {
+        let custom = |pixel_aspect_ratio| {
+            blank_picture(SourceFormat::Extended(CustomPictureFormat {
+                pixel_aspect_ratio,
+                picture_width_indication: 16,
+                picture_height_indication: 16,
+            }))
+        };
+
+        assert_eq!(
+            (12, 11),
+            custom(PixelAspectRatio::Par12_11).pixel_aspect_ratio()
+        );
+        assert_eq!(
+            (1, 1),
+            custom(PixelAspectRatio::Square).pixel_aspect_ratio()
+        );
+        assert_eq!(
+            (1, 1),
+            custom(PixelAspectRatio::Reserved(7)).pixel_aspect_ratio()
+        );
+        assert_eq!(
+            (8, 9),
+            custom(PixelAspectRatio::Extended {
+                par_width: 8,
+                par_height: 9
+            })
+            .pixel_aspect_ratio()
+        );
+        assert_eq!(
+            (12, 11),
+            blank_picture(SourceFormat::QuarterCif).pixel_aspect_ratio()
+        );
+    }
+
+    #[test]
+    fn changed_blocks() {
+        let format = SourceFormat::Extended(CustomPictureFormat {
+            pixel_aspect_ratio: PixelAspectRatio::Square,
+            picture_width_indication: 40,
+            picture_height_indication: 32,
+        });
+        let prev = blank_picture(format);
+
+        let mut luma = blank_picture(format);
+        luma.as_luma_mut()[17 * 40 + 20] = 1;
+        assert_eq!(
+            vec![false, false, false, false, true, false],
+            luma.changed_blocks(&prev)
+        );
+
+        // Chroma-only changes count too, including in partial macroblocks.
+        let mut chroma = blank_picture(format);
+        chroma.as_chroma_r_mut()[19] = 1;
+        assert_eq!(
+            vec![false, false, true, false, false, false],
+            chroma.changed_blocks(&prev)
+        );
+
+        assert!(prev.changed_blocks(&prev).iter().all(|&c| !c));
+    }
+
+    #[test]
+    fn luma_histogram() {
+        let format = SourceFormat::Extended(CustomPictureFormat {
+            pixel_aspect_ratio: PixelAspectRatio::Square,
+            picture_width_indication: 18,
+            picture_height_indication: 7,
+        });
+        let mut picture = blank_picture(format);
+        for luma in picture.as_luma_mut() {
+            *luma = 0x80;
+        }
+
+        let histogram = picture.luma_histogram();
+        assert_eq!(18 * 7, histogram[0x80]);
+        assert_eq!(18 * 7, histogram.iter().sum::<u32>());
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(0, b"123456789"));
+        assert_eq!(crc32(0, b"123456789"), crc32(crc32(0, b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn checksum_of_flat_iframe() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        write_flat_macroblocks(&mut w, 0x40);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.decode_next_picture(&mut reader).unwrap();
+        let picture = decoder.get_last_picture().unwrap();
+
+        // The CRC-32 of 18432 bytes of 64, as computed by zlib.
+        assert_eq!(0xF985_A616, picture.checksum());
+    }
 }
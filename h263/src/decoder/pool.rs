@@ -0,0 +1,124 @@
+//! Recycling of decoded picture buffers
+
+use std::sync::{Arc, Mutex};
+
+/// The shared state of a `PicturePool`.
+#[derive(Default)]
+struct PoolState {
+    /// Buffers returned by dropped pictures, ready for reuse.
+    buffers: Vec<Vec<u8>>,
+
+    /// How many buffers the pool has had to allocate.
+    allocations: usize,
+}
+
+/// A pool of plane buffers shared between decoded pictures.
+///
+/// When a pool is given to an `H263State`, each decoded picture draws its
+/// luma and chroma planes from the pool, and returns them to it when dropped.
+/// This avoids reallocating planes for every picture during sustained
+/// playback.
+///
+/// Cloning a pool yields another handle to the same set of buffers.
+#[derive(Clone, Default)]
+pub struct PicturePool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl PicturePool {
+    /// Construct a new, empty `PicturePool`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get how many buffers this pool has had to allocate, rather than reuse,
+    /// so far.
+    pub fn allocations(&self) -> usize {
+        self.state.lock().unwrap().allocations
+    }
+
+    /// Get how many buffers are currently waiting in this pool to be reused.
+    pub fn available(&self) -> usize {
+        self.state.lock().unwrap().buffers.len()
+    }
+
+    /// Take a zero-filled buffer of `len` bytes from the pool.
+    ///
+    /// The smallest available buffer that can hold `len` bytes without
+    /// growing is reused; if there is none, a new one is allocated.
+    pub(crate) fn take(&self, len: usize) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        let best_fit = state
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.capacity() >= len)
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+
+        match best_fit {
+            Some(index) => {
+                let mut buffer = state.buffers.swap_remove(index);
+                buffer.clear();
+                buffer.resize(len, 0);
+
+                buffer
+            }
+            None => {
+                state.allocations += 1;
+
+                vec![0; len]
+            }
+        }
+    }
+
+    /// Return a buffer to the pool for later reuse.
+    pub(crate) fn recycle(&self, buffer: Vec<u8>) {
+        if buffer.capacity() > 0 {
+            self.state.lock().unwrap().buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state::tests::{write_flat_iframe, write_uncoded_pframe, BitWriter};
+    use crate::decoder::{DecoderOption, H263State, PicturePool};
+    use crate::parser::H263Reader;
+
+    #[test]
+    fn bounded_allocations() {
+        let mut w = BitWriter::default();
+        for tr in 0..100u8 {
+            if tr % 10 == 0 {
+                write_flat_iframe(&mut w, tr, 0x40);
+            } else {
+                write_uncoded_pframe(&mut w, tr);
+            }
+        }
+        let data = w.into_bytes();
+
+        let pool = PicturePool::new();
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_picture_pool(Some(pool.clone()));
+
+        let mut decoded = 0;
+        while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+            assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+            decoded += 1;
+        }
+
+        assert_eq!(100, decoded);
+
+        // Three planes for each of the new picture and its reference.
+        assert!(
+            pool.allocations() <= 6,
+            "{} allocations",
+            pool.allocations()
+        );
+
+        drop(decoder);
+        assert_eq!(pool.allocations(), pool.available());
+    }
+}
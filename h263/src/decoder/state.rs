@@ -1,17 +1,35 @@
 //! H.263 decoder core
 
-use crate::decoder::cpu::{gather, idct_channel, inverse_rle, mv_decode, predict_candidate};
-use crate::decoder::picture::DecodedPicture;
-use crate::decoder::types::DecoderOption;
-use crate::error::{Error, Result};
-use crate::parser::{decode_block, decode_gob, decode_macroblock, decode_picture, H263Reader};
+use crate::decoder::cpu::{
+    deblock_frame, gather, gather_bidirectional, idct_channel, inverse_rle, mv_decode, pb_vectors,
+    predict_candidate, quantized_levels,
+};
+use crate::decoder::picture::{DecodedFrame, DecodedPicture, PictureMeta};
+use crate::decoder::pool::PicturePool;
+use crate::decoder::types::{DecoderOption, DecoderOptionBuilder, QuantizedBlock};
+use crate::error::{Error, Result, Warning};
+use crate::parser::{
+    decode_end_of_sequence, decode_gob, decode_macroblock, decode_macroblock_blocks,
+    decode_picture, macroblock_rows_per_gob, BitstreamSource, H263Reader,
+};
 use crate::types::{
     GroupOfBlocks, Macroblock, MacroblockType, MotionVector, Picture, PictureOption,
     PictureTypeCode, MPPTYPE_OPTIONS, OPPTYPE_OPTIONS,
 };
-use std::cmp::{max, min};
-use std::collections::HashMap;
-use std::io::Read;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// A callback invoked as each group of blocks in a picture is reconstructed.
+///
+/// It is given the index of the GOB within the picture, the range of luma
+/// rows it covers, and the picture being decoded.
+pub(super) type GobCallback<'a> = &'a mut dyn FnMut(usize, Range<usize>, &DecodedPicture);
+
+/// A callback invoked with the quantized coefficients of each coded block.
+type CoefficientHook = Box<dyn FnMut(&QuantizedBlock) + Send>;
+
+/// The DC level that reconstructs to a flat, mid-gray intra block.
+const GRAY_DC: f32 = 1024.0;
 
 /// All state necessary to decode a successive series of H.263 pictures.
 pub struct H263State {
@@ -36,6 +54,122 @@ pub struct H263State {
 
     /// All previously-encoded reference pictures.
     reference_states: HashMap<u16, DecodedPicture>,
+
+    /// The temporal references of retained non-disposable pictures, least
+    /// recently used first.
+    reference_history: Vec<u16>,
+
+    /// The temporal references of non-disposable pictures that were evicted
+    /// to keep within `max_references`.
+    evicted_references: HashSet<u16>,
+
+    /// How many non-disposable pictures to retain as potential references.
+    max_references: usize,
+
+    /// The error that stopped decoding, if `STOP_ON_ERROR` is in force.
+    last_error: Option<Error>,
+
+    /// Non-fatal problems encountered while decoding the last picture.
+    last_warnings: Vec<Warning>,
+
+    /// The pool to draw decoded picture buffers from, if any.
+    picture_pool: Option<PicturePool>,
+
+    /// Which macroblocks, in raster order, to reconstruct without prediction.
+    forced_intra_macroblocks: Vec<bool>,
+
+    /// Only every this many pictures are output by `decode_next_frame`.
+    output_interval: usize,
+
+    /// How many pictures `decode_next_frame` has decoded so far.
+    frames_decoded: usize,
+
+    /// The callback to report quantized block coefficients to, if any.
+    coefficient_hook: Option<CoefficientHook>,
+
+    /// The quantizer to decode the next picture with, in place of `PQUANT`.
+    initial_quantizer: Option<u8>,
+
+    /// Which GOBs, in decode order, are output rather than copied from the
+    /// reference picture.
+    output_gob_mask: Vec<bool>,
+
+    /// The pool that `decode_next_discard` draws picture buffers from, if
+    /// no other pool was given.
+    discard_pool: PicturePool,
+
+    /// How many bytes of junk may precede the first picture start code.
+    max_leading_junk: u32,
+
+    /// The deblocked copy of the last decoded picture, if the `DEBLOCK`
+    /// option is in force.
+    deblocked_picture: Option<DecodedPicture>,
+
+    /// The motion vectors of each macroblock of the last decoded picture.
+    last_motion_vectors: Vec<[MotionVector; 4]>,
+
+    /// The quantizer of each macroblock of the last decoded picture.
+    last_quant_map: Vec<u8>,
+
+    /// The B-picture of the last decoded PB frame.
+    b_picture: Option<DecodedPicture>,
+
+    /// Whether the P-picture of the last decoded PB frame is still to be
+    /// output, after its B-picture.
+    is_p_picture_held: bool,
+
+    /// Copies of decoded pictures not yet output, if the `REORDER_OUTPUT`
+    /// option is in force, in decode order.
+    reorder_queue: Vec<DecodedPicture>,
+
+    /// The picture last taken from `reorder_queue` for output.
+    reordered_picture: Option<DecodedPicture>,
+
+    /// Whether `reorder_queue` is being emptied because no more pictures
+    /// could be decoded.
+    is_flushing_reorder_queue: bool,
+
+    /// Whether the last attempt to decode a picture found an end-of-sequence
+    /// code instead.
+    is_end_of_sequence: bool,
+
+    /// Bytes read from an asynchronous source but not yet decoded.
+    #[cfg(feature = "async")]
+    pub(super) async_buffer: Vec<u8>,
+}
+
+/// A builder for an `H263State`, with its decoder options checked for
+/// contradictions.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DecoderBuilder {
+    options: DecoderOptionBuilder,
+}
+
+impl DecoderBuilder {
+    /// Start building a decoder with no options enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable one or more decoder options.
+    pub fn options(mut self, options: DecoderOption) -> Self {
+        self.options = self.options.set(options, true);
+        self
+    }
+
+    /// End the bitstream at the first fatal decoding error.
+    ///
+    /// `decode_next_picture` then yields `Ok(None)` instead of the error,
+    /// which is kept for `last_error`.
+    pub fn stop_on_error(self) -> Self {
+        self.options(DecoderOption::STOP_ON_ERROR)
+    }
+
+    /// Construct the decoder, or yield an error if its options contradict
+    /// each other.
+    pub fn build(self) -> Result<H263State> {
+        Ok(H263State::new(self.options.build()?))
+    }
 }
 
 impl H263State {
@@ -47,6 +181,31 @@ impl H263State {
             reference_picture: None,
             running_options: PictureOption::empty(),
             reference_states: HashMap::new(),
+            reference_history: Vec::new(),
+            evicted_references: HashSet::new(),
+            max_references: 1,
+            last_error: None,
+            last_warnings: Vec::new(),
+            picture_pool: None,
+            forced_intra_macroblocks: Vec::new(),
+            output_interval: 1,
+            frames_decoded: 0,
+            coefficient_hook: None,
+            initial_quantizer: None,
+            output_gob_mask: Vec::new(),
+            discard_pool: PicturePool::new(),
+            max_leading_junk: 1024,
+            deblocked_picture: None,
+            b_picture: None,
+            is_p_picture_held: false,
+            last_motion_vectors: Vec::new(),
+            last_quant_map: Vec::new(),
+            reorder_queue: Vec::new(),
+            reordered_picture: None,
+            is_flushing_reorder_queue: false,
+            is_end_of_sequence: false,
+            #[cfg(feature = "async")]
+            async_buffer: Vec::new(),
         }
     }
 
@@ -56,46 +215,297 @@ impl H263State {
             .contains(DecoderOption::SORENSON_SPARK_BITSTREAM)
     }
 
+    /// Get the Sorenson Spark bitstream version of the last decoded picture.
+    ///
+    /// If `None`, then no pictures have yet to be decoded, or this decoder is
+    /// not in Sorenson mode.
+    pub fn sorenson_version(&self) -> Option<u8> {
+        self.get_last_picture()
+            .and_then(|picture| picture.as_header().version)
+    }
+
     /// Get the last picture decoded in the bitstream.
     ///
+    /// This is the picture as retained for prediction, so it is not
+    /// deblocked by the `DEBLOCK` option, only by the Annex J loop filter.
+    ///
     /// If `None`, then no pictures have yet to be decoded.
     pub fn get_last_picture(&self) -> Option<&DecodedPicture> {
-        if self.last_picture.is_none() {
-            None
-        } else {
-            self.reference_states.get(&self.last_picture.unwrap())
-        }
+        self.last_picture
+            .and_then(|lp| self.reference_states.get(&lp))
+    }
+
+    /// Get the motion vectors of the last decoded picture.
+    ///
+    /// There is one entry per macroblock, in raster order, holding the
+    /// vectors of its four luma blocks in half-pixel units. Unless the
+    /// macroblock was coded with four motion vectors (Annex F), all four are
+    /// the same. Intra and uncoded macroblocks have zero motion vectors.
+    pub fn last_motion_vectors(&self) -> &[[MotionVector; 4]] {
+        &self.last_motion_vectors
+    }
+
+    /// Get the quantizers of the last decoded picture.
+    ///
+    /// There is one entry per macroblock, in raster order, holding the
+    /// `QUANT` value it was reconstructed with after any `GQUANT` or `DQUANT`
+    /// update. These are the quantizers that select the strength of the
+    /// deblocking filter, and may be passed to `deblock_frame` directly.
+    /// Macroblocks that were not coded or were concealed take the quantizer
+    /// in force when they were reached.
+    pub fn last_quant_map(&self) -> &[u8] {
+        &self.last_quant_map
+    }
+
+    /// Get the error that stopped decoding.
+    ///
+    /// This is only ever populated if the `STOP_ON_ERROR` decoder option is
+    /// in force. If `None`, then decoding has not yet been stopped.
+    pub fn last_error(&self) -> Option<&Error> {
+        self.last_error.as_ref()
+    }
+
+    /// Get the non-fatal problems encountered while decoding the last picture.
+    pub fn last_warnings(&self) -> &[Warning] {
+        &self.last_warnings
+    }
+
+    /// Determine if the last picture decode operation stopped at an explicit
+    /// end-of-sequence (`EOS`) code.
+    ///
+    /// This distinguishes a bitstream that was cleanly terminated from one
+    /// that merely ran out of data; both yield `None` when decoding. The
+    /// code is consumed, so decoding may carry on with a following sequence.
+    pub fn is_end_of_sequence(&self) -> bool {
+        self.is_end_of_sequence
     }
 
     /// Get the implicit reference picture decoded in the bitstream.
     ///
-    /// If `None`, then no pictures have yet to be decoded.
+    /// This is the anchor that the next predicted picture will be predicted
+    /// from, unless another is selected with `select_reference_picture`: the
+    /// last decoded picture that is not disposable. It is not deblocked by
+    /// the `DEBLOCK` option, only by the Annex J loop filter, so it holds
+    /// exactly the samples predicted from. It may be used to visualize
+    /// prediction residuals or to conceal errors.
+    ///
+    /// If `None`, then no I-frame has yet been decoded.
     pub fn get_reference_picture(&self) -> Option<&DecodedPicture> {
-        if self.reference_picture.is_none() {
-            None
-        } else {
-            self.reference_states.get(&self.last_picture.unwrap())
-        }
+        self.reference_picture
+            .and_then(|rp| self.reference_states.get(&rp))
     }
 
-    /// Remove all disposable pictures from the reference states list.
-    pub fn cleanup_buffers(&mut self) {
-        let last_picture = self
-            .last_picture
-            .and_then(|lp| self.reference_states.remove_entry(&lp));
-        let reference_picture = self
-            .reference_picture
-            .and_then(|rp| self.reference_states.remove_entry(&rp));
+    /// Get a retained picture by its temporal reference.
+    ///
+    /// Only the last decoded picture and retained reference pictures (see
+    /// `set_max_references`) are available.
+    pub fn get_picture(&self, temporal_reference: u16) -> Option<&DecodedPicture> {
+        self.reference_states.get(&temporal_reference)
+    }
+
+    /// Set how many non-disposable pictures are retained as potential
+    /// reference pictures.
+    ///
+    /// The default of 1 only retains the implicit reference picture. At least
+    /// one picture is always retained. Once more pictures than this have been
+    /// decoded, the least recently used ones are evicted; decoding a picture
+    /// or selecting it as a reference counts as using it.
+    pub fn set_max_references(&mut self, max_references: usize) {
+        self.max_references = max_references.max(1);
+        self.cleanup_buffers();
+    }
+
+    /// Set the pool that decoded pictures draw their buffers from.
+    ///
+    /// Pictures decoded after this call recycle their buffers into `pool`
+    /// once they are dropped. If `None`, buffers are allocated for each
+    /// picture instead.
+    pub fn set_picture_pool(&mut self, pool: Option<PicturePool>) {
+        self.picture_pool = pool;
+    }
+
+    /// Force macroblocks to be reconstructed without inter prediction.
+    ///
+    /// This is intended for studying error propagation. Each entry of `mask`
+    /// corresponds to a macroblock, in raster order; macroblocks past the end
+    /// of the mask are unaffected. Masked macroblocks that the bitstream codes
+    /// as intra decode normally. All other masked macroblocks ignore their
+    /// motion vectors, and add their coded residual, if any, to a flat
+    /// mid-gray block instead of the prediction.
+    ///
+    /// The mask stays in force for all subsequent pictures, until it is
+    /// replaced. An empty mask disables this behavior.
+    pub fn set_forced_intra_macroblocks(&mut self, mask: Vec<bool>) {
+        self.forced_intra_macroblocks = mask;
+    }
+
+    /// Select which groups of blocks contribute to decoded pictures.
+    ///
+    /// This is intended for display experiments. Each entry of `mask`
+    /// corresponds to a GOB, in decode order; GOBs past the end of the mask
+    /// are selected. The rows of unselected GOBs are decoded, but then
+    /// replaced with the same rows of the reference picture, if it has the
+    /// same dimensions. The replaced rows are also what later pictures predict
+    /// from.
+    ///
+    /// The mask stays in force for all subsequent pictures, until it is
+    /// replaced. An empty mask selects every GOB.
+    pub fn set_output_gob_mask(&mut self, mask: Vec<bool>) {
+        self.output_gob_mask = mask;
+    }
+
+    /// Set how often `decode_next_frame` outputs a picture.
+    ///
+    /// Only the first of every `output_interval` pictures is output; the rest
+    /// are still decoded, so that later pictures predict correctly, but are
+    /// reported as skipped so callers can avoid converting or displaying
+    /// them. The default of 1 outputs every picture. An interval of 0 is
+    /// treated as 1.
+    pub fn set_output_interval(&mut self, output_interval: usize) {
+        self.output_interval = output_interval.max(1);
+    }
+
+    /// Set a callback to be given the quantized coefficients of every block.
+    ///
+    /// This is intended for bitstream analysis tools. After each coded
+    /// macroblock is read, `hook` is called once for each of its six blocks,
+    /// with the levels as coded in the bitstream, before dequantization. Blocks
+    /// that code no coefficients are reported as all zeroes, except for their
+    /// intra DC coefficient, if any. Uncoded macroblocks are not reported.
+    ///
+    /// If `None`, no coefficients are reported, and no work is done to
+    /// collect them.
+    pub fn set_coefficient_hook(&mut self, hook: Option<CoefficientHook>) {
+        self.coefficient_hook = hook;
+    }
+
+    /// Set the quantizer to start decoding the next picture with.
+    ///
+    /// This is intended for recovering partial streams, such as after seeking
+    /// into the middle of a picture whose header is unreliable. The quantizer
+    /// replaces the picture's own `PQUANT`, and stays in force until the
+    /// bitstream updates it with `GQUANT` or `DQUANT`. It only applies to the
+    /// next successfully decoded picture. Quantizers outside of the valid
+    /// range of 1 to 31 are clamped into it.
+    pub fn set_initial_quantizer(&mut self, quantizer: Option<u8>) {
+        self.initial_quantizer = quantizer.map(|q| q.clamp(1, 31));
+    }
+
+    /// Set how many bytes of junk may precede the first picture start code.
+    ///
+    /// Until a picture has been decoded, the decoder skips over up to this
+    /// many bytes of arbitrary data, such as container leftovers, looking for
+    /// a start code. The scan is bounded so that a source of pure garbage
+    /// fails quickly rather than being read in full. The default is 1024
+    /// bytes; a limit of 0 requires the bitstream to start with a picture.
+    pub fn set_max_leading_junk(&mut self, max_leading_junk: u32) {
+        self.max_leading_junk = max_leading_junk;
+    }
+
+    /// Seed the decoder with a picture to predict the next picture from.
+    ///
+    /// The picture is retained under its temporal reference, as though it were
+    /// the last picture decoded, and becomes the implicit reference picture.
+    /// This allows predicted pictures to be decoded after seeking, using a
+    /// reference picture synthesized by the caller.
+    pub fn insert_reference_picture(&mut self, picture: DecodedPicture) {
+        let this_tr = picture.as_header().temporal_reference;
 
-        self.reference_states = HashMap::new();
+        self.last_picture = Some(this_tr);
+        self.reference_picture = Some(this_tr);
+        self.reference_history.retain(|tr| *tr != this_tr);
+        self.reference_history.push(this_tr);
+        self.evicted_references.remove(&this_tr);
+        self.reference_states.insert(this_tr, picture);
+        self.deblocked_picture = None;
+        self.b_picture = None;
+        self.is_p_picture_held = false;
+        self.cleanup_buffers();
+    }
 
-        if let Some((k, lp)) = last_picture {
-            self.reference_states.insert(k, lp);
+    /// Select the reference picture for the next predicted picture.
+    ///
+    /// This overrides the implicit reference picture (the last non-disposable
+    /// picture decoded) with any retained reference picture, such as when
+    /// simulating reference picture selection after a lost picture.
+    ///
+    /// Yields `ReferenceEvicted` if the picture was decoded but no longer
+    /// fits within `set_max_references`, and `ReferencePictureMissing` if it
+    /// was never decoded as a reference picture.
+    pub fn select_reference_picture(&mut self, temporal_reference: u16) -> Result<()> {
+        if self.evicted_references.contains(&temporal_reference) {
+            return Err(Error::ReferenceEvicted);
         }
 
-        if let Some((k, rp)) = reference_picture {
-            self.reference_states.insert(k, rp);
+        if !self.reference_history.contains(&temporal_reference)
+            || !self.reference_states.contains_key(&temporal_reference)
+        {
+            return Err(Error::ReferencePictureMissing);
         }
+
+        self.reference_history
+            .retain(|tr| *tr != temporal_reference);
+        self.reference_history.push(temporal_reference);
+        self.reference_picture = Some(temporal_reference);
+
+        Ok(())
+    }
+
+    /// Return this decoder to the start of a fresh sequence.
+    ///
+    /// All decoded pictures, reference pictures and per-picture results are
+    /// discarded, as is any error that stopped decoding, so that the next
+    /// picture decoded is treated as the first of a new bitstream. This is
+    /// intended for seeking to an I-frame or switching streams, where no
+    /// stale reference data must carry over.
+    ///
+    /// Settings such as the decoder options, picture pool and masks are
+    /// kept. Pictures held for reordering under `REORDER_OUTPUT` are
+    /// discarded without being output. Allocations held by the decoder are
+    /// retained for reuse, and the planes of discarded pictures return to
+    /// their picture pool, if any.
+    pub fn reset(&mut self) {
+        self.last_picture = None;
+        self.reference_picture = None;
+        self.running_options = PictureOption::empty();
+        self.reference_states.clear();
+        self.reference_history.clear();
+        self.evicted_references.clear();
+        self.last_error = None;
+        self.last_warnings.clear();
+        self.frames_decoded = 0;
+        self.initial_quantizer = None;
+        self.deblocked_picture = None;
+        self.last_motion_vectors.clear();
+        self.last_quant_map.clear();
+        self.b_picture = None;
+        self.is_p_picture_held = false;
+        self.reorder_queue.clear();
+        self.reordered_picture = None;
+        self.is_flushing_reorder_queue = false;
+        self.is_end_of_sequence = false;
+        #[cfg(feature = "async")]
+        self.async_buffer.clear();
+    }
+
+    /// Remove all disposable and expired pictures from the reference states
+    /// list.
+    pub fn cleanup_buffers(&mut self) {
+        let expired = self
+            .reference_history
+            .len()
+            .saturating_sub(self.max_references);
+        self.evicted_references
+            .extend(self.reference_history.drain(..expired));
+
+        let last_picture = self.last_picture;
+        let reference_picture = self.reference_picture;
+        let reference_history = &self.reference_history;
+        self.reference_states.retain(|tr, _| {
+            Some(*tr) == last_picture
+                || Some(*tr) == reference_picture
+                || reference_history.contains(tr)
+        });
     }
 
     /// Parse a picture from the reader using the current state's decoder
@@ -106,17 +516,23 @@ impl H263State {
         previous_picture: Option<&Picture>,
     ) -> Result<Option<Picture>>
     where
-        R: Read,
+        R: BitstreamSource,
     {
         decode_picture(reader, self.decoder_options, previous_picture)
     }
 
     /// Decode the next picture in the bitstream.
     ///
-    /// This does not yield any picture data: it merely advances the state of
-    /// the encoder, if possible, such that the next picture in the stream can
-    /// be retrieved from it. Bits are retrieved from the `reader`, which must
-    /// be pointing to an optionally-aligned picture start code.
+    /// This advances the state of the decoder, if possible, and yields the
+    /// newly decoded picture. Bits are retrieved from the `reader`, which must
+    /// be pointing to an optionally-aligned picture start code. If the reader
+    /// runs out of data before a picture could be found, this yields `None`,
+    /// signalling the end of the bitstream. This also yields `None` at an
+    /// end-of-sequence code; see `is_end_of_sequence` to tell the two apart.
+    ///
+    /// If the `STOP_ON_ERROR` decoder option is in force, errors are not
+    /// returned; instead, the error is retained (see `last_error`) and this
+    /// function yields `None` for this and all subsequent calls.
     ///
     /// In the event that an error occurs, previously existing decoder state
     /// and underlying reader state will remain. You may inspect the error in
@@ -136,14 +552,365 @@ impl H263State {
     /// is OK, but seeking the reader to a new position is not. In order to
     /// seek to a new position, you must discard all existing decoder state,
     /// then seek to the position of a valid I frame and begin decoding anew.
-    pub fn decode_next_picture<R>(&mut self, reader: &mut H263Reader<R>) -> Result<()>
+    ///
+    /// The reader's `error_position` tells where in the bitstream decoding
+    /// gave up, and `resync_to_next_start_code` may be used to skip past the
+    /// damage.
+    pub fn decode_next_picture<R>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+    ) -> Result<Option<&DecodedPicture>>
+    where
+        R: BitstreamSource,
+    {
+        if self.last_error.is_some() {
+            return Ok(None);
+        }
+
+        let result = self.decode_picture_data(reader, None);
+
+        self.finish_decode(result)
+    }
+
+    /// Decode the next picture in the bitstream, subject to the output
+    /// interval.
+    ///
+    /// This behaves like `decode_next_picture`, except that pictures which are
+    /// not due for output under `set_output_interval` yield
+    /// `DecodedFrame::Skipped` instead of the picture.
+    pub fn decode_next_frame<R>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+    ) -> Result<Option<DecodedFrame<'_>>>
+    where
+        R: BitstreamSource,
+    {
+        if self.decode_next_picture(reader)?.is_none() {
+            return Ok(None);
+        }
+
+        let frame_index = self.frames_decoded;
+        self.frames_decoded += 1;
+
+        let picture = self.output_picture().ok_or(Error::InternalDecoderError)?;
+        if frame_index.is_multiple_of(self.output_interval) {
+            Ok(Some(DecodedFrame::Picture(picture)))
+        } else {
+            Ok(Some(DecodedFrame::Skipped {
+                temporal_reference: picture.as_header().temporal_reference,
+            }))
+        }
+    }
+
+    /// Decode the next picture in the bitstream, yielding only a summary
+    /// of it.
+    ///
+    /// This behaves like `decode_next_picture`, and advances the decoder in
+    /// exactly the same way, but is intended for measuring decoding
+    /// performance. Unless a pool was given with `set_picture_pool`, picture
+    /// buffers are drawn from a pool internal to this decoder, so that once
+    /// enough pictures have been decoded, no more buffers are allocated.
+    pub fn decode_next_discard<R>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+    ) -> Result<Option<PictureMeta>>
+    where
+        R: BitstreamSource,
+    {
+        if self.last_error.is_some() {
+            return Ok(None);
+        }
+
+        let user_pool = self.picture_pool.take();
+        self.picture_pool = Some(
+            user_pool
+                .clone()
+                .unwrap_or_else(|| self.discard_pool.clone()),
+        );
+        let result = self.decode_picture_data(reader, None);
+        self.picture_pool = user_pool;
+
+        Ok(self.finish_decode(result)?.map(DecodedPicture::meta))
+    }
+
+    /// Decode the next picture from a bitstream that is already in memory.
+    ///
+    /// This behaves like `decode_next_picture` on a reader over `data`,
+    /// which must start at an optionally-aligned picture start code, except
+    /// that `data` is read in place instead of being buffered. Along with the
+    /// picture, if any, this yields how many bytes of `data` were consumed,
+    /// so that the next call may be given the rest. This includes an
+    /// end-of-sequence code, if one was found instead of a picture. The count
+    /// is rounded up to a whole byte, and so is only exact if the next start
+    /// code is byte-aligned, as it is in FLV video tags.
+    pub fn decode_from_slice(&mut self, data: &[u8]) -> Result<(Option<&DecodedPicture>, usize)> {
+        let mut reader = H263Reader::from_slice(data);
+        let is_decoded = self.decode_next_picture(&mut reader)?.is_some();
+        let consumed = reader.bit_position().div_ceil(8).min(data.len());
+
+        let picture = if is_decoded {
+            Some(self.output_picture().ok_or(Error::InternalDecoderError)?)
+        } else {
+            None
+        };
+
+        Ok((picture, consumed))
+    }
+
+    /// Decode the next picture in the bitstream, reporting each group of
+    /// blocks as soon as it has been reconstructed.
+    ///
+    /// This behaves like `decode_next_picture`, except that `on_gob` is called
+    /// in decode order after each GOB's reconstruction is complete. It is
+    /// given the index of the GOB within the picture, the range of luma rows
+    /// it covers, and the picture being decoded, whose samples within those
+    /// rows (and the corresponding chroma rows) are final. The row ranges of
+    /// all GOBs tile the picture from top to bottom.
+    ///
+    /// Pictures filtered in the loop by Annex J are only final once the
+    /// filter has crossed every GOB boundary, so their GOBs are all reported
+    /// after the whole picture is reconstructed.
+    ///
+    /// Bitstreams without GOB headers, such as Sorenson Spark, are reported
+    /// with the GOB layout H.263 would use for the same picture size.
+    pub fn decode_next_picture_progressive<R, F>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+        mut on_gob: F,
+    ) -> Result<Option<&DecodedPicture>>
+    where
+        R: BitstreamSource,
+        F: FnMut(usize, Range<usize>, &DecodedPicture),
+    {
+        if self.last_error.is_some() {
+            return Ok(None);
+        }
+
+        let result = self.decode_picture_data(reader, Some(&mut on_gob));
+
+        self.finish_decode(result)
+    }
+
+    /// Get the last decoded picture as it is to be output.
+    ///
+    /// If the `REORDER_OUTPUT` option is in force, this is the picture last
+    /// released from the reorder queue. Otherwise, it is the B-picture of a
+    /// PB frame whose P-picture is being held back; failing that, it is the
+    /// deblocked copy of the last picture if the `DEBLOCK` option is in
+    /// force, and the last picture itself otherwise.
+    fn output_picture(&self) -> Option<&DecodedPicture> {
+        if self.decoder_options.contains(DecoderOption::REORDER_OUTPUT) {
+            return self.reordered_picture.as_ref();
+        }
+
+        self.decoded_output_picture()
+    }
+
+    /// Get the last decoded picture as it is to be output, in decode order.
+    fn decoded_output_picture(&self) -> Option<&DecodedPicture> {
+        if self.is_p_picture_held {
+            return self.b_picture.as_ref();
+        }
+
+        self.deblocked_picture
+            .as_ref()
+            .or_else(|| self.get_last_picture())
+    }
+
+    /// Release the P-picture of the last decoded PB frame for output, if it
+    /// is being held back behind its B-picture.
+    ///
+    /// Yields the temporal reference of the released picture. A picture
+    /// decode operation that releases a picture must not read any bits, as
+    /// the picture has already been decoded.
+    pub(super) fn release_held_picture(&mut self) -> Option<u16> {
+        if !self.is_p_picture_held {
+            return None;
+        }
+
+        self.is_p_picture_held = false;
+        self.last_picture
+    }
+
+    /// Convert the result of decoding picture data into the result of a
+    /// picture decode operation, applying the `STOP_ON_ERROR` option.
+    pub(super) fn finish_decode(
+        &mut self,
+        result: Result<Option<u16>>,
+    ) -> Result<Option<&DecodedPicture>> {
+        match result {
+            Ok(Some(_)) => Ok(self.output_picture()),
+            Ok(None) => Ok(None),
+            Err(e) if self.decoder_options.contains(DecoderOption::STOP_ON_ERROR) => {
+                self.last_error = Some(e);
+
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Queue a copy of the picture just decoded for reordering.
+    pub(super) fn queue_for_reorder(&mut self) {
+        if let Some(picture) = self.decoded_output_picture() {
+            let copy = picture.copy_in(self.picture_pool.as_ref());
+            self.reorder_queue.push(copy);
+        }
+    }
+
+    /// Release the earliest picture in display order from the reorder queue
+    /// for output, if one is due.
+    ///
+    /// One picture is always kept back in the queue, unless it is being
+    /// flushed. Yields the temporal reference of the released picture.
+    pub(super) fn release_reordered_picture(&mut self) -> Option<u16> {
+        if self.reorder_queue.len() < 2 && !self.is_flushing_reorder_queue {
+            return None;
+        }
+
+        //Temporal references wrap around, so display order is judged by
+        //distance modulo the 8-bit `TR` (or 10 bits, with `ETR`), which is
+        //never more than half of the range between pictures in the queue.
+        let precedes = |a: &Picture, b: &Picture| {
+            let modulus = b.temporal_reference_modulus();
+            let distance = b.temporal_reference.wrapping_sub(a.temporal_reference) % modulus;
+
+            (1..modulus / 2).contains(&distance)
+        };
+        let earliest = (0..self.reorder_queue.len()).reduce(|earliest, index| {
+            let header = |i: usize| self.reorder_queue[i].as_header();
+            if precedes(header(index), header(earliest)) {
+                index
+            } else {
+                earliest
+            }
+        });
+
+        match earliest {
+            Some(index) => {
+                let picture = self.reorder_queue.remove(index);
+                let this_tr = picture.as_header().temporal_reference;
+                self.reordered_picture = Some(picture);
+
+                Some(this_tr)
+            }
+            None => {
+                self.is_flushing_reorder_queue = false;
+
+                None
+            }
+        }
+    }
+
+    /// Pass the result of decoding one picture, in decode order, through the
+    /// reorder queue if the `REORDER_OUTPUT` option is in force.
+    ///
+    /// Yields the result of the picture decode operation, or `None` if
+    /// another picture must be decoded before one is due for output.
+    pub(super) fn reorder_decoded(
+        &mut self,
+        result: Result<Option<u16>>,
+    ) -> Option<Result<Option<u16>>> {
+        if !self.decoder_options.contains(DecoderOption::REORDER_OUTPUT) {
+            return Some(result);
+        }
+
+        match result {
+            Ok(Some(_)) => self.queue_for_reorder(),
+            Ok(None) => self.is_flushing_reorder_queue = true,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let was_flushing = self.is_flushing_reorder_queue;
+        match self.release_reordered_picture() {
+            Some(this_tr) => Some(Ok(Some(this_tr))),
+            None if was_flushing => Some(Ok(None)),
+            None => None,
+        }
+    }
+
+    /// Determine if the reorder queue is being emptied, in which case no
+    /// more pictures should be decoded until it is.
+    pub(super) fn is_flushing_reorder_queue(&self) -> bool {
+        self.is_flushing_reorder_queue
+    }
+
+    /// Decode the next picture in the bitstream into the reference pile.
+    ///
+    /// Yields the temporal reference of the picture due for output, or
+    /// `None` if the bitstream ended before a picture could be found. If the
+    /// `REORDER_OUTPUT` option is in force, this decodes until a picture is
+    /// due for output in display order.
+    pub(super) fn decode_picture_data<R>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+        mut on_gob: Option<GobCallback<'_>>,
+    ) -> Result<Option<u16>>
     where
-        R: Read,
+        R: BitstreamSource,
     {
+        reader.clear_error_position();
+
+        loop {
+            let result = if self.is_flushing_reorder_queue() {
+                Ok(None)
+            } else {
+                self.decode_next_in_order(reader, &mut on_gob)
+            };
+
+            if let Some(result) = self.reorder_decoded(result) {
+                return result;
+            }
+        }
+    }
+
+    /// Decode the next picture in the bitstream into the reference pile, in
+    /// decode order.
+    ///
+    /// Yields the temporal reference of the decoded picture, or `None` if the
+    /// bitstream ended before a picture could be found.
+    pub(super) fn decode_next_in_order<R>(
+        &mut self,
+        reader: &mut H263Reader<R>,
+        on_gob: &mut Option<GobCallback<'_>>,
+    ) -> Result<Option<u16>>
+    where
+        R: BitstreamSource,
+    {
+        if let Some(this_tr) = self.release_held_picture() {
+            return Ok(Some(this_tr));
+        }
+
         reader.with_transaction(|reader| {
-            let next_picture = self
-                .parse_picture(reader, self.get_last_picture().map(|p| p.as_header()))?
-                .ok_or(Error::MiddleOfBitstream)?;
+            if self.last_picture.is_none() {
+                match reader.recognize_start_code_within(self.max_leading_junk.saturating_mul(8)) {
+                    Ok(Some(junk_bits)) => reader.skip_bits(junk_bits)?,
+                    Ok(None) => {}
+                    Err(ref e) if e.is_eof_error() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            self.is_end_of_sequence = match decode_end_of_sequence(reader) {
+                Ok(is_end_of_sequence) => is_end_of_sequence,
+                Err(ref e) if e.is_eof_error() => false,
+                Err(e) => return Err(e),
+            };
+            if self.is_end_of_sequence {
+                reader.commit();
+
+                return Ok(None);
+            }
+
+            let next_picture =
+                match self.parse_picture(reader, self.get_last_picture().map(|p| p.as_header())) {
+                    Ok(Some(picture)) => picture,
+                    Ok(None) => return Err(Error::MiddleOfBitstream),
+
+                    //Treat EOF errors as end of bitstream
+                    Err(ref e) if e.is_eof_error() => return Ok(None),
+                    Err(e) => return Err(e),
+                };
 
             let next_running_options = if next_picture.has_plusptype && next_picture.has_opptype {
                 next_picture.options
@@ -151,8 +918,13 @@ impl H263State {
                 (next_picture.options & !*OPPTYPE_OPTIONS)
                     | (self.running_options & *OPPTYPE_OPTIONS)
             } else {
-                (next_picture.options & !*OPPTYPE_OPTIONS & !*MPPTYPE_OPTIONS)
-                    | (self.running_options & (*OPPTYPE_OPTIONS | *MPPTYPE_OPTIONS))
+                //PTYPE restates the modes it can signal for every picture.
+                let ptype_options = PictureOption::UNRESTRICTED_MOTION_VECTORS
+                    | PictureOption::SYNTAX_BASED_ARITHMETIC_CODING
+                    | PictureOption::ADVANCED_PREDICTION;
+                let carried_options = (*OPPTYPE_OPTIONS | *MPPTYPE_OPTIONS) & !ptype_options;
+
+                (next_picture.options & !carried_options) | (self.running_options & carried_options)
             };
 
             let format = if let Some(format) = next_picture.format {
@@ -165,46 +937,131 @@ impl H263State {
                 return Err(Error::PictureFormatMissing);
             };
 
-            let reference_picture = self.get_reference_picture();
+            let reference_picture = match self.reference_picture {
+                Some(rp) => self.reference_states.get(&rp),
+                None => None,
+            };
 
             let output_dimensions = format
                 .into_width_and_height()
                 .ok_or(Error::PictureFormatInvalid)?;
 
+            if !matches!(next_picture.picture_type, PictureTypeCode::IFrame) {
+                let reference_dimensions = reference_picture
+                    .and_then(|reference| reference.format().into_width_and_height());
+                if let Some(reference_dimensions) = reference_dimensions {
+                    if reference_dimensions != output_dimensions {
+                        return Err(Error::DimensionMismatch {
+                            picture: output_dimensions,
+                            reference: reference_dimensions,
+                        });
+                    }
+                }
+            }
+
             let mb_per_line = (output_dimensions.0 as f64 / 16.0).ceil() as usize;
             let mb_height = (output_dimensions.1 as f64 / 16.0).ceil() as usize;
 
             let level_dimensions = (mb_per_line * 16, mb_height * 16);
 
-            let mut in_force_quantizer = next_picture.quantizer;
+            let is_monochrome = self.decoder_options.contains(DecoderOption::MONOCHROME);
+            let is_luma_only = self
+                .decoder_options
+                .contains(DecoderOption::DECODE_LUMA_ONLY);
+            let has_neutral_chroma = is_monochrome || is_luma_only;
+            let reconstructed_blocks = if is_luma_only { 4 } else { 6 };
+            let is_concealing = self.decoder_options.contains(DecoderOption::CONCEAL_ERRORS);
+            let mb_per_gob = mb_per_line * macroblock_rows_per_gob(output_dimensions.1);
+            let mut in_force_quantizer = self.initial_quantizer.unwrap_or(next_picture.quantizer);
             let mut predictor_vectors = Vec::with_capacity(mb_per_line * mb_height); // all previously decoded MVDs
             let mut macroblock_types = Vec::with_capacity(mb_per_line * mb_height);
+            let mut macroblock_quantizers = Vec::with_capacity(mb_per_line * mb_height);
+            let mut macroblock_coded = Vec::with_capacity(mb_per_line * mb_height);
+            let mut macroblock_concealed = Vec::with_capacity(mb_per_line * mb_height);
             let mut macroblocks_after_gob = 0; //reset after every GOB header
 
+            let mut warnings = Vec::new();
+
+            //PB frames interleave the macroblocks of a B-picture with those of
+            //the P-picture. TRD is the temporal distance from the previous
+            //reference picture to the P-picture.
+            let pb_frame = match (
+                next_picture.picture_type,
+                next_picture.pb_reference,
+                &next_picture.pb_quantizer,
+            ) {
+                (PictureTypeCode::PbFrame, Some(trb), Some(dbquant)) => {
+                    let reference_tr = reference_picture
+                        .ok_or(Error::UncodedIFrameBlocks)?
+                        .as_header()
+                        .temporal_reference;
+                    let modulus = next_picture.temporal_reference_modulus();
+                    let trd = next_picture.temporal_reference.wrapping_sub(reference_tr) % modulus;
+                    if trd == 0 {
+                        warnings.push(Warning::ZeroTemporalDistance);
+                    }
+
+                    Some((trb, trd.max(1), dbquant.clone()))
+                }
+                _ => None,
+            };
+
             let mut next_decoded_picture =
-                DecodedPicture::new(next_picture, format).ok_or(Error::PictureFormatInvalid)?;
+                DecodedPicture::new_in(next_picture, format, self.picture_pool.as_ref())
+                    .ok_or(Error::PictureFormatInvalid)?;
 
             let mut luma_levels = vec![[[0.0; 8]; 8]; level_dimensions.0 * level_dimensions.1 / 64];
             let mut chroma_b_levels =
                 vec![[[0.0; 8]; 8]; level_dimensions.0 * level_dimensions.1 / 4 / 64];
             let mut chroma_r_levels =
                 vec![[[0.0; 8]; 8]; level_dimensions.0 * level_dimensions.1 / 4 / 64];
+            let mut luma_masks = vec![0; luma_levels.len()];
+            let mut chroma_b_masks = vec![0; chroma_b_levels.len()];
+            let mut chroma_r_masks = vec![0; chroma_r_levels.len()];
+
+            //Only PB frames have B-blocks to decode.
+            let b_scale = usize::from(pb_frame.is_some());
+            let mut b_luma_levels = vec![[[0.0; 8]; 8]; luma_levels.len() * b_scale];
+            let mut b_chroma_b_levels = vec![[[0.0; 8]; 8]; chroma_b_levels.len() * b_scale];
+            let mut b_chroma_r_levels = vec![[[0.0; 8]; 8]; chroma_r_levels.len() * b_scale];
+            let mut b_luma_masks = vec![0; b_luma_levels.len()];
+            let mut b_chroma_b_masks = vec![0; b_chroma_b_levels.len()];
+            let mut b_chroma_r_masks = vec![0; b_chroma_r_levels.len()];
+            let mut b_vectors = Vec::with_capacity(mb_per_line * mb_height * b_scale);
 
             loop {
+                //All macroblocks are present, so the picture is complete.
+                if macroblock_types.len() >= mb_per_line * mb_height {
+                    break;
+                }
+
                 let mb = decode_macroblock(
                     reader,
                     next_decoded_picture.as_header(),
                     next_running_options,
-                );
+                )
+                .and_then(|mb| {
+                    let blocks = decode_macroblock_blocks(
+                        reader,
+                        self.decoder_options,
+                        next_decoded_picture.as_header(),
+                        next_running_options,
+                        &mb,
+                    )?;
+
+                    Ok((mb, blocks))
+                });
                 let pos = (
                     (macroblock_types.len() % mb_per_line) * 16,
                     (macroblock_types.len() / mb_per_line) * 16,
                 );
                 let mut motion_vectors = [MotionVector::zero(); 4];
+                let mut b_motion_vectors = ([MotionVector::zero(); 4], [MotionVector::zero(); 4]);
+                let is_coded = matches!(mb, Ok((Macroblock::Coded { .. }, _)));
 
                 let mb_type = match mb {
-                    Ok(Macroblock::Stuffing) => continue,
-                    Ok(Macroblock::Uncoded) => {
+                    Ok((Macroblock::Stuffing, _)) => continue,
+                    Ok((Macroblock::Uncoded, _)) => {
                         if matches!(
                             next_decoded_picture.as_header().picture_type,
                             PictureTypeCode::IFrame
@@ -214,17 +1071,19 @@ impl H263State {
 
                         MacroblockType::Inter
                     }
-                    Ok(Macroblock::Coded {
-                        mb_type,
-                        coded_block_pattern,
-                        coded_block_pattern_b: _coded_block_pattern_b,
-                        d_quantizer,
-                        motion_vector,
-                        addl_motion_vectors,
-                        motion_vectors_b: _motion_vectors_b,
-                    }) => {
+                    Ok((
+                        Macroblock::Coded {
+                            mb_type,
+                            d_quantizer,
+                            motion_vector,
+                            addl_motion_vectors,
+                            motion_vector_b,
+                            ..
+                        },
+                        blocks,
+                    )) => {
                         let quantizer = in_force_quantizer as i8 + d_quantizer.unwrap_or(0);
-                        in_force_quantizer = max(1, min(31, quantizer)) as u8;
+                        in_force_quantizer = quantizer.clamp(1, 31) as u8;
 
                         if mb_type.is_inter() {
                             let mv1 = motion_vector.unwrap_or_else(MotionVector::zero);
@@ -284,118 +1143,163 @@ impl H263State {
                             };
                         };
 
-                        let luma0 = decode_block(
-                            reader,
-                            self.decoder_options,
-                            next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_luma[0],
-                        )?;
-                        inverse_rle(
-                            &luma0,
-                            &mut luma_levels,
-                            pos,
-                            level_dimensions.0 / 8,
-                            in_force_quantizer,
-                        );
+                        let p_blocks = blocks[..6].iter().take(reconstructed_blocks);
+                        for (block, encoded_block) in p_blocks.enumerate() {
+                            let (levels, masks, block_pos, levels_per_row) = match block {
+                                0..=3 => (
+                                    &mut luma_levels,
+                                    &mut luma_masks,
+                                    (pos.0 + block % 2 * 8, pos.1 + block / 2 * 8),
+                                    level_dimensions.0 / 8,
+                                ),
+                                4 => (
+                                    &mut chroma_b_levels,
+                                    &mut chroma_b_masks,
+                                    (pos.0 / 2, pos.1 / 2),
+                                    mb_per_line,
+                                ),
+                                _ => (
+                                    &mut chroma_r_levels,
+                                    &mut chroma_r_masks,
+                                    (pos.0 / 2, pos.1 / 2),
+                                    mb_per_line,
+                                ),
+                            };
 
-                        let luma1 = decode_block(
-                            reader,
-                            self.decoder_options,
-                            next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_luma[1],
-                        )?;
-                        inverse_rle(
-                            &luma1,
-                            &mut luma_levels,
-                            (pos.0 + 8, pos.1),
-                            level_dimensions.0 / 8,
-                            in_force_quantizer,
-                        );
+                            inverse_rle(
+                                encoded_block,
+                                levels,
+                                masks,
+                                block_pos,
+                                levels_per_row,
+                                in_force_quantizer,
+                                self.decoder_options,
+                            );
+                        }
 
-                        let luma2 = decode_block(
-                            reader,
-                            self.decoder_options,
-                            next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_luma[2],
-                        )?;
-                        inverse_rle(
-                            &luma2,
-                            &mut luma_levels,
-                            (pos.0, pos.1 + 8),
-                            level_dimensions.0 / 8,
-                            in_force_quantizer,
-                        );
+                        if let Some(hook) = self.coefficient_hook.as_mut() {
+                            let block_count = if is_monochrome { 4 } else { 6 };
+                            for (block, encoded_block) in blocks[..block_count].iter().enumerate() {
+                                hook(&QuantizedBlock {
+                                    macroblock: macroblock_types.len(),
+                                    block,
+                                    levels: quantized_levels(encoded_block),
+                                });
+                            }
+                        }
 
-                        let luma3 = decode_block(
-                            reader,
-                            self.decoder_options,
-                            next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_luma[3],
-                        )?;
-                        inverse_rle(
-                            &luma3,
-                            &mut luma_levels,
-                            (pos.0 + 8, pos.1 + 8),
-                            level_dimensions.0 / 8,
-                            in_force_quantizer,
-                        );
+                        if let Some((trb, trd, dbquant)) = &pb_frame {
+                            //Intra macroblocks still send a vector, which
+                            //only serves to predict their B-blocks.
+                            let p_vectors = if mb_type.is_intra() {
+                                let mvd = motion_vector.unwrap_or_else(MotionVector::zero);
+                                let mpred = predict_candidate(
+                                    &predictor_vectors[macroblocks_after_gob..],
+                                    &motion_vectors,
+                                    mb_per_line,
+                                    0,
+                                );
 
-                        let chroma_b = decode_block(
-                            reader,
-                            self.decoder_options,
-                            next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_chroma_b,
-                        )?;
-                        inverse_rle(
-                            &chroma_b,
-                            &mut chroma_b_levels,
-                            (pos.0 / 2, pos.1 / 2),
-                            mb_per_line,
-                            in_force_quantizer,
-                        );
+                                [mv_decode(&next_decoded_picture, next_running_options, mpred, mvd);
+                                    4]
+                            } else {
+                                motion_vectors
+                            };
+
+                            let mvdb = motion_vector_b.unwrap_or_else(MotionVector::zero);
+                            for (block, mv) in p_vectors.iter().enumerate() {
+                                let (forward, backward) =
+                                    pb_vectors(*mv, mvdb, *trb as i16, *trd as i16);
+                                b_motion_vectors.0[block] = forward;
+                                b_motion_vectors.1[block] = backward;
+                            }
+
+                            let b_quantizer = dbquant.b_quantizer(in_force_quantizer);
+                            let b_blocks = blocks[6..].iter().take(reconstructed_blocks);
+                            for (block, encoded_block) in b_blocks.enumerate() {
+                                let (levels, masks, block_pos, levels_per_row) = match block {
+                                    0..=3 => (
+                                        &mut b_luma_levels,
+                                        &mut b_luma_masks,
+                                        (pos.0 + block % 2 * 8, pos.1 + block / 2 * 8),
+                                        level_dimensions.0 / 8,
+                                    ),
+                                    4 => (
+                                        &mut b_chroma_b_levels,
+                                        &mut b_chroma_b_masks,
+                                        (pos.0 / 2, pos.1 / 2),
+                                        mb_per_line,
+                                    ),
+                                    _ => (
+                                        &mut b_chroma_r_levels,
+                                        &mut b_chroma_r_masks,
+                                        (pos.0 / 2, pos.1 / 2),
+                                        mb_per_line,
+                                    ),
+                                };
 
-                        let chroma_r = decode_block(
+                                inverse_rle(
+                                    encoded_block,
+                                    levels,
+                                    masks,
+                                    block_pos,
+                                    levels_per_row,
+                                    b_quantizer,
+                                    self.decoder_options,
+                                );
+                            }
+                        }
+
+                        mb_type
+                    }
+
+                    //Attempt to recover from macroblock errors if possible. When
+                    //concealing errors, any error is recovered from by
+                    //skipping to the next start code.
+                    Err(ref e)
+                        if (e.is_macroblock_error() && !self.is_sorenson())
+                            || (is_concealing && !e.is_eof_error()) =>
+                    {
+                        let mut gob = decode_gob(
                             reader,
                             self.decoder_options,
                             next_decoded_picture.as_header(),
-                            next_running_options,
-                            mb_type,
-                            coded_block_pattern.codes_chroma_r,
-                        )?;
-                        inverse_rle(
-                            &chroma_r,
-                            &mut chroma_r_levels,
-                            (pos.0 / 2, pos.1 / 2),
-                            mb_per_line,
-                            in_force_quantizer,
                         );
+                        if is_concealing && matches!(gob, Err(ref e) if e.is_gob_error()) {
+                            gob = reader.resync_to_next_start_code().and_then(|_| {
+                                decode_gob(
+                                    reader,
+                                    self.decoder_options,
+                                    next_decoded_picture.as_header(),
+                                )
+                            });
+                        }
 
-                        mb_type
-                    }
-
-                    //Attempt to recover from macroblock errors if possible
-                    Err(ref e) if e.is_macroblock_error() && !self.is_sorenson() => {
-                        match decode_gob(reader, self.decoder_options) {
+                        match gob {
                             //Resynchronized to end of picture.
                             Ok(None) => break,
 
                             //Resynchronized to end of GOB.
                             Ok(Some(GroupOfBlocks {
-                                group_number: _group_number,
+                                group_number,
                                 multiplex_bitstream: _multiplex_bitstream,
                                 frame_id: _frame_id,
                                 quantizer,
                             })) => {
+                                //Conceal everything skipped to reach the GOB.
+                                let gob_start = (group_number as usize * mb_per_gob)
+                                    .min(mb_per_line * mb_height);
+                                while is_concealing && macroblock_types.len() < gob_start {
+                                    predictor_vectors.push([MotionVector::zero(); 4]);
+                                    macroblock_types.push(MacroblockType::Inter);
+                                    macroblock_quantizers.push(in_force_quantizer);
+                                    macroblock_coded.push(false);
+                                    macroblock_concealed.push(true);
+                                    if pb_frame.is_some() {
+                                        b_vectors.push(b_motion_vectors);
+                                    }
+                                }
+
                                 in_force_quantizer = quantizer;
                                 macroblocks_after_gob = macroblock_types.len();
                                 continue;
@@ -414,6 +1318,12 @@ impl H263State {
 
                 predictor_vectors.push(motion_vectors);
                 macroblock_types.push(mb_type);
+                macroblock_quantizers.push(in_force_quantizer);
+                macroblock_coded.push(is_coded);
+                macroblock_concealed.push(false);
+                if pb_frame.is_some() {
+                    b_vectors.push(b_motion_vectors);
+                }
             }
 
             //If the picture ended early, assume all the remaining blocks are
@@ -424,6 +1334,44 @@ impl H263State {
 
             if macroblock_types.len() < macroblock_types.capacity() {
                 macroblock_types.resize(macroblock_types.capacity(), MacroblockType::Inter);
+                macroblock_quantizers.resize(macroblock_types.len(), in_force_quantizer);
+                macroblock_coded.resize(macroblock_types.len(), false);
+                macroblock_concealed.resize(macroblock_types.len(), is_concealing);
+            }
+
+            if pb_frame.is_some() {
+                b_vectors.resize(
+                    mb_per_line * mb_height,
+                    ([MotionVector::zero(); 4], [MotionVector::zero(); 4]),
+                );
+            }
+
+            //Replace the prediction of macroblocks with gray where requested,
+            //keeping whatever residual they have.
+            for (i, mb_type) in macroblock_types.iter_mut().enumerate() {
+                //Concealed macroblocks also have nothing to be copied from
+                //without a reference picture.
+                let is_forced = self.forced_intra_macroblocks.get(i) == Some(&true)
+                    || (macroblock_concealed[i] && reference_picture.is_none());
+                if !is_forced || mb_type.is_intra() {
+                    continue;
+                }
+
+                let pos = ((i % mb_per_line) * 16, (i / mb_per_line) * 16);
+                for offset in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+                    let block_id =
+                        (pos.0 + offset.0) / 8 + (pos.1 + offset.1) / 8 * mb_per_line * 2;
+                    luma_levels[block_id][0][0] += GRAY_DC;
+                    luma_masks[block_id] |= 1;
+                }
+
+                chroma_b_levels[i][0][0] += GRAY_DC;
+                chroma_b_masks[i] |= 1;
+                chroma_r_levels[i][0][0] += GRAY_DC;
+                chroma_r_masks[i] |= 1;
+                macroblock_coded[i] = true;
+                predictor_vectors[i] = [MotionVector::zero(); 4];
+                *mb_type = MacroblockType::Intra;
             }
 
             //We have now read out all of the macroblock and block data and
@@ -434,28 +1382,211 @@ impl H263State {
                 reference_picture,
                 &predictor_vectors,
                 mb_per_line,
+                next_running_options.contains(PictureOption::ADVANCED_PREDICTION),
+                //Motion vectors over the picture edge are only allowed by
+                //Annexes D and F.
+                next_running_options.intersects(
+                    PictureOption::UNRESTRICTED_MOTION_VECTORS | PictureOption::ADVANCED_PREDICTION,
+                ),
+                has_neutral_chroma,
                 &mut next_decoded_picture,
+                &mut warnings,
             )?;
-            idct_channel(
-                &luma_levels,
-                next_decoded_picture.as_luma_mut(),
-                mb_per_line * 2,
-                (output_dimensions.0).into(),
-            );
 
-            let chroma_samples_per_row = next_decoded_picture.chroma_samples_per_row();
-            idct_channel(
-                &chroma_b_levels,
-                next_decoded_picture.as_chroma_b_mut(),
-                mb_per_line,
-                chroma_samples_per_row,
-            );
-            idct_channel(
-                &chroma_r_levels,
-                next_decoded_picture.as_chroma_r_mut(),
-                mb_per_line,
-                chroma_samples_per_row,
+            if has_neutral_chroma {
+                next_decoded_picture.as_chroma_b_mut().fill(128);
+                next_decoded_picture.as_chroma_r_mut().fill(128);
+                chroma_b_masks.fill(0);
+                chroma_r_masks.fill(0);
+            }
+
+            warnings.extend(
+                macroblock_concealed
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, is_concealed)| **is_concealed)
+                    .map(|(macroblock, _)| Warning::MacroblockConcealed { macroblock }),
             );
+            //Motion compensation only reads the reference picture, so the
+            //residual can be added one GOB at a time.
+            let luma_samples_per_row = output_dimensions.0 as usize;
+            let luma_height = output_dimensions.1 as usize;
+            let chroma_samples_per_row = next_decoded_picture.chroma_samples_per_row();
+            let chroma_height = next_decoded_picture.as_chroma_b().len() / chroma_samples_per_row;
+            let mb_rows_per_gob = macroblock_rows_per_gob(output_dimensions.1);
+
+            //Annex J filters the picture in the loop, before it is retained
+            //for prediction, so that the encoder and decoder predict from
+            //the same samples. The filter crosses GOB boundaries, so GOBs
+            //are only finished once the whole picture has been filtered.
+            let is_loop_filtered = next_running_options.contains(PictureOption::DEBLOCKING_FILTER);
+
+            //The last GOB is shorter if the picture height is not a multiple
+            //of the GOB height, so its rows are clipped to the picture.
+            let gob_rows: Vec<_> = (0..mb_height)
+                .step_by(mb_rows_per_gob)
+                .map(|first_mb_row| {
+                    let mb_rows = first_mb_row..(first_mb_row + mb_rows_per_gob).min(mb_height);
+                    let luma_rows = mb_rows.start * 16..(mb_rows.end * 16).min(luma_height);
+                    let chroma_rows = mb_rows.start * 8..(mb_rows.end * 8).min(chroma_height);
+
+                    (mb_rows, luma_rows, chroma_rows)
+                })
+                .collect();
+
+            for (gob_index, (mb_rows, luma_rows, chroma_rows)) in gob_rows.iter().enumerate() {
+                let luma_blocks = mb_rows.start * mb_per_line * 4..mb_rows.end * mb_per_line * 4;
+                let chroma_blocks = mb_rows.start * mb_per_line..mb_rows.end * mb_per_line;
+                let luma_samples =
+                    luma_rows.start * luma_samples_per_row..luma_rows.end * luma_samples_per_row;
+                let chroma_samples = chroma_rows.start * chroma_samples_per_row
+                    ..chroma_rows.end * chroma_samples_per_row;
+
+                idct_channel(
+                    &luma_levels[luma_blocks.clone()],
+                    &luma_masks[luma_blocks],
+                    &mut next_decoded_picture.as_luma_mut()[luma_samples.clone()],
+                    mb_per_line * 2,
+                    luma_samples_per_row,
+                );
+                idct_channel(
+                    &chroma_b_levels[chroma_blocks.clone()],
+                    &chroma_b_masks[chroma_blocks.clone()],
+                    &mut next_decoded_picture.as_chroma_b_mut()[chroma_samples.clone()],
+                    mb_per_line,
+                    chroma_samples_per_row,
+                );
+                idct_channel(
+                    &chroma_r_levels[chroma_blocks.clone()],
+                    &chroma_r_masks[chroma_blocks],
+                    &mut next_decoded_picture.as_chroma_r_mut()[chroma_samples],
+                    mb_per_line,
+                    chroma_samples_per_row,
+                );
+
+                if !is_loop_filtered {
+                    self.finish_gob(
+                        reference_picture,
+                        &mut next_decoded_picture,
+                        gob_index,
+                        (luma_rows.clone(), chroma_rows.clone()),
+                        on_gob,
+                    );
+                }
+            }
+
+            if is_loop_filtered {
+                let (luma, chroma_b, chroma_r) = next_decoded_picture.as_yuv_mut();
+                deblock_frame(
+                    luma,
+                    chroma_b,
+                    chroma_r,
+                    luma_samples_per_row,
+                    &macroblock_quantizers,
+                    &macroblock_coded,
+                );
+
+                for (gob_index, (_, luma_rows, chroma_rows)) in gob_rows.iter().enumerate() {
+                    self.finish_gob(
+                        reference_picture,
+                        &mut next_decoded_picture,
+                        gob_index,
+                        (luma_rows.clone(), chroma_rows.clone()),
+                        on_gob,
+                    );
+                }
+            }
+
+            //Otherwise, the deblocked picture is only for output; prediction
+            //continues from the picture as reconstructed.
+            self.deblocked_picture = None;
+            if self.decoder_options.contains(DecoderOption::DEBLOCK) && !is_loop_filtered {
+                let mut deblocked = DecodedPicture::new_in(
+                    next_decoded_picture.as_header().clone(),
+                    format,
+                    self.picture_pool.as_ref(),
+                )
+                .ok_or(Error::PictureFormatInvalid)?;
+                deblocked
+                    .as_luma_mut()
+                    .copy_from_slice(next_decoded_picture.as_luma());
+                deblocked
+                    .as_chroma_b_mut()
+                    .copy_from_slice(next_decoded_picture.as_chroma_b());
+                deblocked
+                    .as_chroma_r_mut()
+                    .copy_from_slice(next_decoded_picture.as_chroma_r());
+
+                let (luma, chroma_b, chroma_r) = deblocked.as_yuv_mut();
+                deblock_frame(
+                    luma,
+                    chroma_b,
+                    chroma_r,
+                    luma_samples_per_row,
+                    &macroblock_quantizers,
+                    &macroblock_coded,
+                );
+
+                self.deblocked_picture = Some(deblocked);
+            }
+
+            //The B-picture of a PB frame is predicted from both the previous
+            //reference picture and the P-picture, and is output before the
+            //P-picture.
+            self.b_picture = None;
+            if let Some((trb, _, _)) = pb_frame {
+                let forward_picture = reference_picture.ok_or(Error::UncodedIFrameBlocks)?;
+
+                let mut b_header = next_decoded_picture.as_header().clone();
+                b_header.picture_type = PictureTypeCode::BFrame;
+                b_header.temporal_reference = (forward_picture.as_header().temporal_reference
+                    + trb as u16)
+                    % b_header.temporal_reference_modulus();
+
+                let mut b_picture =
+                    DecodedPicture::new_in(b_header, format, self.picture_pool.as_ref())
+                        .ok_or(Error::PictureFormatInvalid)?;
+                gather_bidirectional(
+                    forward_picture,
+                    &next_decoded_picture,
+                    &b_vectors,
+                    mb_per_line,
+                    has_neutral_chroma,
+                    &mut b_picture,
+                );
+
+                if has_neutral_chroma {
+                    b_picture.as_chroma_b_mut().fill(128);
+                    b_picture.as_chroma_r_mut().fill(128);
+                    b_chroma_b_masks.fill(0);
+                    b_chroma_r_masks.fill(0);
+                }
+
+                idct_channel(
+                    &b_luma_levels,
+                    &b_luma_masks,
+                    b_picture.as_luma_mut(),
+                    mb_per_line * 2,
+                    luma_samples_per_row,
+                );
+                idct_channel(
+                    &b_chroma_b_levels,
+                    &b_chroma_b_masks,
+                    b_picture.as_chroma_b_mut(),
+                    mb_per_line,
+                    chroma_samples_per_row,
+                );
+                idct_channel(
+                    &b_chroma_r_levels,
+                    &b_chroma_r_masks,
+                    b_picture.as_chroma_r_mut(),
+                    mb_per_line,
+                    chroma_samples_per_row,
+                );
+
+                self.b_picture = Some(b_picture);
+                self.is_p_picture_held = true;
+            }
 
             //At this point, all decoding should be complete, and we should
             //have a fresh picture to put into the reference pile. We treat YUV
@@ -467,6 +1598,24 @@ impl H263State {
             ) {
                 //You cannot backwards predict across iframes
                 self.reference_picture = None;
+
+                //Nothing retained can predict a picture of a different size,
+                //so a resolution switch drops every reference at once.
+                let last_dimensions = self
+                    .get_last_picture()
+                    .and_then(|last| last.format().into_width_and_height());
+                if let Some(last_dimensions) = last_dimensions {
+                    if last_dimensions != output_dimensions {
+                        self.last_picture = None;
+                        self.reference_states.clear();
+                        self.reference_history.clear();
+                        self.evicted_references.clear();
+                        warnings.push(Warning::DimensionsChanged {
+                            from: last_dimensions,
+                            to: output_dimensions,
+                        });
+                    }
+                }
             }
 
             let this_tr = next_decoded_picture.as_header().temporal_reference;
@@ -477,14 +1626,1579 @@ impl H263State {
                 .is_disposable()
             {
                 self.reference_picture = Some(this_tr);
+                self.reference_history.retain(|tr| *tr != this_tr);
+                self.reference_history.push(this_tr);
+                self.evicted_references.remove(&this_tr);
             }
 
             self.reference_states.insert(this_tr, next_decoded_picture);
+            self.initial_quantizer = None;
+            self.last_warnings = warnings;
+            self.last_motion_vectors = predictor_vectors;
+            self.last_quant_map = macroblock_quantizers;
+            self.running_options = next_running_options;
             self.cleanup_buffers();
 
             reader.commit();
 
-            Ok(())
+            Ok(Some(this_tr))
         })
     }
+
+    /// Finish the reconstruction of one GOB of `picture`, covering the given
+    /// luma and chroma rows.
+    ///
+    /// The rows are replaced with those of the reference picture if the GOB
+    /// is masked out by `set_output_gob_mask`, and then reported to `on_gob`.
+    fn finish_gob(
+        &self,
+        reference_picture: Option<&DecodedPicture>,
+        picture: &mut DecodedPicture,
+        gob_index: usize,
+        (luma_rows, chroma_rows): (Range<usize>, Range<usize>),
+        on_gob: &mut Option<GobCallback<'_>>,
+    ) {
+        let masked_reference = reference_picture.filter(|reference| {
+            self.output_gob_mask.get(gob_index) == Some(&false)
+                && reference.format() == picture.format()
+        });
+        if let Some(reference) = masked_reference {
+            let luma_samples_per_row = picture.luma_samples_per_row();
+            let chroma_samples_per_row = picture.chroma_samples_per_row();
+            let luma_samples =
+                luma_rows.start * luma_samples_per_row..luma_rows.end * luma_samples_per_row;
+            let chroma_samples = chroma_rows.start * chroma_samples_per_row
+                ..chroma_rows.end * chroma_samples_per_row;
+
+            picture.as_luma_mut()[luma_samples.clone()]
+                .copy_from_slice(&reference.as_luma()[luma_samples]);
+            picture.as_chroma_b_mut()[chroma_samples.clone()]
+                .copy_from_slice(&reference.as_chroma_b()[chroma_samples.clone()]);
+            picture.as_chroma_r_mut()[chroma_samples.clone()]
+                .copy_from_slice(&reference.as_chroma_r()[chroma_samples]);
+        }
+
+        if let Some(on_gob) = on_gob.as_mut() {
+            on_gob(gob_index, luma_rows, picture);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::decoder::picture::tests::blank_picture;
+    use crate::decoder::{
+        deblock_frame, DecodedFrame, DecoderBuilder, DecoderOption, H263State, PicturePool,
+        QuantizedBlock,
+    };
+    use crate::error::{Error, Warning};
+    use crate::parser::H263Reader;
+    #[cfg(feature = "trace")]
+    use crate::parser::TraceEvent;
+    use crate::types::{HalfPel, MotionVector, PictureTypeCode, SourceFormat};
+    use h263_rs_yuv::bt601::yuv420_to_rgba;
+    use std::sync::{Arc, Mutex};
+
+    /// Bit-level writer used to assemble test bitstreams.
+    #[derive(Default)]
+    pub struct BitWriter {
+        data: Vec<u8>,
+        bits_written: usize,
+    }
+
+    impl BitWriter {
+        /// Append the lowest `bits` bits of `value`, most significant first.
+        pub fn write(&mut self, value: u32, bits: u32) {
+            for bit in (0..bits).rev() {
+                if self.bits_written.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+
+                if (value >> bit) & 1 == 1 {
+                    *self.data.last_mut().unwrap() |= 0x80 >> (self.bits_written % 8);
+                }
+
+                self.bits_written += 1;
+            }
+        }
+
+        /// Pad with zero bits up to the next byte boundary.
+        pub fn align(&mut self) {
+            self.bits_written = self.data.len() * 8;
+        }
+
+        pub fn into_bytes(self) -> Vec<u8> {
+            self.data
+        }
+    }
+
+    /// Write a byte-aligned Sorenson picture header for a Sub-QCIF picture.
+    ///
+    /// `picture_type` is the 2-bit Sorenson picture type (0 = I, 1 = P,
+    /// 2 = disposable P). `extra` is written as `PSUPP` bytes.
+    pub fn write_sorenson_header(
+        w: &mut BitWriter,
+        temporal_reference: u8,
+        picture_type: u32,
+        extra: &[u8],
+    ) {
+        w.align();
+        w.write(1, 17); // PSC
+        w.write(0, 5); // version
+        w.write(temporal_reference as u32, 8);
+        w.write(4, 3); // Sub-QCIF
+        w.write(picture_type, 2);
+        w.write(0, 1); // deblocking flag
+        w.write(1, 5); // quantizer
+        for &byte in extra {
+            w.write(1, 1); // PEI
+            w.write(byte as u32, 8);
+        }
+        w.write(0, 1); // PEI
+    }
+
+    /// Write a Sub-QCIF I-frame where every block has the given `INTRADC`
+    /// code and no other coefficients.
+    pub fn write_flat_iframe(w: &mut BitWriter, temporal_reference: u8, intradc: u8) {
+        write_sorenson_header(w, temporal_reference, 0, &[]);
+        write_flat_macroblocks(w, intradc);
+    }
+
+    /// Write the macroblocks of a Sub-QCIF I-frame where every block has the
+    /// given `INTRADC` code and no other coefficients.
+    pub fn write_flat_macroblocks(w: &mut BitWriter, intradc: u8) {
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(intradc as u32, 8);
+            }
+        }
+    }
+
+    /// Write a Sub-QCIF P-frame where every macroblock is uncoded.
+    pub fn write_uncoded_pframe(w: &mut BitWriter, temporal_reference: u8) {
+        write_sorenson_header(w, temporal_reference, 1, &[]);
+
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+    }
+
+    #[test]
+    fn stop_on_error_ends_stream() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xFF);
+        write_uncoded_pframe(&mut w, 1);
+        write_flat_iframe(&mut w, 2, 0x00); // INTRADC of zero is forbidden
+        let data = w.into_bytes();
+
+        let decoder_options = DecoderOption::SORENSON_SPARK_BITSTREAM;
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(decoder_options);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.decode_next_picture(&mut reader).is_err());
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = DecoderBuilder::new()
+            .options(decoder_options)
+            .stop_on_error()
+            .build()
+            .unwrap();
+        let mut decoded = 0;
+        while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+            assert!(picture.as_luma().iter().all(|&l| l == 128));
+            decoded += 1;
+        }
+
+        assert_eq!(2, decoded);
+        assert!(decoder.last_error().is_some());
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn select_older_reference() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_flat_iframe(&mut w, 1, 0xC0);
+        write_uncoded_pframe(&mut w, 2);
+        let data = w.into_bytes();
+
+        let decode_with = |max_references, reference: Option<u16>| {
+            let mut reader = H263Reader::from_source(&data[..]);
+            let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+            decoder.set_max_references(max_references);
+            decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+            decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+            let selected = reference.map(|tr| decoder.select_reference_picture(tr));
+            let expected = reference
+                .and_then(|tr| decoder.get_picture(tr))
+                .map(|p| p.as_luma().to_vec());
+            let luma = decoder
+                .decode_next_picture(&mut reader)
+                .unwrap()
+                .unwrap()
+                .as_luma()
+                .to_vec();
+
+            (selected, expected, luma)
+        };
+
+        // Predicting from N-1 yields the second picture.
+        let (_, _, luma) = decode_with(2, None);
+        assert!(luma.iter().all(|&l| l == 0xC0));
+
+        // Predicting from N-2 yields the first picture.
+        let (selected, expected, luma) = decode_with(2, Some(0));
+        assert!(selected.unwrap().is_ok());
+        assert_eq!(Some(luma.clone()), expected);
+        assert!(luma.iter().all(|&l| l == 0x40));
+
+        // N-2 is not retained by default.
+        let (selected, _, luma) = decode_with(1, Some(0));
+        assert!(matches!(
+            selected,
+            Some(Err(crate::Error::ReferenceEvicted))
+        ));
+        assert!(luma.iter().all(|&l| l == 0xC0));
+    }
+
+    #[test]
+    fn evict_least_recently_used_reference() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_flat_iframe(&mut w, 1, 0x60);
+        write_flat_iframe(&mut w, 2, 0xC0);
+        write_uncoded_pframe(&mut w, 3);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_max_references(2);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        // The oldest of the three pictures has been evicted.
+        assert!(matches!(
+            decoder.select_reference_picture(0),
+            Err(crate::Error::ReferenceEvicted)
+        ));
+        assert!(matches!(
+            decoder.select_reference_picture(7),
+            Err(crate::Error::ReferencePictureMissing)
+        ));
+        decoder.select_reference_picture(1).unwrap();
+
+        // Selecting picture 1 made picture 2 the least recently used.
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.get_picture(2).is_none());
+        assert!(matches!(
+            decoder.select_reference_picture(2),
+            Err(crate::Error::ReferenceEvicted)
+        ));
+        decoder.select_reference_picture(1).unwrap();
+    }
+
+    #[test]
+    fn forced_intra_macroblock() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+
+        // The sixth macroblock codes a DC residual of 33 / 8 in its luma.
+        write_sorenson_header(&mut w, 1, 1, &[]);
+        for mb in 0..(8 * 6) {
+            if mb == 5 {
+                w.write(0b0, 1); // COD
+                w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+                w.write(0b0011, 4); // CBPY: all luma coefficients
+                w.write(0b1, 1); // MVD: 0
+                w.write(0b1, 1); // MVD: 0
+                for _ in 0..4 {
+                    w.write(0b0000011, 7); // TCOEF: ESCAPE
+                    w.write(0b1, 1); // LAST
+                    w.write(0, 6); // RUN
+                    w.write(16, 8); // LEVEL
+                }
+            } else {
+                w.write(0b1, 1); // COD
+            }
+        }
+
+        write_uncoded_pframe(&mut w, 2);
+        let data = w.into_bytes();
+
+        let is_in_mb5 = |i: usize| (80..96).contains(&(i % 128)) && i / 128 < 16;
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut mask = vec![false; 8 * 6];
+        mask[5] = true;
+        decoder.set_forced_intra_macroblocks(mask);
+
+        // The I-frame is unaffected.
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+
+        // The residual is added to gray, instead of to the prediction.
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        for (i, &l) in picture.as_luma().iter().enumerate() {
+            assert_eq!(if is_in_mb5(i) { 128 + 4 } else { 0x40 }, l);
+        }
+        assert!(picture.as_chroma_b()[40..48].iter().all(|&c| c == 128));
+
+        // The gray block is predicted from after the mask is lifted.
+        decoder.set_forced_intra_macroblocks(Vec::new());
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        for (i, &l) in picture.as_luma().iter().enumerate() {
+            assert_eq!(if is_in_mb5(i) { 128 + 4 } else { 0x40 }, l);
+        }
+    }
+
+    #[test]
+    fn progressive_gob_callback() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut gobs = Vec::new();
+        decoder
+            .decode_next_picture_progressive(&mut reader, |gob_index, rows, picture| {
+                let luma = picture.as_luma();
+
+                // Reported rows are complete, and later ones are not started.
+                assert!(luma[rows.start * 128..rows.end * 128]
+                    .iter()
+                    .all(|&l| l == 0x40));
+                assert!(luma[rows.end * 128..].iter().all(|&l| l == 0));
+
+                gobs.push((gob_index, rows));
+            })
+            .unwrap()
+            .unwrap();
+
+        // Sub-QCIF has six GOBs, one macroblock row each.
+        let expected: Vec<_> = (0..6).map(|i| (i, i * 16..(i + 1) * 16)).collect();
+        assert_eq!(expected, gobs);
+    }
+
+    #[test]
+    fn partial_last_gob() {
+        // A 16x424 picture has two macroblock rows per GOB, and 26.5 rows of
+        // macroblocks, so the last GOB only covers half a macroblock row.
+        let mut w = BitWriter::default();
+        w.write(1, 17); // PSC
+        w.write(0, 5); // version
+        w.write(0, 8); // temporal reference
+        w.write(1, 3); // custom size, 16-bit dimensions
+        w.write(16, 16);
+        w.write(424, 16);
+        w.write(0, 2); // I-frame
+        w.write(0, 1); // deblocking flag
+        w.write(1, 5); // quantizer
+        w.write(0, 1); // PEI
+        for _ in 0..27 {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(0x40, 8);
+            }
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut gobs = Vec::new();
+        let picture = decoder
+            .decode_next_picture_progressive(&mut reader, |gob_index, rows, _| {
+                gobs.push((gob_index, rows));
+            })
+            .unwrap()
+            .unwrap();
+
+        let mut expected: Vec<_> = (0..13).map(|i| (i, i * 32..(i + 1) * 32)).collect();
+        expected.push((13, 416..424));
+        assert_eq!(expected, gobs);
+
+        assert_eq!(16 * 424, picture.as_luma().len());
+        assert_eq!(8 * 212, picture.as_chroma_b().len());
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+        assert!(picture.as_chroma_r().iter().all(|&c| c == 0x40));
+    }
+
+    #[test]
+    fn output_every_other_frame() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_flat_iframe(&mut w, 1, 0xC0);
+        write_uncoded_pframe(&mut w, 2);
+        write_uncoded_pframe(&mut w, 3);
+        write_uncoded_pframe(&mut w, 4);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_output_interval(2);
+
+        let mut outputs = Vec::new();
+        while let Some(frame) = decoder.decode_next_frame(&mut reader).unwrap() {
+            outputs.push(match frame {
+                DecodedFrame::Picture(picture) => {
+                    Some((picture.as_header().temporal_reference, picture.as_luma()[0]))
+                }
+                DecodedFrame::Skipped { temporal_reference } => {
+                    assert_eq!(1, temporal_reference % 2);
+                    None
+                }
+            });
+        }
+
+        // Picture 2 is predicted from skipped picture 1.
+        assert_eq!(
+            vec![
+                Some((0, 0x40)),
+                None,
+                Some((2, 0xC0)),
+                None,
+                Some((4, 0xC0))
+            ],
+            outputs
+        );
+    }
+
+    #[test]
+    fn end_of_bitstream() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xFF);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+        assert!(decoder.last_error().is_none());
+        assert_eq!(Some(0), decoder.sorenson_version());
+    }
+
+    #[test]
+    fn coefficient_hook_reports_quantized_levels() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+        w.write(0b00010, 5); // CBPY: only luma block 0 coded
+        w.write(0x40, 8); // INTRADC
+        w.write(0b001110, 6); // TCOEF: LAST = 1, RUN = 2, |LEVEL| = 1
+        w.write(1, 1); // sign: negative
+        for _ in 0..5 {
+            w.write(0x40, 8);
+        }
+        for _ in 1..(8 * 6) {
+            w.write(0b1, 1);
+            w.write(0b0011, 4);
+            for _ in 0..6 {
+                w.write(0x40, 8);
+            }
+        }
+        let data = w.into_bytes();
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let hook_reported = reported.clone();
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_coefficient_hook(Some(Box::new(move |block: &QuantizedBlock| {
+            hook_reported.lock().unwrap().push(*block);
+        })));
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(8 * 6 * 6, reported.len());
+
+        // The DC is the first coefficient; the run skips two more in zigzag
+        // order, landing on the third row of the first column.
+        let mut expected = [0; 64];
+        expected[0] = 0x40;
+        expected[2 * 8] = -1;
+        assert_eq!(
+            QuantizedBlock {
+                macroblock: 0,
+                block: 0,
+                levels: expected,
+            },
+            reported[0]
+        );
+
+        expected[2 * 8] = 0;
+        for block in &reported[1..] {
+            assert_eq!(expected, block.levels);
+        }
+        assert_eq!(1, reported[1].block);
+        assert_eq!((1, 0), (reported[6].macroblock, reported[6].block));
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace_syntax_elements() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 3, 0x40);
+        let data = w.into_bytes();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let tracer_events = events.clone();
+        let mut reader = H263Reader::from_source(&data[..]);
+        reader.set_tracer(Some(Box::new(move |event: &TraceEvent| {
+            tracer_events.lock().unwrap().push(*event);
+        })));
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+
+        let events = events.lock().unwrap();
+        let names: Vec<_> = events.iter().map(|event| event.name).collect();
+        assert_eq!(
+            &["PSC", "VERSION", "TR", "PTYPE", "PQUANT", "PEI"],
+            &names[..6]
+        );
+        assert_eq!(6 + 8 * 6 * 8, names.len());
+        for macroblock in names[6..].chunks(8) {
+            assert_eq!(
+                &[
+                    "MCBPC", "CBPY", "INTRADC", "INTRADC", "INTRADC", "INTRADC", "INTRADC",
+                    "INTRADC"
+                ],
+                macroblock
+            );
+        }
+
+        assert_eq!(
+            TraceEvent {
+                name: "TR",
+                bit_offset: 22,
+                bits: 8,
+                value: 3,
+            },
+            events[2]
+        );
+    }
+
+    #[test]
+    fn seeded_quantizer_and_reference() {
+        // An I-frame whose first block has a single AC coefficient, and so
+        // depends on the quantizer.
+        let iframe = |quantizer: u32| {
+            let mut w = BitWriter::default();
+            w.write(1, 17); // PSC
+            w.write(0, 5); // version
+            w.write(0, 8); // temporal reference
+            w.write(4, 3); // Sub-QCIF
+            w.write(0, 2); // I-frame
+            w.write(0, 1); // deblocking flag
+            w.write(quantizer, 5);
+            w.write(0, 1); // PEI
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b00010, 5); // CBPY: only luma block 0 coded
+            w.write(0x40, 8); // INTRADC
+            w.write(0b0111, 4); // TCOEF: LAST = 1, RUN = 0, |LEVEL| = 1
+            w.write(0, 1); // sign: positive
+            for _ in 0..5 {
+                w.write(0x40, 8);
+            }
+            for _ in 1..(8 * 6) {
+                w.write(0b1, 1);
+                w.write(0b0011, 4);
+                for _ in 0..6 {
+                    w.write(0x40, 8);
+                }
+            }
+
+            w.into_bytes()
+        };
+        let decode = |data: &[u8], quantizer: Option<u8>| {
+            let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+            decoder.set_initial_quantizer(quantizer);
+            let mut reader = H263Reader::from_source(data);
+            let luma = decoder
+                .decode_next_picture(&mut reader)
+                .unwrap()
+                .unwrap()
+                .as_luma()
+                .to_vec();
+
+            (decoder, luma)
+        };
+
+        let (_, expected) = decode(&iframe(8), None);
+        let (_, unseeded) = decode(&iframe(1), None);
+        let (mut decoder, seeded) = decode(&iframe(1), Some(8));
+        assert_ne!(expected, unseeded);
+        assert_eq!(expected, seeded);
+
+        // The seed only applies to one picture.
+        let data = iframe(1);
+        let mut reader = H263Reader::from_source(&data[..]);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(unseeded, picture.as_luma());
+
+        // A predicted picture can be decoded from a synthesized reference.
+        let mut reference = blank_picture(SourceFormat::SubQcif);
+        reference.as_luma_mut().iter_mut().for_each(|l| *l = 0x60);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.insert_reference_picture(reference);
+
+        let mut w = BitWriter::default();
+        write_uncoded_pframe(&mut w, 1);
+        let data = w.into_bytes();
+        let mut reader = H263Reader::from_source(&data[..]);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(picture.as_luma().iter().all(|&l| l == 0x60));
+    }
+
+    #[test]
+    fn leading_junk() {
+        let mut w = BitWriter::default();
+        for byte in [0xFF, 0x12, 0x34, 0x40, 0xA5, 0x03, 0x7E] {
+            w.write(byte, 8);
+        }
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_uncoded_pframe(&mut w, 1);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        for tr in 0..2 {
+            let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+            assert_eq!(tr, picture.as_header().temporal_reference);
+            assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+        }
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_max_leading_junk(6);
+        assert!(matches!(
+            decoder.decode_next_picture(&mut reader),
+            Err(Error::InvalidStartCode)
+        ));
+    }
+
+    #[test]
+    fn discard_matches_full_decode() {
+        let mut w = BitWriter::default();
+        for tr in 0..20u8 {
+            if tr % 5 == 0 {
+                write_flat_iframe(&mut w, tr, 0x40);
+            } else {
+                write_uncoded_pframe(&mut w, tr);
+            }
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut expected = Vec::new();
+        while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+            expected.push(picture.meta());
+        }
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let mut actual = Vec::new();
+        while let Some(meta) = decoder.decode_next_discard(&mut reader).unwrap() {
+            actual.push(meta);
+        }
+
+        assert_eq!(20, expected.len());
+        assert_eq!(expected, actual);
+        assert!(decoder.discard_pool.allocations() <= 6);
+    }
+
+    #[test]
+    fn monochrome_macroblocks() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..4 {
+                w.write(0x40, 8);
+            }
+        }
+        write_flat_iframe(&mut w, 1, 0xC0);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder =
+            H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM | DecoderOption::MONOCHROME);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+        assert!(picture.as_chroma_b().iter().all(|&c| c == 128));
+        assert!(picture.as_chroma_r().iter().all(|&c| c == 128));
+
+        // The next picture starts where the luma-only macroblocks ended.
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(1, picture.as_header().temporal_reference);
+    }
+
+    #[test]
+    fn output_gob_mask() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_flat_iframe(&mut w, 1, 0xC0);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_output_gob_mask(vec![true, false, true, false, true, false]);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        // Sub-QCIF GOBs are one macroblock row each.
+        for (row, luma) in picture.as_luma().chunks(128).enumerate() {
+            let expected = if (row / 16) % 2 == 1 { 0x40 } else { 0xC0 };
+            assert!(luma.iter().all(|&l| l == expected), "row {}", row);
+        }
+        for (row, chroma) in picture.as_chroma_b().chunks(64).enumerate() {
+            let expected = if (row / 8) % 2 == 1 { 0x40 } else { 0xC0 };
+            assert!(chroma.iter().all(|&c| c == expected), "row {}", row);
+        }
+    }
+
+    #[test]
+    fn deblock_output_only() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        for mb in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(if mb % 8 < 4 { 0x40 } else { 0x44 }, 8);
+            }
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!([0x40, 0x40, 0x44, 0x44], picture.as_luma()[62..66]);
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder =
+            H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM | DecoderOption::DEBLOCK);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        for row in picture.as_luma().chunks(128) {
+            assert_eq!([0x40, 0x41, 0x43, 0x44], row[62..66]);
+        }
+        for row in picture.as_chroma_b().chunks(64) {
+            assert_eq!([0x40, 0x41, 0x43, 0x44], row[30..34]);
+        }
+
+        // The picture retained for prediction is not filtered.
+        let reference = decoder.get_last_picture().unwrap();
+        assert_eq!([0x40, 0x40, 0x44, 0x44], reference.as_luma()[62..66]);
+    }
+
+    #[test]
+    fn decode_convert_deblock_round_trip() {
+        // CI also builds this for `wasm32-unknown-unknown`, where the filter
+        // and the color conversion take their scalar paths.
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        for mb in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(if mb % 8 < 4 { 0x40 } else { 0x44 }, 8);
+            }
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let (mut luma, mut chroma_b, mut chroma_r) = (
+            picture.as_luma().to_vec(),
+            picture.as_chroma_b().to_vec(),
+            picture.as_chroma_r().to_vec(),
+        );
+        let unfiltered = yuv420_to_rgba(&luma, &chroma_b, &chroma_r, 128, 64);
+
+        crate::deblock::deblock_frame(
+            &mut luma,
+            &mut chroma_b,
+            &mut chroma_r,
+            128,
+            &[1; 48],
+            &[true; 48],
+        );
+        let filtered = yuv420_to_rgba(&luma, &chroma_b, &chroma_r, 128, 64);
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder =
+            H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM | DecoderOption::DEBLOCK);
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(luma, picture.as_luma());
+        assert_eq!(
+            filtered,
+            yuv420_to_rgba(
+                picture.as_luma(),
+                picture.as_chroma_b(),
+                picture.as_chroma_r(),
+                128,
+                64,
+            )
+        );
+
+        assert_eq!(128 * 96 * 4, filtered.len());
+        assert_ne!(unfiltered, filtered);
+        assert!(filtered.chunks(4).all(|pixel| pixel[3] == 255));
+        for (filtered, unfiltered) in filtered.chunks(128 * 4).zip(unfiltered.chunks(128 * 4)) {
+            assert_eq!(unfiltered[..61 * 4], filtered[..61 * 4]);
+            assert_ne!(unfiltered[62 * 4..66 * 4], filtered[62 * 4..66 * 4]);
+        }
+    }
+
+    /// Write a Sub-QCIF picture header with a `PLUSPTYPE`, and `PQUANT` 8.
+    ///
+    /// Only I-frames restate the optional modes, and enable Annex J if
+    /// `is_deblocked`; P-frames carry the mode over.
+    fn write_plusptype_header(w: &mut BitWriter, temporal_reference: u8, is_deblocked: bool) {
+        w.align();
+        w.write(0x20, 22); // PSC, GN 0
+        w.write(temporal_reference as u32, 8);
+        w.write(0b1000_0111, 8); // PTYPE: extended
+        if temporal_reference == 0 {
+            w.write(1, 3); // UFEP
+            w.write(1, 3); // OPPTYPE: Sub-QCIF
+            w.write(0b0_0000, 5); // OPPTYPE: no CPCF, Annexes D, E, F or I
+            w.write(is_deblocked as u32, 1); // OPPTYPE: Annex J
+            w.write(0b0_0000, 5); // OPPTYPE: no Annexes K, N, R, S or T
+            w.write(0b1000, 4); // OPPTYPE
+            w.write(0b000_000_001, 9); // MPPTYPE: I-frame
+        } else {
+            w.write(0, 3); // UFEP
+            w.write(0b001_000_001, 9); // MPPTYPE: P-frame
+        }
+        w.write(0, 1); // CPM
+        w.write(8, 5); // PQUANT
+        w.write(0, 1); // PEI
+    }
+
+    #[test]
+    fn deblock_in_loop() {
+        let stream = |is_deblocked: bool| {
+            let mut w = BitWriter::default();
+            write_plusptype_header(&mut w, 0, is_deblocked);
+            for mb in 0..(8 * 6) {
+                w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+                w.write(0b0011, 4); // CBPY: no luma coefficients
+                for block in 0..6 {
+                    w.write(0x40 + 8 * ((mb + block) % 3), 8); // INTRADC
+                }
+            }
+
+            // Only the tenth macroblock is coded, without any residual, so
+            // it is filtered against its neighbours.
+            write_plusptype_header(&mut w, 1, is_deblocked);
+            for mb in 0..(8 * 6) {
+                if mb == 9 {
+                    w.write(0b0, 1); // COD
+                    w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+                    w.write(0b11, 2); // CBPY: no luma coefficients
+                    w.write(0b1, 1); // MVD: 0
+                    w.write(0b1, 1); // MVD: 0
+                } else {
+                    w.write(0b1, 1); // COD
+                }
+            }
+
+            write_plusptype_header(&mut w, 2, is_deblocked);
+            for _ in 0..(8 * 6) {
+                w.write(0b1, 1); // COD
+            }
+
+            w.into_bytes()
+        };
+        let decode = |data: &[u8], decoder_options: DecoderOption| {
+            let mut reader = H263Reader::from_source(data);
+            let mut decoder = H263State::new(decoder_options);
+            let mut pictures = Vec::new();
+            while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+                let (y, b, r) = picture.as_yuv();
+                pictures.push((y.to_vec(), b.to_vec(), r.to_vec()));
+            }
+
+            pictures
+        };
+
+        // The reference decoder filters the unfiltered I-frame, then
+        // predicts each P-frame from the filtered picture before it.
+        let unfiltered = decode(&stream(false), DecoderOption::empty());
+        let mut expected = Vec::new();
+        let mut picture = unfiltered[0].clone();
+        let mut p_frame_coded = [false; 48];
+        p_frame_coded[9] = true;
+        for coded in [[true; 48], p_frame_coded, [false; 48]] {
+            let (y, b, r) = &mut picture;
+            deblock_frame(y, b, r, 128, &[8; 48], &coded);
+            expected.push(picture.clone());
+        }
+        assert_ne!(unfiltered[0], expected[0]);
+        assert_ne!(expected[0], expected[1]);
+        assert_eq!(expected[1], expected[2]);
+
+        let filtered = decode(&stream(true), DecoderOption::empty());
+        assert_eq!(expected, filtered);
+
+        // The first row of the first macroblock steps from 64 to 72 between
+        // its luma blocks. At a strength of 4 for QUANT 8, d = 24 / 8 = 3 is
+        // ramped to d1 = 3 for B and C, and d2 = -8 / 4 = -2, clipped to the
+        // range of d1 / 2, is -1 for A and D.
+        assert_eq!(&[64, 64, 72, 72], &unfiltered[0].0[6..10]);
+        assert_eq!(&[65, 67, 69, 71], &filtered[0].0[6..10]);
+
+        // Masked GOBs are copied from the reference after filtering, so the
+        // edges of the coded macroblock within them are left as they were.
+        let gob_1 = 16 * 128..32 * 128;
+        assert_ne!(&filtered[0].0[gob_1.clone()], &filtered[1].0[gob_1.clone()]);
+
+        let data = stream(true);
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        decoder.set_output_gob_mask(vec![true, false]);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            &filtered[0].0[gob_1.clone()],
+            &picture.as_luma()[gob_1.clone()]
+        );
+        assert_eq!(
+            &filtered[1].0[..gob_1.start],
+            &picture.as_luma()[..gob_1.start]
+        );
+        assert_eq!(&filtered[1].0[gob_1.end..], &picture.as_luma()[gob_1.end..]);
+
+        // Pictures that were filtered in the loop are not filtered again.
+        assert_eq!(filtered, decode(&stream(true), DecoderOption::DEBLOCK));
+    }
+
+    #[test]
+    fn last_motion_vectors() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_sorenson_header(&mut w, 1, 1, &[]);
+        w.write(0b0, 1); // COD
+        w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+        w.write(0b11, 2); // CBPY: no luma coefficients
+        w.write(0b0010, 4); // MVD: +1
+        w.write(0b011, 3); // MVD: -0.5
+        w.write(0b0, 1); // COD
+        w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+        w.write(0b11, 2); // CBPY: no luma coefficients
+        w.write(0b1, 1); // MVD: 0
+        w.write(0b1, 1); // MVD: 0
+        for _ in 2..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let units = |mv: MotionVector| {
+            let (x, y): (HalfPel, HalfPel) = mv.into();
+            (x.into_unit(), y.into_unit())
+        };
+        assert!(decoder
+            .last_motion_vectors()
+            .iter()
+            .all(|mvs| mvs.iter().all(|&mv| units(mv) == (0, 0))));
+
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let mvs = decoder.last_motion_vectors();
+        assert_eq!(48, mvs.len());
+        assert_eq!([(2, -1); 4], mvs[0].map(units));
+        // The second vector is predicted from the first.
+        assert_eq!([(2, -1); 4], mvs[1].map(units));
+        assert_eq!([(0, 0); 4], mvs[2].map(units));
+    }
+
+    #[test]
+    fn last_quant_map() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        for mb in 0..(8 * 6) {
+            if mb == 16 {
+                w.align();
+                w.write(1, 17); // GBSC
+                w.write(2, 5); // GN
+                w.write(0, 2); // GFID
+                w.write(7, 5); // GQUANT
+            }
+
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(0x40, 8); // INTRADC
+            }
+        }
+        write_standard_header(&mut w, 1, 0b10000);
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        assert!(decoder.last_quant_map().is_empty());
+
+        // `PQUANT` holds for the first two GOBs, until the third changes it.
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let quant_map = decoder.last_quant_map();
+        assert_eq!(48, quant_map.len());
+        assert!(quant_map[..16].iter().all(|&quantizer| quantizer == 1));
+        assert!(quant_map[16..].iter().all(|&quantizer| quantizer == 7));
+
+        // Each picture starts over from its own `PQUANT`.
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(&[1; 48][..], decoder.last_quant_map());
+    }
+
+    #[test]
+    fn reset_starts_fresh_sequence() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_uncoded_pframe(&mut w, 1);
+        let first = w.into_bytes();
+
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+        write_uncoded_pframe(&mut w, 1);
+        let second = w.into_bytes();
+
+        let pool = PicturePool::new();
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_picture_pool(Some(pool.clone()));
+        let mut reader = H263Reader::from_source(&first[..]);
+        while decoder.decode_next_picture(&mut reader).unwrap().is_some() {}
+
+        decoder.reset();
+        assert!(decoder.get_last_picture().is_none());
+        assert!(decoder.get_reference_picture().is_none());
+        assert!(decoder.get_picture(1).is_none());
+        assert!(decoder.last_motion_vectors().is_empty());
+        assert!(decoder.last_quant_map().is_empty());
+        assert!(pool.available() > 0);
+
+        let mut reader = H263Reader::from_source(&second[..]);
+        for temporal_reference in 0..2 {
+            let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+            assert_eq!(temporal_reference, picture.as_header().temporal_reference);
+            assert!(picture.as_luma().iter().all(|&l| l == 0xC0));
+        }
+        assert!(decoder.get_picture(0).is_none());
+    }
+
+    /// Write a byte-aligned baseline H.263 picture header for a Sub-QCIF
+    /// picture, with the given `PTYPE` bits 9 through 13.
+    pub fn write_standard_header(w: &mut BitWriter, temporal_reference: u8, low_ptype: u32) {
+        w.align();
+        w.write(0x20, 22); // PSC, GN 0
+        w.write(temporal_reference as u32, 8);
+        w.write(0b1000_0001, 8); // PTYPE: Sub-QCIF
+        w.write(low_ptype, 5);
+        w.write(1, 5); // PQUANT
+        w.write(0, 1); // CPM
+        w.write(0, 1); // PEI
+    }
+
+    #[test]
+    fn unrestricted_motion_vectors() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        for mb in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for block in 0..6 {
+                let intradc = match (mb, block) {
+                    (0, 0) | (0, 2) => 0x20,
+                    (0, 1) | (0, 3) => 0x60,
+                    _ => 0xC0,
+                };
+                w.write(intradc, 8);
+            }
+        }
+
+        write_standard_header(&mut w, 1, 0b11000); // INTER, Annex D
+        w.write(0b0, 1); // COD
+        w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+        w.write(0b11, 2); // CBPY: no luma coefficients
+        w.write(0b00000011001, 11); // MVD: -8
+        w.write(0b1, 1); // MVD: 0
+        w.write(0b0, 1); // COD
+        w.write(0b1, 1); // MCBPC: INTER, no chroma coefficients
+        w.write(0b11, 2); // CBPY: no luma coefficients
+        w.write(0b00000001001, 11); // MVD: -12, on top of a predictor of -8
+        w.write(0b1, 1); // MVD: 0
+        for _ in 2..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(PictureTypeCode::IFrame, picture.as_header().picture_type);
+
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(PictureTypeCode::PFrame, picture.as_header().picture_type);
+        for row in picture.as_luma().chunks(128).take(16) {
+            // The left edge of the reference picture is extended outwards.
+            assert_eq!([0x20; 16], row[0..16]);
+            assert_eq!([0x20; 12], row[16..28]);
+            assert_eq!([0x60; 4], row[28..32]);
+        }
+
+        // -20 pixels is only reachable with the Annex D range.
+        let (x, _): (HalfPel, HalfPel) = decoder.last_motion_vectors()[1][0].into();
+        assert_eq!(-40, x.into_unit());
+        assert!(decoder.last_warnings().is_empty());
+    }
+
+    #[test]
+    fn predicted_picture_size_must_match_reference() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+
+        w.align();
+        w.write(1, 17); // PSC
+        w.write(0, 5); // version
+        w.write(1, 8); // TR
+        w.write(3, 3); // QCIF
+        w.write(1, 2); // P-frame
+        w.write(0, 1); // deblocking flag
+        w.write(1, 5); // quantizer
+        w.write(0, 1); // PEI
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(matches!(
+            decoder.decode_next_picture(&mut reader),
+            Err(Error::DimensionMismatch {
+                picture: (176, 144),
+                reference: (128, 96),
+            })
+        ));
+    }
+
+    #[test]
+    fn luma_only_matches_full_decode() {
+        let mut w = BitWriter::default();
+        write_sorenson_header(&mut w, 0, 0, &[]);
+        for macroblock in 0..(8 * 6) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for block in 0..4 {
+                w.write(0x30 + macroblock + block * 4, 8); // INTRADC
+            }
+            w.write(0x20, 8); // INTRADC
+            w.write(0xD0, 8); // INTRADC
+        }
+        write_uncoded_pframe(&mut w, 1);
+        let data = w.into_bytes();
+
+        let decode = |decoder_options| {
+            let mut reader = H263Reader::from_source(&data[..]);
+            let mut decoder = H263State::new(decoder_options);
+            let mut pictures = Vec::new();
+            while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+                let (luma, chroma_b, chroma_r) = picture.as_yuv();
+                pictures.push((luma.to_vec(), chroma_b.to_vec(), chroma_r.to_vec()));
+            }
+
+            pictures
+        };
+
+        let decoder_options = DecoderOption::SORENSON_SPARK_BITSTREAM;
+        let full = decode(decoder_options);
+        let luma_only = decode(decoder_options | DecoderOption::DECODE_LUMA_ONLY);
+        assert_eq!(2, luma_only.len());
+        for ((luma, chroma_b, _), (luma_only, chroma_b_only, chroma_r_only)) in
+            full.iter().zip(&luma_only)
+        {
+            assert_eq!(luma, luma_only);
+            assert!(chroma_b.iter().all(|&c| c == 0x20));
+            assert!(chroma_b_only.iter().chain(chroma_r_only).all(|&c| c == 128));
+        }
+    }
+
+    #[test]
+    fn decode_from_slice() {
+        let mut first = BitWriter::default();
+        write_flat_iframe(&mut first, 0, 0xC0);
+        first.align();
+        let mut first = first.into_bytes();
+        let first_length = first.len();
+
+        let mut second = BitWriter::default();
+        write_uncoded_pframe(&mut second, 1);
+        second.align();
+        let second = second.into_bytes();
+        let second_length = second.len();
+        first.extend(second);
+        first.extend([0x00, 0x00, 0xFC]); // EOS
+        let data = first;
+
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let (picture, consumed) = decoder.decode_from_slice(&data).unwrap();
+        assert_eq!(0, picture.unwrap().as_header().temporal_reference);
+        assert_eq!(first_length, consumed);
+
+        let rest = &data[consumed..];
+        let (picture, consumed) = decoder.decode_from_slice(rest).unwrap();
+        assert_eq!(1, picture.unwrap().as_header().temporal_reference);
+        assert_eq!(second_length, consumed);
+
+        // The end-of-sequence code is consumed too, leaving nothing.
+        let rest = &rest[consumed..];
+        let (picture, consumed) = decoder.decode_from_slice(rest).unwrap();
+        assert!(picture.is_none());
+        assert_eq!(3, consumed);
+
+        let (picture, consumed) = decoder.decode_from_slice(&[]).unwrap();
+        assert!(picture.is_none());
+        assert_eq!(0, consumed);
+    }
+
+    #[test]
+    fn reference_picture_follows_anchors() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+        write_uncoded_pframe(&mut w, 1);
+        write_sorenson_header(&mut w, 2, 2, &[]); // disposable P-frame
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let reference_tr = |decoder: &H263State| {
+            Some(
+                decoder
+                    .get_reference_picture()?
+                    .as_header()
+                    .temporal_reference,
+            )
+        };
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        assert_eq!(None, reference_tr(&decoder));
+
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(Some(0), reference_tr(&decoder));
+
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(Some(1), reference_tr(&decoder));
+
+        // Disposable pictures are never anchors.
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(Some(1), reference_tr(&decoder));
+    }
+
+    #[test]
+    fn reorder_output() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+        write_uncoded_pframe(&mut w, 2);
+        write_sorenson_header(&mut w, 1, 2, &[]); // disposable P-frame
+        for _ in 0..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        write_uncoded_pframe(&mut w, 3);
+        let data = w.into_bytes();
+
+        let display_order = |decoder_options| {
+            let mut reader = H263Reader::from_source(&data[..]);
+            let mut decoder = H263State::new(decoder_options);
+            let mut order = Vec::new();
+            while let Some(picture) = decoder.decode_next_picture(&mut reader).unwrap() {
+                order.push(picture.as_header().temporal_reference);
+            }
+
+            order
+        };
+
+        let decoder_options = DecoderOption::SORENSON_SPARK_BITSTREAM;
+        assert_eq!(vec![0, 2, 1, 3], display_order(decoder_options));
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            display_order(decoder_options | DecoderOption::REORDER_OUTPUT)
+        );
+    }
+
+    #[test]
+    fn end_of_sequence_is_reported() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+        w.align();
+        w.write(0x3F, 22); // EOS
+        write_flat_iframe(&mut w, 1, 0xC0);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_some());
+        assert!(!decoder.is_end_of_sequence());
+
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+        assert!(decoder.is_end_of_sequence());
+
+        // A new sequence may follow.
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_some());
+        assert!(!decoder.is_end_of_sequence());
+
+        // Running out of data is not an end-of-sequence code.
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+        assert!(!decoder.is_end_of_sequence());
+    }
+
+    #[test]
+    fn intra_picture_may_change_size() {
+        /// Write a Sorenson QCIF picture header.
+        fn write_qcif_header(w: &mut BitWriter, temporal_reference: u8, picture_type: u32) {
+            w.align();
+            w.write(1, 17); // PSC
+            w.write(0, 5); // version
+            w.write(temporal_reference as u32, 8);
+            w.write(3, 3); // QCIF
+            w.write(picture_type, 2);
+            w.write(0, 1); // deblocking flag
+            w.write(1, 5); // quantizer
+            w.write(0, 1); // PEI
+        }
+
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0xC0);
+
+        write_qcif_header(&mut w, 1, 0);
+        for _ in 0..(11 * 9) {
+            w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            for _ in 0..6 {
+                w.write(0x40, 8); // INTRADC
+            }
+        }
+
+        write_qcif_header(&mut w, 2, 1);
+        for _ in 0..(11 * 9) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        decoder.set_max_references(2);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.last_warnings().is_empty());
+
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(
+            &[Warning::DimensionsChanged {
+                from: (128, 96),
+                to: (176, 144),
+            }],
+            decoder.last_warnings()
+        );
+        assert!(decoder.get_picture(0).is_none());
+        let intra_luma = decoder.get_last_picture().unwrap().as_luma().to_vec();
+        assert_eq!(176 * 144, intra_luma.len());
+
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(decoder.last_warnings().is_empty());
+        assert_eq!(
+            &intra_luma[..],
+            decoder.get_last_picture().unwrap().as_luma()
+        );
+    }
+
+    /// Write a Sub-QCIF P-frame whose second macroblock has an invalid
+    /// `INTRADC`, followed by a GOB header for the second macroblock row.
+    fn write_corrupted_pframe(w: &mut BitWriter) {
+        write_standard_header(w, 1, 0b10000);
+        w.write(0b1, 1); // COD
+        w.write(0b0, 1); // COD
+        w.write(0b00011, 5); // MCBPC: INTRA, no chroma coefficients
+        w.write(0b0011, 4); // CBPY: no luma coefficients
+        w.write(0x00, 8); // INTRADC: invalid
+
+        w.align();
+        w.write(1, 17); // GBSC
+        w.write(1, 5); // GN
+        w.write(0, 2); // GFID
+        w.write(1, 5); // GQUANT
+        for _ in 8..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+    }
+
+    #[test]
+    fn conceal_corrupted_macroblocks() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        write_flat_macroblocks(&mut w, 0xC0);
+        write_corrupted_pframe(&mut w);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(matches!(
+            decoder.decode_next_picture(&mut reader),
+            Err(Error::InvalidIntraDc)
+        ));
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::CONCEAL_ERRORS);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert!(picture.as_luma().iter().all(|&luma| luma == 0xC0));
+
+        // The rest of the first GOB is lost along with the corrupted
+        // macroblock, and decoding resumes at the second GOB.
+        let concealed: Vec<_> = (1..8)
+            .map(|macroblock| Warning::MacroblockConcealed { macroblock })
+            .collect();
+        assert_eq!(&concealed[..], decoder.last_warnings());
+        assert!(reader.error_position().is_some());
+    }
+
+    /// Write a Sub-QCIF PB frame with a `TRB` of 1, where every P-block is
+    /// intra coded with an `INTRADC` of 0x60, and no B-block is coded.
+    fn write_pb_frame(w: &mut BitWriter, temporal_reference: u8) {
+        w.align();
+        w.write(0x20, 22); // PSC, GN 0
+        w.write(temporal_reference as u32, 8);
+        w.write(0b1000_0001, 8); // PTYPE: Sub-QCIF
+        w.write(0b10001, 5); // INTER, Annex G
+        w.write(1, 5); // PQUANT
+        w.write(0, 1); // CPM
+        w.write(1, 3); // TRB
+        w.write(0, 2); // DBQUANT
+        w.write(0, 1); // PEI
+        for _ in 0..(8 * 6) {
+            w.write(0b0, 1); // COD
+            w.write(0b00011, 5); // MCBPC: INTRA, no chroma coefficients
+            w.write(0b0, 1); // MODB: no CBPB or MVDB
+            w.write(0b0011, 4); // CBPY: no luma coefficients
+            w.write(0b1, 1); // MVD: 0
+            w.write(0b1, 1); // MVD: 0
+            for _ in 0..6 {
+                w.write(0x60, 8);
+            }
+        }
+    }
+
+    #[test]
+    fn pb_frame_outputs_b_picture_first() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        write_flat_macroblocks(&mut w, 0x20);
+        write_pb_frame(&mut w, 2);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        // The B-picture is the average of the I- and P-pictures around it.
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(PictureTypeCode::BFrame, picture.as_header().picture_type);
+        assert_eq!(1, picture.as_header().temporal_reference);
+        assert!(picture.as_luma().iter().all(|&luma| luma == 0x40));
+        assert!(picture.as_chroma_b().iter().all(|&chroma| chroma == 0x40));
+
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(PictureTypeCode::PbFrame, picture.as_header().picture_type);
+        assert_eq!(2, picture.as_header().temporal_reference);
+        assert!(picture.as_luma().iter().all(|&luma| luma == 0x60));
+        assert!(decoder.last_warnings().is_empty());
+
+        assert!(decoder.decode_next_picture(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn pb_frame_without_temporal_distance() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 255, 0b00000);
+        write_flat_macroblocks(&mut w, 0x20);
+        write_pb_frame(&mut w, 255);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        // The B-picture is still decoded, as if `TRD` were 1, and its
+        // temporal reference wraps around.
+        let picture = decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+        assert_eq!(PictureTypeCode::BFrame, picture.as_header().picture_type);
+        assert_eq!(0, picture.as_header().temporal_reference);
+        assert!(picture.as_luma().iter().all(|&luma| luma == 0x40));
+        assert_eq!(&[Warning::ZeroTemporalDistance], decoder.last_warnings());
+    }
+
+    #[test]
+    fn advanced_prediction() {
+        let mut reference = blank_picture(SourceFormat::SubQcif);
+        for (i, luma) in reference.as_luma_mut().iter_mut().enumerate() {
+            *luma = (i % 128) as u8;
+        }
+
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 1, 0b10010); // INTER, Annex F
+        w.write(0b0, 1); // COD
+        w.write(0b010, 3); // MCBPC: INTER4V, no chroma coefficients
+        w.write(0b11, 2); // CBPY: no luma coefficients
+        w.write(0b0000110, 7); // MVD: +2
+        w.write(0b1, 1); // MVD: 0
+        w.write(0b0000111, 7); // MVD: -2, cancelling out the predictor
+        w.write(0b1, 1); // MVD: 0
+        for _ in 0..4 {
+            w.write(0b1, 1); // MVD: 0
+        }
+        for _ in 1..(8 * 6) {
+            w.write(0b1, 1); // COD
+        }
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        let mut decoder = H263State::new(DecoderOption::empty());
+        decoder.insert_reference_picture(reference);
+        decoder.decode_next_picture(&mut reader).unwrap().unwrap();
+
+        // Only the top-left block moves, by two pixels.
+        let (x, _): (HalfPel, HalfPel) = decoder.last_motion_vectors()[0][0].into();
+        assert_eq!(4, x.into_unit());
+
+        let picture = decoder.get_last_picture().unwrap();
+        let luma = |x: usize, y: usize| picture.as_luma()[x + y * 128];
+
+        // Its corner at the picture edge only sees its own motion vector...
+        assert_eq!(2, luma(0, 0));
+        // ...but its other corner overlaps the still blocks beside it...
+        assert_eq!(8, luma(7, 7));
+        // ...which in turn overlap it.
+        assert_eq!(9, luma(8, 0));
+        assert_eq!(1, luma(0, 8));
+
+        // Macroblocks with only still neighbors are unaffected.
+        assert_eq!(
+            (0..16).collect::<Vec<u8>>(),
+            picture.as_luma()[20 * 128..20 * 128 + 16]
+        );
+    }
 }
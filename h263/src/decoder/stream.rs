@@ -0,0 +1,87 @@
+//! Decoding straight from a byte source
+
+use crate::decoder::picture::DecodedPicture;
+use crate::decoder::state::H263State;
+use crate::decoder::types::DecoderOption;
+use crate::error::Result;
+use crate::parser::H263Reader;
+use std::io::Read;
+
+/// A decoder bundled with the bitstream it decodes.
+///
+/// This is the simplest way to decode a bitstream: construct it around any
+/// `Read` source, then call `decode_next_picture` until it yields `None`.
+/// The underlying `H263State` remains available for anything more involved.
+pub struct H263Stream<R>
+where
+    R: Read,
+{
+    /// The state of the decoder.
+    state: H263State,
+
+    /// The bitstream being decoded.
+    reader: H263Reader<R>,
+}
+
+impl<R> H263Stream<R>
+where
+    R: Read,
+{
+    /// Construct a new `H263Stream` decoding the bitstream in `source`.
+    pub fn new(source: R, decoder_options: DecoderOption) -> Self {
+        Self {
+            state: H263State::new(decoder_options),
+            reader: H263Reader::from_source(source),
+        }
+    }
+
+    /// Decode the next picture in the bitstream.
+    ///
+    /// The picture holds the luma and chroma planes, their row strides, the
+    /// picture dimensions (see `DecodedPicture::meta`) and the header it was
+    /// decoded with, including its picture type. Yields `None` once the
+    /// bitstream has ended.
+    pub fn decode_next_picture(&mut self) -> Result<Option<&DecodedPicture>> {
+        self.state.decode_next_picture(&mut self.reader)
+    }
+
+    /// Get the decoder state.
+    pub fn state(&self) -> &H263State {
+        &self.state
+    }
+
+    /// Get the decoder state, to change its settings.
+    pub fn state_mut(&mut self) -> &mut H263State {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state::tests::{write_flat_iframe, write_uncoded_pframe, BitWriter};
+    use crate::decoder::{DecoderOption, H263Stream};
+    use crate::types::PictureTypeCode;
+
+    #[test]
+    fn decode_from_slice() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        write_uncoded_pframe(&mut w, 1);
+        let data = w.into_bytes();
+
+        let mut stream = H263Stream::new(&data[..], DecoderOption::SORENSON_SPARK_BITSTREAM);
+
+        let picture = stream.decode_next_picture().unwrap().unwrap();
+        let meta = picture.meta();
+        assert_eq!((128, 96), (meta.width, meta.height));
+        assert_eq!(PictureTypeCode::IFrame, meta.picture_type);
+        assert_eq!(64, picture.chroma_samples_per_row());
+
+        let picture = stream.decode_next_picture().unwrap().unwrap();
+        assert_eq!(PictureTypeCode::PFrame, picture.meta().picture_type);
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+
+        assert!(stream.decode_next_picture().unwrap().is_none());
+        assert_eq!(Some(0), stream.state().sorenson_version());
+    }
+}
@@ -1,8 +1,10 @@
 //! Decoder types
 
+use crate::error::{Error, Result};
+
 bitflags! {
     /// Options which influence the decoding of a bitstream.
-    pub struct DecoderOption : u8 {
+    pub struct DecoderOption : u16 {
         /// Attempt to decode the video as a Sorenson Spark bitstream.
         ///
         /// Sorenson Spark is a modified H.263 video format notably used in early
@@ -13,5 +15,223 @@ bitflags! {
         /// Whether or not the use of Annex O's Temporal, SNR, and Spatial
         /// Scalability mode has been negotiated.
         const USE_SCALABILITY_MODE = 0b10;
+
+        /// Treat fatal decoding errors as the end of the bitstream.
+        ///
+        /// When set, `decode_next_picture` yields `Ok(None)` instead of an
+        /// error the first time a picture fails to decode, and on every call
+        /// afterwards. The error that stopped decoding can be retrieved with
+        /// `H263State::last_error`.
+        const STOP_ON_ERROR = 0b100;
+
+        /// Reconstruct all transform coefficients as
+        /// `QUANT * (2 * |LEVEL| + 1)`, regardless of the quantizer.
+        ///
+        /// H.263 subtracts one from this for even quantizers, i.e. adds
+        /// `(QUANT - 1) | 1` rather than `QUANT` to `2 * |LEVEL| * QUANT`.
+        /// Some nonstandard encoders expect the unadjusted reconstruction.
+        const UNADJUSTED_RECONSTRUCTION = 0b1000;
+
+        /// Decode the video as monochrome (4:0:0).
+        ///
+        /// Some nonstandard encoders omit the two chroma blocks from every
+        /// macroblock, which H.263 cannot signal. When set, only the four luma
+        /// blocks of each macroblock are read, and decoded pictures have
+        /// neutral (mid-gray) chroma planes.
+        const MONOCHROME = 0b1_0000;
+
+        /// Apply the H.263 Annex J deblocking filter to decoded pictures.
+        ///
+        /// When set, the pictures returned by decoding are smoothed across
+        /// block edges, with a strength chosen by the quantizer of each
+        /// macroblock. Pictures retained for prediction are not filtered, so
+        /// this only affects the output of bitstreams that were not encoded
+        /// with the filter in the loop. Pictures that signal Annex J are
+        /// always filtered in the loop, and are not filtered a second time.
+        const DEBLOCK = 0b10_0000;
+
+        /// Conceal corrupted macroblocks instead of failing the picture.
+        ///
+        /// When set, a macroblock that cannot be decoded does not abort the
+        /// picture. Instead, the decoder skips ahead to the next GOB or
+        /// picture start code, and every macroblock it skipped over is
+        /// copied from the reference picture (or filled with gray, if there
+        /// is none). Each concealed macroblock is reported in the decoder's
+        /// `last_warnings` as `Warning::MacroblockConcealed`, as are the
+        /// missing macroblocks of a picture that ended early.
+        const CONCEAL_ERRORS = 0b100_0000;
+
+        /// Output pictures in display order rather than decode order.
+        ///
+        /// When set, decoded pictures are held in a queue and output in
+        /// order of their temporal references. This adds a latency of one
+        /// picture: each picture is only output once the picture after it has
+        /// been decoded, or once the bitstream or sequence has ended, at
+        /// which point the queue is flushed before `None` is yielded.
+        const REORDER_OUTPUT = 0b1000_0000;
+
+        /// Only reconstruct the luma plane of decoded pictures.
+        ///
+        /// Chroma blocks are still parsed, as the bitstream demands, but are
+        /// neither dequantized nor transformed, and decoded pictures have
+        /// neutral (mid-gray) chroma planes, as with `MONOCHROME`. Luma
+        /// decodes exactly as it would otherwise. This saves work when only
+        /// brightness is needed, such as for thumbnails or motion detection.
+        const DECODE_LUMA_ONLY = 0b1_0000_0000;
+    }
+}
+
+impl DecoderOption {
+    /// Start building a validated set of decoder options.
+    pub fn builder() -> DecoderOptionBuilder {
+        DecoderOptionBuilder::default()
+    }
+
+    /// Check that these options do not contradict each other.
+    ///
+    /// Sorenson Spark picture headers have no way to signal the layer
+    /// numbers of Annex O, so `USE_SCALABILITY_MODE` cannot be combined with
+    /// `SORENSON_SPARK_BITSTREAM`.
+    pub fn validate(self) -> Result<Self> {
+        if self.contains(Self::SORENSON_SPARK_BITSTREAM | Self::USE_SCALABILITY_MODE) {
+            return Err(Error::ConflictingDecoderOptions(
+                "Sorenson Spark bitstreams cannot use the Annex O scalability mode",
+            ));
+        }
+
+        Ok(self)
+    }
+}
+
+/// A builder for a set of `DecoderOption`s that are checked for
+/// contradictions before decoding starts.
+///
+/// The raw flags can still be combined directly, in which case they are not
+/// validated.
+#[derive(Copy, Clone, Debug)]
+pub struct DecoderOptionBuilder {
+    options: DecoderOption,
+}
+
+impl Default for DecoderOptionBuilder {
+    fn default() -> Self {
+        Self {
+            options: DecoderOption::empty(),
+        }
+    }
+}
+
+impl DecoderOptionBuilder {
+    /// Enable or disable a single option, or several at once.
+    pub fn set(mut self, option: DecoderOption, enabled: bool) -> Self {
+        self.options.set(option, enabled);
+        self
+    }
+
+    /// Set whether to decode the video as a Sorenson Spark bitstream.
+    pub fn sorenson_spark(self, enabled: bool) -> Self {
+        self.set(DecoderOption::SORENSON_SPARK_BITSTREAM, enabled)
+    }
+
+    /// Set whether the Annex O scalability mode has been negotiated.
+    pub fn scalability_mode(self, enabled: bool) -> Self {
+        self.set(DecoderOption::USE_SCALABILITY_MODE, enabled)
+    }
+
+    /// Set whether fatal decoding errors end the bitstream.
+    pub fn stop_on_error(self, enabled: bool) -> Self {
+        self.set(DecoderOption::STOP_ON_ERROR, enabled)
+    }
+
+    /// Set whether coefficients are reconstructed without the adjustment
+    /// for even quantizers.
+    pub fn unadjusted_reconstruction(self, enabled: bool) -> Self {
+        self.set(DecoderOption::UNADJUSTED_RECONSTRUCTION, enabled)
+    }
+
+    /// Set whether to decode the video as monochrome.
+    pub fn monochrome(self, enabled: bool) -> Self {
+        self.set(DecoderOption::MONOCHROME, enabled)
+    }
+
+    /// Set whether to deblock decoded pictures for output.
+    pub fn deblock(self, enabled: bool) -> Self {
+        self.set(DecoderOption::DEBLOCK, enabled)
+    }
+
+    /// Set whether to conceal corrupted macroblocks.
+    pub fn conceal_errors(self, enabled: bool) -> Self {
+        self.set(DecoderOption::CONCEAL_ERRORS, enabled)
+    }
+
+    /// Set whether to only reconstruct the luma plane.
+    pub fn luma_only(self, enabled: bool) -> Self {
+        self.set(DecoderOption::DECODE_LUMA_ONLY, enabled)
+    }
+
+    /// Set whether to output pictures in display order.
+    pub fn reorder_output(self, enabled: bool) -> Self {
+        self.set(DecoderOption::REORDER_OUTPUT, enabled)
+    }
+
+    /// Yield the options, or an error if they contradict each other.
+    ///
+    /// See `DecoderOption::validate` for the combinations that are rejected.
+    pub fn build(self) -> Result<DecoderOption> {
+        self.options.validate()
+    }
+}
+
+/// The quantized transform coefficients of one block, as coded in the
+/// bitstream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedBlock {
+    /// The index of the macroblock containing this block, in raster order.
+    pub macroblock: usize,
+
+    /// The index of this block within its macroblock.
+    ///
+    /// Blocks 0 through 3 are luma, in raster order; 4 is chroma-blue and 5 is
+    /// chroma-red.
+    pub block: usize,
+
+    /// The coded `LEVEL` of each coefficient, in row-major (x + y*8) order.
+    ///
+    /// These have been decompressed and dezigzagged, but not dequantized.
+    /// Intra DC coefficients hold their `INTRADC` value, with the code `0xFF`
+    /// reported as 128.
+    pub levels: [i16; 64],
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::DecoderOption;
+    use crate::error::Error;
+
+    #[test]
+    fn builder_accepts_compatible_options() {
+        let options = DecoderOption::builder()
+            .sorenson_spark(true)
+            .deblock(true)
+            .conceal_errors(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            DecoderOption::SORENSON_SPARK_BITSTREAM
+                | DecoderOption::DEBLOCK
+                | DecoderOption::CONCEAL_ERRORS,
+            options
+        );
+    }
+
+    #[test]
+    fn builder_rejects_sorenson_scalability() {
+        let result = DecoderOption::builder()
+            .sorenson_spark(true)
+            .scalability_mode(true)
+            .build();
+
+        assert!(matches!(result, Err(Error::ConflictingDecoderOptions(_))));
     }
 }
@@ -11,6 +11,12 @@ pub enum Error {
     #[error("the H.263 bitstream doesn't start with a picture")]
     MiddleOfBitstream,
 
+    #[error("the H.263 bitstream ended unexpectedly")]
+    UnexpectedEof,
+
+    #[error("the H.263 bitstream has no start code where one is required")]
+    InvalidStartCode,
+
     #[error("the H.263 bitstream contains an invalid macroblock header")]
     InvalidMacroblockHeader,
 
@@ -20,14 +26,14 @@ pub enum Error {
     #[error("the H.263 bitstream contains an invalid intra-dc coefficient")]
     InvalidIntraDc,
 
-    #[error("the H.263 bitstream contains an invalid short ac coefficient")]
-    InvalidShortCoefficient,
-
     #[error("the H.263 bitstream contains an invalid long ac coefficient")]
     InvalidLongCoefficient,
 
-    #[error("the H.263 bitstream contains an invalid motion vector")]
-    InvalidMvd,
+    /// A variable-length code that does not appear in its code table.
+    ///
+    /// The syntax element being read, such as `TCOEF` or `MVD`, is given.
+    #[error("the H.263 bitstream contains an invalid {0} code")]
+    InvalidVlcCode(&'static str),
 
     #[error("the H.263 bitstream has an invalid picture type")]
     InvalidPType,
@@ -35,6 +41,9 @@ pub enum Error {
     #[error("the H.263 bitstream has an invalid extension picture type")]
     InvalidPlusPType,
 
+    #[error("the Sorenson Spark bitstream has an unknown version")]
+    InvalidSorensonVersion,
+
     #[error("the H.263 bitstream has an invalid group-of-blocks header")]
     InvalidGobHeader,
 
@@ -47,19 +56,47 @@ pub enum Error {
     #[error("the decoded H.263 bitstream has an invalid picture format")]
     PictureFormatInvalid,
 
+    /// A predicted picture whose size differs from that of its reference
+    /// picture, given as `(width, height)`.
+    #[error("a {picture:?} picture cannot be predicted from a {reference:?} reference picture")]
+    DimensionMismatch {
+        picture: (u16, u16),
+        reference: (u16, u16),
+    },
+
+    /// A set of decoder options that contradict each other, for the reason
+    /// given.
+    #[error("the decoder options are contradictory: {0}")]
+    ConflictingDecoderOptions(&'static str),
+
     #[error("the decoded H.263 bitstream has uncoded iframe blocks")]
     UncodedIFrameBlocks,
 
+    #[error("the requested reference picture is not available")]
+    ReferencePictureMissing,
+
+    #[error("the requested reference picture has been evicted from the reference buffer")]
+    ReferenceEvicted,
+
+    #[error("the FLV video tag is empty or does not contain Sorenson H.263 video")]
+    InvalidFlvTag,
+
     #[error("an I/O error occured: {0}")]
     UnhandledIoError(IoError),
 
-    #[error("a feature in the H.263 bitstream being decoded is not yet supported")]
-    UnimplementedDecoding,
+    /// A bitstream feature that this decoder does not implement, such as an
+    /// optional annex, which is named.
+    #[error("{0} is not yet supported by this H.263 decoder")]
+    UnsupportedFeature(&'static str),
 }
 
 impl From<IoError> for Error {
     fn from(ioe: IoError) -> Self {
-        Error::UnhandledIoError(ioe)
+        if ioe.kind() == IoErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else {
+            Error::UnhandledIoError(ioe)
+        }
     }
 }
 
@@ -68,11 +105,7 @@ impl Error {
     ///
     /// EOF errors end the current picture.
     pub fn is_eof_error(&self) -> bool {
-        if let Self::UnhandledIoError(ioe) = self {
-            matches!(ioe.kind(), IoErrorKind::UnexpectedEof)
-        } else {
-            false
-        }
+        matches!(self, Self::UnexpectedEof)
     }
 
     /// Determines if this is an error caused by macroblock parsing.
@@ -92,4 +125,39 @@ impl Error {
     }
 }
 
+/// Non-fatal problems encountered while decoding a picture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum Warning {
+    #[error("macroblock {macroblock} has a motion vector pointing outside the reference picture")]
+    MotionVectorClamped {
+        /// The index of the macroblock within the picture, in raster order.
+        macroblock: usize,
+    },
+
+    #[error("macroblock {macroblock} could not be decoded and was concealed")]
+    MacroblockConcealed {
+        /// The index of the macroblock within the picture, in raster order.
+        macroblock: usize,
+    },
+
+    /// An intra picture changed the picture size, given as `(width, height)`.
+    ///
+    /// All previously retained reference pictures were dropped.
+    #[error("the picture size changed from {from:?} to {to:?}")]
+    DimensionsChanged {
+        /// The size of the previously decoded picture.
+        from: (u16, u16),
+
+        /// The size of this picture.
+        to: (u16, u16),
+    },
+
+    /// The P-picture of a PB frame has the same temporal reference as its
+    /// reference picture.
+    ///
+    /// The B-picture was predicted as if they were one picture period apart.
+    #[error("the PB frame has no temporal distance to its reference picture")]
+    ZeroTemporalDistance,
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,81 @@
+//! Flash Video (FLV) container support
+
+use crate::decoder::{DecodedPicture, H263State};
+use crate::error::{Error, Result};
+use crate::parser::H263Reader;
+
+/// The FLV `CodecID` of Sorenson Spark video.
+const CODEC_ID_SORENSON_H263: u8 = 2;
+
+/// The FLV `FrameType` of video info and command frames.
+const FRAME_TYPE_VIDEO_INFO: u8 = 5;
+
+/// Decode the picture carried in the payload of an FLV video tag.
+///
+/// `tag_payload` is the `VIDEODATA` of the tag: a one-byte header holding the
+/// `FrameType` and `CodecID` nibbles, followed by a Sorenson Spark picture.
+/// The `state` must have been created with the `SORENSON_SPARK_BITSTREAM`
+/// decoder option.
+///
+/// Keyframes, inter frames and disposable inter frames are all decoded; the
+/// picture header itself determines how. Video info and command frames carry
+/// no picture and yield `None`. Tags of any codec other than Sorenson H.263
+/// yield an `InvalidFlvTag` error.
+pub fn decode_video_tag<'a>(
+    state: &'a mut H263State,
+    tag_payload: &[u8],
+) -> Result<Option<&'a DecodedPicture>> {
+    let (header, picture_data) = tag_payload.split_first().ok_or(Error::InvalidFlvTag)?;
+    let frame_type = header >> 4;
+    let codec_id = header & 0x0F;
+
+    if codec_id != CODEC_ID_SORENSON_H263 {
+        return Err(Error::InvalidFlvTag);
+    }
+
+    if frame_type == FRAME_TYPE_VIDEO_INFO {
+        return Ok(None);
+    }
+
+    state.decode_next_picture(&mut H263Reader::from_source(picture_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state_tests::{write_flat_iframe, write_uncoded_pframe, BitWriter};
+    use crate::decoder::{DecoderOption, H263State};
+    use crate::error::Error;
+    use crate::flv::decode_video_tag;
+
+    #[test]
+    fn decode_keyframe_tag() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x40);
+        let keyframe: Vec<u8> = std::iter::once(0x12).chain(w.into_bytes()).collect();
+
+        let mut w = BitWriter::default();
+        write_uncoded_pframe(&mut w, 1);
+        let interframe: Vec<u8> = std::iter::once(0x22).chain(w.into_bytes()).collect();
+
+        let mut state = H263State::new(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let picture = decode_video_tag(&mut state, &keyframe).unwrap().unwrap();
+        assert_eq!(128, picture.luma_samples_per_row());
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+
+        let picture = decode_video_tag(&mut state, &interframe).unwrap().unwrap();
+        assert_eq!(1, picture.as_header().temporal_reference);
+        assert!(picture.as_luma().iter().all(|&l| l == 0x40));
+
+        assert!(decode_video_tag(&mut state, &[0x52, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            decode_video_tag(&mut state, &[0x14, 0x00]),
+            Err(Error::InvalidFlvTag)
+        ));
+        assert!(matches!(
+            decode_video_tag(&mut state, &[]),
+            Err(Error::InvalidFlvTag)
+        ));
+    }
+}
@@ -6,12 +6,19 @@ extern crate bitflags;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod deblock;
 mod decoder;
 mod error;
+pub mod flv;
 pub mod parser;
 mod traits;
+pub mod transform;
 mod types;
 
-pub use decoder::{DecoderOption, H263State};
-pub use error::{Error, Result};
-pub use types::PictureTypeCode;
+pub use decoder::{
+    supported_features, ColorConfig, ColorMatrix, ColorRange, DecodedFrame, DecodedPicture,
+    DecoderBuilder, DecoderOption, DecoderOptionBuilder, FeatureSet, H263State, H263Stream,
+    PictureMeta, PicturePool, QuantizedBlock, Rect,
+};
+pub use error::{Error, Result, Warning};
+pub use types::{CustomPictureClock, HalfPel, MotionVector, PictureTypeCode};
@@ -1,5 +1,21 @@
 //! H.263 Bitstream parser functions.
 
+/// Evaluate `$read`, and report the bits it consumed from `$reader` as the
+/// syntax element `$name`.
+///
+/// Without the `trace` feature, this is just `$read`.
+macro_rules! traced {
+    ($reader:ident, $name:expr, $read:expr) => {{
+        #[cfg(feature = "trace")]
+        let trace_start = $reader.trace_position();
+        let value = $read;
+        #[cfg(feature = "trace")]
+        $reader.trace($name, trace_start);
+
+        value
+    }};
+}
+
 mod block;
 mod gob;
 mod macroblock;
@@ -7,8 +23,11 @@ mod picture;
 mod reader;
 mod vlc;
 
-pub use block::decode_block;
-pub use gob::decode_gob;
+pub use block::{decode_block, decode_macroblock_blocks};
+pub(crate) use gob::macroblock_rows_per_gob;
+pub use gob::{decode_end_of_sequence, decode_gob, GobData, GobReader};
 pub use macroblock::decode_macroblock;
-pub use picture::decode_picture;
-pub use reader::H263Reader;
+pub use picture::{build_seek_index, decode_picture, probe_picture_header, SeekPoint};
+pub use reader::{BitstreamSource, H263Reader, SliceSource};
+#[cfg(feature = "trace")]
+pub use reader::{TraceEvent, Tracer};
@@ -4,10 +4,11 @@
 
 use crate::decoder::DecoderOption;
 use crate::error::{Error, Result};
-use crate::parser::reader::H263Reader;
+use crate::parser::reader::{BitstreamSource, H263Reader};
 use crate::parser::vlc::{Entry, Entry::*};
-use crate::types::{Block, IntraDc, MacroblockType, Picture, PictureOption, TCoefficient};
-use std::io::Read;
+use crate::types::{
+    Block, IntraDc, Macroblock, MacroblockType, Picture, PictureOption, TCoefficient,
+};
 
 /// Represents a partially decoded short `TCOEF` entry.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -676,20 +677,23 @@ pub fn decode_block<R>(
     mut tcoef_present: bool,
 ) -> Result<Block>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let intradc = if macroblock_type.is_intra() {
-            Some(IntraDc::from_u8(reader.read_u8()?).ok_or(Error::InvalidIntraDc)?)
+            let intradc = traced!(reader, "INTRADC", reader.read_u8()?);
+            Some(IntraDc::from_u8(intradc).ok_or(Error::InvalidIntraDc)?)
         } else {
             None
         };
 
         let mut tcoef = Vec::new();
         while tcoef_present {
+            #[cfg(feature = "trace")]
+            let trace_start = reader.trace_position();
             let short_tcoef = reader.read_vlc(&TCOEF_TABLE[..])?;
 
-            match short_tcoef.ok_or(Error::InvalidShortCoefficient)? {
+            match short_tcoef.ok_or(Error::InvalidVlcCode("TCOEF"))? {
                 EscapeToLong => {
                     let level_width = if decoder_options
                         .contains(DecoderOption::SORENSON_SPARK_BITSTREAM)
@@ -715,7 +719,9 @@ where
                     //TODO: Modified Quantization (Annex T)
                     if level == i16::MAX << level_width {
                         if running_options.contains(PictureOption::MODIFIED_QUANTIZATION) {
-                            return Err(Error::UnimplementedDecoding);
+                            return Err(Error::UnsupportedFeature(
+                                "Annex T, Modified Quantization mode",
+                            ));
                         } else {
                             return Err(Error::InvalidLongCoefficient);
                         }
@@ -748,12 +754,89 @@ where
                     tcoef_present = !last;
                 }
             };
+
+            #[cfg(feature = "trace")]
+            reader.trace("TCOEF", trace_start);
         }
 
         Ok(Block { intradc, tcoef })
     })
 }
 
+/// Decode all of the blocks of a macroblock from the bitstream referenced by
+/// `reader`.
+///
+/// The six blocks of the macroblock are yielded in bitstream order: the four
+/// luma blocks, then the chroma-B and chroma-R blocks. They are followed by
+/// the six B-blocks of a PB-frame macroblock, if it carries `CBPB`. Uncoded
+/// and stuffing macroblocks have no blocks.
+///
+/// Chroma blocks are not read if the `MONOCHROME` decoder option is in
+/// force, and are yielded empty instead.
+///
+/// The reader is not advanced if any block fails to decode.
+pub fn decode_macroblock_blocks<R>(
+    reader: &mut H263Reader<R>,
+    decoder_options: DecoderOption,
+    picture: &Picture,
+    running_options: PictureOption,
+    macroblock: &Macroblock,
+) -> Result<Vec<Block>>
+where
+    R: BitstreamSource,
+{
+    reader.with_transaction(|reader| {
+        let mut blocks = Vec::new();
+
+        if let Macroblock::Coded {
+            mb_type,
+            coded_block_pattern,
+            coded_block_pattern_b,
+            ..
+        } = macroblock
+        {
+            let patterns = std::iter::once((*mb_type, coded_block_pattern)).chain(
+                coded_block_pattern_b
+                    .iter()
+                    .map(|cbpb| (MacroblockType::Inter, cbpb)),
+            );
+
+            for (mb_type, pattern) in patterns {
+                for tcoef_present in pattern.codes_luma {
+                    blocks.push(decode_block(
+                        reader,
+                        decoder_options,
+                        picture,
+                        running_options,
+                        mb_type,
+                        tcoef_present,
+                    )?);
+                }
+
+                for tcoef_present in [pattern.codes_chroma_b, pattern.codes_chroma_r] {
+                    blocks.push(if decoder_options.contains(DecoderOption::MONOCHROME) {
+                        Block {
+                            intradc: None,
+                            tcoef: Vec::new(),
+                        }
+                    } else {
+                        decode_block(
+                            reader,
+                            decoder_options,
+                            picture,
+                            running_options,
+                            mb_type,
+                            tcoef_present,
+                        )?
+                    });
+                }
+            }
+        }
+
+        Ok(blocks)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::decoder::DecoderOption;
@@ -1716,6 +1799,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::PFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -1760,6 +1844,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -1804,6 +1889,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -1859,6 +1945,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -1914,6 +2001,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -1969,6 +2057,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -2024,6 +2113,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
@@ -2079,6 +2169,7 @@ mod tests {
             options: PictureOption::empty(),
             has_plusptype: false,
             has_opptype: false,
+            custom_pcf: None,
             picture_type: PictureTypeCode::IFrame,
             motion_vector_range: None,
             slice_submode: None,
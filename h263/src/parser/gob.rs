@@ -2,41 +2,589 @@
 
 use crate::decoder::DecoderOption;
 use crate::error::{Error, Result};
-use crate::parser::reader::H263Reader;
-use crate::types::GroupOfBlocks;
-use std::io::Read;
+use crate::parser::block::decode_macroblock_blocks;
+use crate::parser::macroblock::decode_macroblock;
+use crate::parser::reader::{BitstreamSource, H263Reader};
+use crate::types::{Block, GroupOfBlocks, Macroblock, Picture, PictureOption, SourceFormat};
+
+/// ITU-T Recommendation H.263 (01/2005) 5.1.27 `EOSBS`
+///
+/// The group number that signals the end of a sub-bitstream.
+const GN_END_OF_SUB_BITSTREAM: u8 = 30;
+
+/// ITU-T Recommendation H.263 (01/2005) 5.1.26 `EOS`
+///
+/// The group number that signals the end of the sequence.
+const GN_END_OF_SEQUENCE: u8 = 31;
+
+/// Determine how many macroblock rows make up a group of blocks, given the
+/// height of the picture in pixels.
+///
+/// This is H.263 (2005/01) 5.2.1 generalized to custom picture formats.
+pub(crate) fn macroblock_rows_per_gob(height: u16) -> usize {
+    match height {
+        0..=400 => 1,
+        401..=800 => 2,
+        _ => 4,
+    }
+}
 
 /// Attempts to read a GOB record from an H.263 bitstream.
 ///
 /// If no valid start code could be found in the bitstream, this function will
 /// raise an error. If it is currently at the start of a picture instead of a
 /// GOB, then it will yield `None`, signalling that the current data should
-/// be parsed as a picture.
+/// be parsed as a picture. The end-of-sequence and end-of-sub-bitstream codes
+/// (group numbers 31 and 30) are not GOBs either, and also yield `None`; in
+/// both cases the current picture has ended.
+///
+/// The `picture` is the header of the picture the GOB belongs to, which
+/// determines whether `GSBI` is present.
 ///
 /// The set of `DecoderOptions` allows configuring certain information about
 /// the decoding process that cannot be determined by decoding the bitstream
 /// itself.
-///
-/// TODO: GOB decoding is a stub (and likely will always be so)
 pub fn decode_gob<R>(
     reader: &mut H263Reader<R>,
     _decoder_options: DecoderOption,
+    picture: &Picture,
 ) -> Result<Option<GroupOfBlocks>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction_union(|reader| {
         let skipped_bits = reader
             .recognize_start_code(false)?
             .ok_or(Error::InvalidGobHeader)?;
 
-        reader.skip_bits(17 + skipped_bits)?;
+        traced!(reader, "GBSC", reader.skip_bits(17 + skipped_bits)?);
 
-        let gob_id = reader.read_bits::<u8>(5)?;
-        if gob_id == 0 || gob_id == 15 {
+        let group_number = traced!(reader, "GN", reader.read_bits::<u8>(5)?);
+        if group_number == 0
+            || group_number == 15
+            || group_number == GN_END_OF_SUB_BITSTREAM
+            || group_number == GN_END_OF_SEQUENCE
+        {
             return Ok(None);
         }
 
-        Err(Error::UnimplementedDecoding)
+        let multiplex_bitstream = if picture.multiplex_bitstream.is_some() {
+            Some(traced!(reader, "GSBI", reader.read_bits::<u8>(2)?))
+        } else {
+            None
+        };
+
+        let frame_id = traced!(reader, "GFID", reader.read_bits::<u8>(2)?);
+        let quantizer = traced!(reader, "GQUANT", reader.read_bits::<u8>(5)?);
+        if quantizer == 0 {
+            return Err(Error::InvalidGobHeader);
+        }
+
+        Ok(Some(GroupOfBlocks {
+            group_number,
+            multiplex_bitstream,
+            frame_id,
+            quantizer,
+        }))
     })
 }
+
+/// Attempts to read an end-of-sequence code from an H.263 bitstream.
+///
+/// If the next start code in the bitstream is `EOS`, then it is consumed,
+/// along with any stuffing before it, and this yields `true`. Otherwise,
+/// nothing is consumed and this yields `false`.
+pub fn decode_end_of_sequence<R>(reader: &mut H263Reader<R>) -> Result<bool>
+where
+    R: BitstreamSource,
+{
+    let eos = reader.with_transaction_union(|reader| {
+        let skipped_bits = match reader.recognize_start_code(false)? {
+            Some(skipped_bits) => skipped_bits,
+            None => return Ok(None),
+        };
+
+        traced!(reader, "EOS", reader.skip_bits(17 + skipped_bits)?);
+
+        let group_number = reader.read_bits::<u8>(5)?;
+        if group_number == GN_END_OF_SEQUENCE {
+            Ok(Some(()))
+        } else {
+            Ok(None)
+        }
+    })?;
+
+    Ok(eos.is_some())
+}
+
+/// A group of blocks read by `GobReader`, along with all of its macroblocks.
+#[derive(Debug)]
+pub struct GobData {
+    /// The GOB header, or `None` for the first group of blocks, whose header
+    /// is the picture header, and for groups of blocks sent without one.
+    pub header: Option<GroupOfBlocks>,
+
+    /// The GOB number, which is 0 for the first group of blocks.
+    pub group_number: u8,
+
+    /// The quantizer in force at the start of the group of blocks.
+    pub quantizer: u8,
+
+    /// The index of the first macroblock of the group of blocks within its
+    /// picture, in raster order.
+    pub first_macroblock: usize,
+
+    /// Whether the bitstream had to be resynchronized to find this group of
+    /// blocks, discarding data that could not be parsed.
+    pub resynchronized: bool,
+
+    /// The macroblocks of the group of blocks, each with the blocks it
+    /// carries, in bitstream order. See `decode_macroblock_blocks`.
+    ///
+    /// Stuffing is omitted. This holds fewer macroblocks than the group of
+    /// blocks covers if it was cut short by an error or the end of the
+    /// bitstream.
+    pub macroblocks: Vec<(Macroblock, Vec<Block>)>,
+}
+
+/// An iterator over the groups of blocks of a picture.
+///
+/// This reads a picture's macroblock data one group of blocks at a time, so
+/// that each GOB can be processed as soon as it arrives rather than waiting
+/// for the whole picture. It should be constructed with the `reader`
+/// positioned just after the picture header, and yields GOBs until the start
+/// code of the next picture or the end of the bitstream, where it leaves the
+/// `reader`.
+///
+/// Sorenson Spark pictures have no GOB headers, and are yielded as a single
+/// group of blocks.
+///
+/// GOB headers are optional, so if there is no start code after the last
+/// macroblock of a group of blocks, the next one is read without a header.
+/// It takes the next group number, and the quantizer in force at the end of
+/// the group of blocks before it.
+///
+/// If a macroblock cannot be parsed, or a GOB header is invalid, the reader
+/// skips ahead to the next start code. The group of blocks being read is
+/// yielded with only the macroblocks before the error, and the one found
+/// after skipping is marked as `resynchronized`. This means that if a GOB
+/// start code is corrupted, its header is misparsed as macroblocks of a
+/// group of blocks without one, and the rest of that group of blocks is lost
+/// once an error is detected. Errors that cannot be recovered from are
+/// yielded, after which the iterator ends.
+pub struct GobReader<'a, R>
+where
+    R: BitstreamSource,
+{
+    reader: &'a mut H263Reader<R>,
+    picture: &'a Picture,
+    decoder_options: DecoderOption,
+    running_options: PictureOption,
+
+    /// How many macroblocks make up each group of blocks.
+    macroblocks_per_gob: usize,
+
+    /// How many macroblocks make up the picture.
+    macroblocks_per_picture: usize,
+
+    /// The header of the next group of blocks to read, and whether the
+    /// bitstream was resynchronized to find it.
+    ///
+    /// `None` indicates that the picture has ended, and a header of `None`
+    /// that the next group of blocks has no header.
+    next_gob: Option<(Option<GroupOfBlocks>, bool)>,
+
+    /// The group number of the next group of blocks, if it has no header.
+    next_group_number: u8,
+
+    /// The quantizer in force at the end of the last group of blocks read.
+    quantizer: u8,
+}
+
+impl<'a, R> GobReader<'a, R>
+where
+    R: BitstreamSource,
+{
+    /// Construct a reader over the groups of blocks of `picture`.
+    ///
+    /// The `format` is the picture's source format, which must be given
+    /// separately as it may be implied by a previous picture. The
+    /// `running_options` are the picture options in force for this picture,
+    /// as for `decode_macroblock`.
+    ///
+    /// Yields `None` if the format is invalid.
+    pub fn new(
+        reader: &'a mut H263Reader<R>,
+        picture: &'a Picture,
+        format: SourceFormat,
+        decoder_options: DecoderOption,
+        running_options: PictureOption,
+    ) -> Option<Self> {
+        let (width, height) = format.into_width_and_height()?;
+        let mb_per_line = (width as usize).div_ceil(16);
+        let mb_height = (height as usize).div_ceil(16);
+
+        Some(Self {
+            reader,
+            picture,
+            decoder_options,
+            running_options,
+            macroblocks_per_gob: mb_per_line * macroblock_rows_per_gob(height),
+            macroblocks_per_picture: mb_per_line * mb_height,
+            next_gob: Some((None, false)),
+            next_group_number: 0,
+            quantizer: picture.quantizer,
+        })
+    }
+
+    /// Read a macroblock and all of its blocks.
+    ///
+    /// Yields `None` for stuffing. The reader is not advanced if this fails.
+    fn read_macroblock(&mut self) -> Result<Option<(Macroblock, Vec<Block>)>> {
+        let picture = self.picture;
+        let decoder_options = self.decoder_options;
+        let running_options = self.running_options;
+
+        self.reader.with_transaction(|reader| {
+            let macroblock = decode_macroblock(reader, picture, running_options)?;
+            if matches!(macroblock, Macroblock::Stuffing) {
+                return Ok(None);
+            }
+
+            let blocks = decode_macroblock_blocks(
+                reader,
+                decoder_options,
+                picture,
+                running_options,
+                &macroblock,
+            )?;
+
+            Ok(Some((macroblock, blocks)))
+        })
+    }
+
+    /// Skip ahead to the next start code and read the GOB header there, if
+    /// any.
+    fn resynchronize(&mut self) -> Result<()> {
+        self.next_gob = None;
+
+        match self.reader.resync_to_next_start_code() {
+            Ok(_) => {}
+            Err(ref e) if e.is_eof_error() => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match decode_gob(self.reader, self.decoder_options, self.picture) {
+            Ok(Some(gob)) => self.next_gob = Some((Some(gob), true)),
+            Ok(None) => {}
+            Err(ref e) if e.is_eof_error() || e.is_gob_error() => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, R> Iterator for GobReader<'a, R>
+where
+    R: BitstreamSource,
+{
+    type Item = Result<GobData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (header, resynchronized) = self.next_gob.take()?;
+        let group_number = header
+            .as_ref()
+            .map_or(self.next_group_number, |gob| gob.group_number);
+        let quantizer = header.as_ref().map_or(self.quantizer, |gob| gob.quantizer);
+        let first_macroblock = group_number as usize * self.macroblocks_per_gob;
+        let is_sorenson = self
+            .decoder_options
+            .contains(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let macroblock_count = if is_sorenson {
+            self.macroblocks_per_picture
+        } else {
+            self.macroblocks_per_gob.min(
+                self.macroblocks_per_picture
+                    .saturating_sub(first_macroblock),
+            )
+        };
+
+        let mut gob = GobData {
+            header,
+            group_number,
+            quantizer,
+            first_macroblock,
+            resynchronized,
+            macroblocks: Vec::with_capacity(macroblock_count),
+        };
+
+        self.next_group_number = group_number + 1;
+        self.quantizer = quantizer;
+        while gob.macroblocks.len() < macroblock_count {
+            match self.read_macroblock() {
+                Ok(Some(macroblock)) => {
+                    if let Macroblock::Coded {
+                        d_quantizer: Some(d_quantizer),
+                        ..
+                    } = macroblock.0
+                    {
+                        self.quantizer = (self.quantizer as i8 + d_quantizer).clamp(1, 31) as u8;
+                    }
+
+                    gob.macroblocks.push(macroblock);
+                }
+                Ok(None) => continue,
+                Err(ref e) if e.is_eof_error() => return Some(Ok(gob)),
+                Err(ref e) if e.is_macroblock_error() && !is_sorenson => {
+                    return match self.resynchronize() {
+                        Ok(()) => Some(Ok(gob)),
+                        Err(e) => Some(Err(e)),
+                    };
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        if is_sorenson {
+            return Some(Ok(gob));
+        }
+
+        let is_last_gob = first_macroblock + macroblock_count >= self.macroblocks_per_picture;
+        match self.reader.recognize_start_code(false) {
+            Ok(None) if !is_last_gob => {
+                self.next_gob = Some((None, false));
+                return Some(Ok(gob));
+            }
+            Err(ref e) if e.is_eof_error() => return Some(Ok(gob)),
+            Err(e) => return Some(Err(e)),
+            _ => {}
+        }
+
+        match decode_gob(self.reader, self.decoder_options, self.picture) {
+            Ok(Some(next_gob)) => self.next_gob = Some((Some(next_gob), false)),
+            Ok(None) => {}
+            Err(ref e) if e.is_eof_error() => {}
+            Err(ref e) if e.is_gob_error() => {
+                if let Err(e) = self.resynchronize() {
+                    return Some(Err(e));
+                }
+            }
+            Err(e) => return Some(Err(e)),
+        }
+
+        Some(Ok(gob))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state_tests::{write_standard_header, BitWriter};
+    use crate::decoder::DecoderOption;
+    use crate::error::Result;
+    use crate::parser::gob::{decode_end_of_sequence, decode_gob, GobData, GobReader};
+    use crate::parser::picture::decode_picture;
+    use crate::parser::reader::H263Reader;
+    use crate::types::{Picture, SourceFormat};
+
+    /// Parse the header of a Sub-QCIF I-frame.
+    fn sub_qcif_picture() -> Picture {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        let data = w.into_bytes();
+
+        let mut reader = H263Reader::from_source(&data[..]);
+        decode_picture(&mut reader, DecoderOption::empty(), None)
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Write a Sub-QCIF I-frame with a GOB header before every macroblock row
+    /// but the first. The `GBSC` of the GOBs in `corrupted` is damaged, and
+    /// the GOBs in `omitted` have no header at all.
+    fn write_gob_iframe(w: &mut BitWriter, corrupted: &[u32], omitted: &[u32]) {
+        write_standard_header(w, 0, 0b00000);
+        for group_number in 0..6 {
+            if group_number > 0 && !omitted.contains(&group_number) {
+                w.align();
+                if corrupted.contains(&group_number) {
+                    w.write(0b0_0000_0010_0000_0001, 17); // damaged GBSC
+                } else {
+                    w.write(1, 17); // GBSC
+                }
+                w.write(group_number, 5); // GN
+                w.write(0, 2); // GFID
+                w.write(group_number + 1, 5); // GQUANT
+            }
+
+            for _ in 0..8 {
+                w.write(0b1, 1); // MCBPC: INTRA, no chroma coefficients
+                w.write(0b0011, 4); // CBPY: no luma coefficients
+                for _ in 0..6 {
+                    w.write(0x40, 8); // INTRADC
+                }
+            }
+        }
+    }
+
+    fn read_gobs(data: &[u8]) -> Vec<GobData> {
+        let mut reader = H263Reader::from_source(data);
+        let picture = decode_picture(&mut reader, DecoderOption::empty(), None)
+            .unwrap()
+            .unwrap();
+
+        GobReader::new(
+            &mut reader,
+            &picture,
+            SourceFormat::SubQcif,
+            DecoderOption::empty(),
+            picture.options,
+        )
+        .unwrap()
+        .collect::<Result<_>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn end_of_sequence_is_not_a_gob() {
+        // GBSC followed by GN = 31 (EOS), then stuffing
+        let bitstream = [0x00, 0x00, 0xFC, 0x00];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+
+        assert!(
+            decode_gob(&mut reader, DecoderOption::empty(), &sub_qcif_picture())
+                .unwrap()
+                .is_none()
+        );
+
+        // The code was not consumed, so it remains readable.
+        assert_eq!(0x0000FC00u32, reader.read_bits(32).unwrap());
+    }
+
+    #[test]
+    fn end_of_sequence_is_consumed() {
+        // EOS, then stuffing, then a PSC
+        let bitstream = [0x00, 0x00, 0xFC, 0x00, 0x00, 0x80];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+
+        assert!(decode_end_of_sequence(&mut reader).unwrap());
+        assert!(!decode_end_of_sequence(&mut reader).unwrap());
+        // The PSC remains, after two bits of stuffing.
+        assert_eq!(1, reader.read_bits::<u32>(2 + 17).unwrap());
+    }
+
+    #[test]
+    fn end_of_sub_bitstream_is_not_a_gob() {
+        // GBSC followed by GN = 30 (EOSBS), SBIT and ESBI
+        let bitstream = [0x00, 0x00, 0xF8, 0x00];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+
+        assert!(
+            decode_gob(&mut reader, DecoderOption::empty(), &sub_qcif_picture())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn gob_header() {
+        // GBSC, GN = 3, GFID = 2, GQUANT = 9
+        let bitstream = [0x00, 0x00, 0x8E, 0x48];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+
+        let gob = decode_gob(&mut reader, DecoderOption::empty(), &sub_qcif_picture())
+            .unwrap()
+            .unwrap();
+        assert_eq!(3, gob.group_number);
+        assert_eq!(None, gob.multiplex_bitstream);
+        assert_eq!(2, gob.frame_id);
+        assert_eq!(9, gob.quantizer);
+    }
+
+    #[test]
+    fn gob_reader_yields_each_gob() {
+        let mut w = BitWriter::default();
+        write_gob_iframe(&mut w, &[], &[]);
+        let gobs = read_gobs(&w.into_bytes());
+
+        let groups: Vec<_> = gobs
+            .iter()
+            .map(|gob| (gob.group_number, gob.quantizer, gob.first_macroblock))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 1, 0),
+                (1, 2, 8),
+                (2, 3, 16),
+                (3, 4, 24),
+                (4, 5, 32),
+                (5, 6, 40)
+            ],
+            groups
+        );
+        assert!(gobs[0].header.is_none());
+        for gob in gobs {
+            assert!(!gob.resynchronized);
+            assert_eq!(8, gob.macroblocks.len());
+            assert!(gob.macroblocks.iter().all(|(_, blocks)| blocks.len() == 6));
+        }
+    }
+
+    #[test]
+    fn corrupted_gob_start_code_is_skipped() {
+        let mut w = BitWriter::default();
+        write_gob_iframe(&mut w, &[2], &[]);
+        let gobs = read_gobs(&w.into_bytes());
+
+        let groups: Vec<_> = gobs
+            .iter()
+            .map(|gob| (gob.group_number, gob.resynchronized))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, false),
+                (1, false),
+                (2, false),
+                (3, true),
+                (4, false),
+                (5, false)
+            ],
+            groups
+        );
+        assert_eq!(8, gobs[1].macroblocks.len());
+
+        // The damaged header is taken for macroblocks of the third GOB.
+        assert!(gobs[2].header.is_none());
+        assert!(gobs[2].macroblocks.len() < 8);
+    }
+
+    #[test]
+    fn gob_header_may_be_omitted() {
+        let mut w = BitWriter::default();
+        write_gob_iframe(&mut w, &[], &[2, 3]);
+        let gobs = read_gobs(&w.into_bytes());
+
+        // Groups without a header carry on from the one before them.
+        let groups: Vec<_> = gobs
+            .iter()
+            .map(|gob| (gob.group_number, gob.quantizer, gob.first_macroblock))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 1, 0),
+                (1, 2, 8),
+                (2, 2, 16),
+                (3, 2, 24),
+                (4, 5, 32),
+                (5, 6, 40)
+            ],
+            groups
+        );
+        assert!(gobs[2].header.is_none());
+        assert!(gobs[3].header.is_none());
+        for gob in gobs {
+            assert!(!gob.resynchronized);
+            assert_eq!(8, gob.macroblocks.len());
+        }
+    }
+}
@@ -3,13 +3,12 @@
 #![allow(clippy::unusual_byte_groupings)]
 
 use crate::error::{Error, Result};
-use crate::parser::reader::H263Reader;
+use crate::parser::reader::{BitstreamSource, H263Reader};
 use crate::parser::vlc::{Entry, Entry::End, Entry::Fork};
 use crate::types::{
     CodedBlockPattern, HalfPel, Macroblock, MacroblockType, MotionVector, Picture, PictureOption,
     PictureTypeCode,
 };
-use std::io::Read;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum BlockPatternEntry {
@@ -195,7 +194,7 @@ const MODB_TABLE: [Entry<(bool, bool)>; 5] = [
 
 fn decode_cbpb<R>(reader: &mut H263Reader<R>) -> Result<CodedBlockPattern>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let cbp0 = reader.read_bits::<u8>(1)? == 1;
@@ -256,7 +255,7 @@ const CBPY_TABLE_INTRA: [Entry<Option<[bool; 4]>>; 33] = [
 
 fn decode_dquant<R>(reader: &mut H263Reader<R>) -> Result<i8>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         Ok(match reader.read_bits::<u8>(2)? {
@@ -409,27 +408,30 @@ const MVD_TABLE: [Entry<Option<f32>>; 130] = [
 
 /// Decode a motion vector from the bitstream.
 ///
-/// This currently only handles standard range motion vectors, not unrestricted
-/// ones.
+/// Pictures with a `PLUSPTYPE` code unrestricted motion vectors (Annex D)
+/// with the reversible VLC of table D.3; all others use the `MVD` table,
+/// whose differences `halfpel_decode` extends to the Annex D range.
 fn decode_motion_vector<R>(
     reader: &mut H263Reader<R>,
     picture: &Picture,
     running_options: PictureOption,
 ) -> Result<MotionVector>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         if running_options.contains(PictureOption::UNRESTRICTED_MOTION_VECTORS)
             && picture.has_plusptype
         {
-            let x = reader.read_umv()?;
-            let y = reader.read_umv()?;
+            let x = traced!(reader, "MVD", reader.read_umv()?);
+            let y = traced!(reader, "MVD", reader.read_umv()?);
 
             Ok((x, y).into())
         } else {
-            let x = HalfPel::from(reader.read_vlc(&MVD_TABLE[..])?.ok_or(Error::InvalidMvd)?);
-            let y = HalfPel::from(reader.read_vlc(&MVD_TABLE[..])?.ok_or(Error::InvalidMvd)?);
+            let x = traced!(reader, "MVD", reader.read_vlc(&MVD_TABLE[..])?);
+            let x = HalfPel::from(x.ok_or(Error::InvalidVlcCode("MVD"))?);
+            let y = traced!(reader, "MVD", reader.read_vlc(&MVD_TABLE[..])?);
+            let y = HalfPel::from(y.ok_or(Error::InvalidVlcCode("MVD"))?);
 
             Ok((x, y).into())
         }
@@ -448,21 +450,26 @@ pub fn decode_macroblock<R>(
     running_options: PictureOption,
 ) -> Result<Macroblock>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let is_coded: u8 = if matches!(picture.picture_type, PictureTypeCode::IFrame) {
             0
         } else {
-            reader.read_bits(1)?
+            traced!(reader, "COD", reader.read_bits(1)?)
         };
 
         if is_coded == 0 {
-            let mcbpc = match picture.picture_type {
-                PictureTypeCode::IFrame => reader.read_vlc(&MCBPC_I_TABLE[..])?,
-                PictureTypeCode::PFrame => reader.read_vlc(&MCBPC_P_TABLE[..])?,
-                _ => return Err(Error::UnimplementedDecoding),
-            };
+            let mcbpc = traced!(
+                reader,
+                "MCBPC",
+                match picture.picture_type {
+                    PictureTypeCode::IFrame => reader.read_vlc(&MCBPC_I_TABLE[..])?,
+                    PictureTypeCode::PFrame | PictureTypeCode::PbFrame =>
+                        reader.read_vlc(&MCBPC_P_TABLE[..])?,
+                    _ => return Err(Error::UnsupportedFeature("this picture type")),
+                }
+            );
 
             let (mb_type, codes_chroma_b, codes_chroma_r) = match mcbpc {
                 BlockPatternEntry::Stuffing => return Ok(Macroblock::Stuffing),
@@ -470,34 +477,45 @@ where
                 BlockPatternEntry::Valid(mbt, chroma_b, chroma_r) => (mbt, chroma_b, chroma_r),
             };
 
+            //TODO: Advanced INTRA Coding (Annex I)
+            if mb_type.is_intra() && running_options.contains(PictureOption::ADVANCED_INTRA_CODING)
+            {
+                return Err(Error::UnsupportedFeature(
+                    "Annex I, Advanced INTRA Coding mode",
+                ));
+            }
+
             let (has_cbpb, has_mvdb) = if matches!(picture.picture_type, PictureTypeCode::PbFrame) {
-                reader.read_vlc(&MODB_TABLE[..])?
+                traced!(reader, "MODB", reader.read_vlc(&MODB_TABLE[..])?)
             } else {
                 (false, false)
             };
 
+            let coded_block_pattern_b = if has_cbpb {
+                Some(traced!(reader, "CBPB", decode_cbpb(reader)?))
+            } else {
+                None
+            };
+
+            let cbpy = traced!(reader, "CBPY", reader.read_vlc(&CBPY_TABLE_INTRA)?);
             let codes_luma = if mb_type.is_intra() {
-                match reader.read_vlc(&CBPY_TABLE_INTRA)? {
+                match cbpy {
                     Some(v) => v,
                     None => return Err(Error::InvalidMacroblockCodedBits),
                 }
             } else {
-                match reader.read_vlc(&CBPY_TABLE_INTRA)? {
+                match cbpy {
                     Some([v1, v2, v3, v4]) => [!v1, !v2, !v3, !v4],
                     None => return Err(Error::InvalidMacroblockCodedBits),
                 }
             };
 
-            let coded_block_pattern_b = if has_cbpb {
-                Some(decode_cbpb(reader)?)
-            } else {
-                None
-            };
-
             let d_quantizer = if running_options.contains(PictureOption::MODIFIED_QUANTIZATION) {
-                return Err(Error::UnimplementedDecoding);
+                return Err(Error::UnsupportedFeature(
+                    "Annex T, Modified Quantization mode",
+                ));
             } else if mb_type.has_quantizer() {
-                Some(decode_dquant(reader)?)
+                Some(traced!(reader, "DQUANT", decode_dquant(reader)?))
             } else {
                 None
             };
@@ -518,13 +536,8 @@ where
                 None
             };
 
-            let motion_vectors_b = if has_mvdb {
-                let mv1 = decode_motion_vector(reader, picture, running_options)?;
-                let mv2 = decode_motion_vector(reader, picture, running_options)?;
-                let mv3 = decode_motion_vector(reader, picture, running_options)?;
-                let mv4 = decode_motion_vector(reader, picture, running_options)?;
-
-                Some([mv1, mv2, mv3, mv4])
+            let motion_vector_b = if has_mvdb {
+                Some(decode_motion_vector(reader, picture, running_options)?)
             } else {
                 None
             };
@@ -540,7 +553,7 @@ where
                 d_quantizer,
                 motion_vector,
                 addl_motion_vectors,
-                motion_vectors_b,
+                motion_vector_b,
             })
         } else {
             Ok(Macroblock::Uncoded)
@@ -550,16 +563,20 @@ where
 
 #[cfg(test)]
 mod tests {
+    use crate::decoder::DecoderOption;
+    use crate::error::Error;
+    use crate::parser::macroblock::decode_macroblock;
     use crate::parser::macroblock::{
         BlockPatternEntry, CBPY_TABLE_INTRA, MCBPC_I_TABLE, MCBPC_P_TABLE, MODB_TABLE, MVD_TABLE,
     };
+    use crate::parser::picture::decode_picture;
     use crate::parser::reader::H263Reader;
-    use crate::types::MacroblockType;
+    use crate::types::{MacroblockType, PictureOption};
 
     #[test]
     #[allow(clippy::inconsistent_digit_grouping)]
     fn macroblock_mcbpc_iframe() {
-        let bit_pattern = vec![
+        let bit_pattern = [
             0b1_001_010_0,
             0b11_0001_00,
             0b0001_0000,
@@ -752,7 +769,7 @@ mod tests {
     #[test]
     #[allow(clippy::inconsistent_digit_grouping)]
     fn macroblock_modb_table() {
-        let bit_pattern = vec![0b0_10_11_000];
+        let bit_pattern = [0b0_10_11_000];
         let mut reader = H263Reader::from_source(&bit_pattern[..]);
 
         assert_eq!(reader.read_vlc(&MODB_TABLE).unwrap(), (false, false));
@@ -763,7 +780,7 @@ mod tests {
     #[test]
     #[allow(clippy::inconsistent_digit_grouping)]
     fn macroblock_cbpy_table() {
-        let bit_pattern = vec![
+        let bit_pattern = [
             0b0011_0010,
             0b1_00100_10,
             0b01_00011_0,
@@ -1007,4 +1024,20 @@ mod tests {
         assert_eq!(reader.read_vlc(&MVD_TABLE).unwrap(), None);
         assert_eq!(reader.read_vlc(&MVD_TABLE).unwrap(), None);
     }
+
+    #[test]
+    fn advanced_intra_coding_is_unimplemented() {
+        // Sorenson Sub-QCIF I-frame header, then an INTRA macroblock
+        let bitstream = [0x00, 0x00, 0x80, 0x02, 0x00, 0xA7, 0xFF];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let picture = decode_picture(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM, None)
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            decode_macroblock(&mut reader, &picture, PictureOption::ADVANCED_INTRA_CODING),
+            Err(Error::UnsupportedFeature(_))
+        ));
+        assert!(decode_macroblock(&mut reader, &picture, PictureOption::empty()).is_ok());
+    }
 }
@@ -2,14 +2,13 @@
 
 use crate::decoder::DecoderOption;
 use crate::error::{Error, Result};
-use crate::parser::reader::H263Reader;
+use crate::parser::reader::{BitstreamSource, H263Reader};
 use crate::types::{
     BPictureQuantizer, BackchannelMessage, CustomPictureClock, CustomPictureFormat,
     MotionVectorRange, Picture, PictureOption, PictureTypeCode, PixelAspectRatio,
     ReferencePictureResampling, ReferencePictureSelectionMode, ScalabilityLayer, SliceSubmode,
     SourceFormat,
 };
-use std::io::Read;
 
 /// The information imparted by a `PTYPE` record.
 ///
@@ -20,7 +19,7 @@ pub type PType = (PictureOption, Option<(SourceFormat, PictureTypeCode)>);
 /// Decodes the first 8 bits of `PTYPE`.
 fn decode_ptype<R>(reader: &mut H263Reader<R>) -> Result<PType>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let mut options = PictureOption::empty();
@@ -55,9 +54,9 @@ where
 
         let low_ptype_bits: u8 = reader.read_bits(5)?;
         let mut r#type = if low_ptype_bits & 0x10 != 0 {
-            PictureTypeCode::IFrame
-        } else {
             PictureTypeCode::PFrame
+        } else {
+            PictureTypeCode::IFrame
         };
 
         if low_ptype_bits & 0x08 != 0 {
@@ -140,7 +139,7 @@ fn decode_plusptype<R>(
     previous_picture_options: PictureOption,
 ) -> Result<PlusPType>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let ufep: u8 = reader.read_bits(3)?;
@@ -269,7 +268,7 @@ type SorensonPType = (SourceFormat, PictureTypeCode, PictureOption);
 /// Attempts to read a Sorenson-equivalent PTYPE from the bitstream.
 fn decode_sorenson_ptype<R>(reader: &mut H263Reader<R>) -> Result<SorensonPType>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let (mut source_format, bit_count) = match reader.read_bits(3)? {
@@ -333,7 +332,7 @@ where
 /// called after parsing `PQUANT`.
 fn decode_cpm_and_psbi<R>(reader: &mut H263Reader<R>) -> Result<Option<u8>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         if reader.read_bits::<u8>(1)? != 0 {
@@ -347,7 +346,7 @@ where
 /// Attempts to read `CPFMT` from the bitstream.
 fn decode_cpfmt<R>(reader: &mut H263Reader<R>) -> Result<CustomPictureFormat>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let cpfmt: u32 = reader.read_bits(23)?;
@@ -393,7 +392,7 @@ where
 /// Attempts to read `CPCFC` from the bitstream.
 fn decode_cpcfc<R>(reader: &mut H263Reader<R>) -> Result<CustomPictureClock>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let cpcfc = reader.read_u8()?;
@@ -408,7 +407,7 @@ where
 /// Attempts to read `UUI` from the bitstream.
 fn decode_uui<R>(reader: &mut H263Reader<R>) -> Result<MotionVectorRange>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let is_limited: u8 = reader.read_bits(1)?;
@@ -428,7 +427,7 @@ where
 /// Attempts to read `SSS` from the bitstream.
 fn decode_sss<R>(reader: &mut H263Reader<R>) -> Result<SliceSubmode>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let mut sss = SliceSubmode::empty();
@@ -452,7 +451,7 @@ fn decode_elnum_rlnum<R>(
     followers: PlusPTypeFollower,
 ) -> Result<ScalabilityLayer>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let enhancement = reader.read_bits(4)?;
@@ -472,7 +471,7 @@ where
 /// Attempts to read `RPSMF` from the bitstream.
 fn decode_rpsmf<R>(reader: &mut H263Reader<R>) -> Result<ReferencePictureSelectionMode>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let mut rpsmf = ReferencePictureSelectionMode::empty();
@@ -497,7 +496,7 @@ where
 /// Attempts to read `TRPI` and `TRP` from the bitstream.
 fn decode_trpi<R>(reader: &mut H263Reader<R>) -> Result<Option<u16>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let trpi: u8 = reader.read_bits(1)?;
@@ -515,13 +514,13 @@ where
 /// Attempts to read `BCI` and `BCM` from the bitstream.
 fn decode_bcm<R>(reader: &mut H263Reader<R>) -> Result<Option<BackchannelMessage>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let bci: u8 = reader.read_bits(1)?;
 
         if bci == 1 {
-            Err(Error::UnimplementedDecoding)
+            Err(Error::UnsupportedFeature("Annex N back-channel messages"))
         } else {
             let not_bci: u8 = reader.read_bits(1)?;
 
@@ -538,15 +537,19 @@ where
 /// Attempts to read `RPRP` from the bitstream.
 fn decode_rprp<R>(reader: &mut H263Reader<R>) -> Result<Option<ReferencePictureResampling>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
-    reader.with_transaction(|_reader| Err(Error::UnimplementedDecoding))
+    reader.with_transaction(|_reader| {
+        Err(Error::UnsupportedFeature(
+            "Annex P, Reference Picture Resampling",
+        ))
+    })
 }
 
 /// Attempts to read `TRB` from the bitstream.
 fn decode_trb<R>(reader: &mut H263Reader<R>, has_custom_pclk: bool) -> Result<u8>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         if has_custom_pclk {
@@ -560,7 +563,7 @@ where
 /// Attempts to read `DBQUANT` from the bitstream.
 fn decode_dbquant<R>(reader: &mut H263Reader<R>) -> Result<BPictureQuantizer>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| match reader.read_bits::<u8>(2)? {
         0 => Ok(BPictureQuantizer::Five),
@@ -575,7 +578,7 @@ where
 /// bitstream.
 fn decode_pei<R>(reader: &mut H263Reader<R>) -> Result<Vec<u8>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction(|reader| {
         let mut data = Vec::new();
@@ -613,22 +616,34 @@ pub fn decode_picture<R>(
     previous_picture: Option<&Picture>,
 ) -> Result<Option<Picture>>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     reader.with_transaction_union(|reader| {
         let skipped_bits = reader
             .recognize_start_code(false)?
-            .ok_or(Error::MiddleOfBitstream)?;
-
-        reader.skip_bits(17 + skipped_bits)?;
-
-        let gob_id = reader.read_bits(5)?;
+            .ok_or(Error::InvalidStartCode)?;
+
+        traced!(reader, "PSC", reader.skip_bits(17 + skipped_bits)?);
+
+        let is_sorenson = decoder_options.contains(DecoderOption::SORENSON_SPARK_BITSTREAM);
+        let gob_id = traced!(
+            reader,
+            if is_sorenson { "VERSION" } else { "GN" },
+            reader.read_bits(5)?
+        );
+
+        if is_sorenson {
+            //Version 0 and 1 share a header layout, and differ in how
+            //escaped coefficients are coded.
+            if gob_id > 1 {
+                return Err(Error::InvalidSorensonVersion);
+            }
 
-        if decoder_options.contains(DecoderOption::SORENSON_SPARK_BITSTREAM) {
-            let temporal_reference = reader.read_u8()? as u16;
-            let (source_format, picture_type, options) = decode_sorenson_ptype(reader)?;
-            let quantizer: u8 = reader.read_bits(5)?;
-            let extra = decode_pei(reader)?;
+            let temporal_reference = traced!(reader, "TR", reader.read_u8()?) as u16;
+            let (source_format, picture_type, options) =
+                traced!(reader, "PTYPE", decode_sorenson_ptype(reader)?);
+            let quantizer: u8 = traced!(reader, "PQUANT", reader.read_bits(5)?);
+            let extra = traced!(reader, "PEI", decode_pei(reader)?);
 
             return Ok(Some(Picture {
                 //Sorenson abuses the GOB ID as a version field.
@@ -638,6 +653,7 @@ where
                 options,
                 has_plusptype: false,
                 has_opptype: false,
+                custom_pcf: None,
                 picture_type,
                 quantizer,
                 extra,
@@ -660,8 +676,8 @@ where
             return Ok(None);
         }
 
-        let low_tr = reader.read_u8()?;
-        let (mut options, maybe_format_and_type) = decode_ptype(reader)?;
+        let low_tr = traced!(reader, "TR", reader.read_u8()?);
+        let (mut options, maybe_format_and_type) = traced!(reader, "PTYPE", decode_ptype(reader)?);
         let mut multiplex_bitstream = None;
         let (mut format, picture_type, followers, has_plusptype, has_opptype) =
             match maybe_format_and_type {
@@ -674,17 +690,21 @@ where
                 ),
                 None => {
                     let (extra_options, maybe_format, picture_type, followers, has_opptype) =
-                        decode_plusptype(
+                        traced!(
                             reader,
-                            decoder_options,
-                            previous_picture
-                                .map(|p| p.options)
-                                .unwrap_or_else(PictureOption::empty),
-                        )?;
+                            "PLUSPTYPE",
+                            decode_plusptype(
+                                reader,
+                                decoder_options,
+                                previous_picture
+                                    .map(|p| p.options)
+                                    .unwrap_or_else(PictureOption::empty),
+                            )?
+                        );
 
                     options |= extra_options;
 
-                    multiplex_bitstream = Some(decode_cpm_and_psbi(reader)?);
+                    multiplex_bitstream = Some(traced!(reader, "CPM", decode_cpm_and_psbi(reader)?));
 
                     (maybe_format, picture_type, followers, true, has_opptype)
                 }
@@ -701,17 +721,19 @@ where
         //somehow.
 
         if followers.contains(PlusPTypeFollower::HAS_CUSTOM_FORMAT) {
-            format = Some(SourceFormat::Extended(decode_cpfmt(reader)?));
+            format = Some(SourceFormat::Extended(traced!(reader, "CPFMT", decode_cpfmt(reader)?)));
         }
 
         let picture_clock = if followers.contains(PlusPTypeFollower::HAS_CUSTOM_CLOCK) {
-            Some(decode_cpcfc(reader)?)
+            Some(traced!(reader, "CPCFC", decode_cpcfc(reader)?))
+        } else if has_plusptype && !has_opptype {
+            previous_picture.and_then(|p| p.custom_pcf)
         } else {
             None
         };
 
         let temporal_reference = if picture_clock.is_some() {
-            let high_tr = reader.read_bits::<u16>(2)? << 8;
+            let high_tr = traced!(reader, "ETR", reader.read_bits::<u16>(2)?) << 8;
 
             high_tr | low_tr as u16
         } else {
@@ -720,38 +742,38 @@ where
 
         let motion_vector_range = if followers.contains(PlusPTypeFollower::HAS_MOTION_VECTOR_RANGE)
         {
-            Some(decode_uui(reader)?)
+            Some(traced!(reader, "UUI", decode_uui(reader)?))
         } else {
             None
         };
 
         let slice_submode = if followers.contains(PlusPTypeFollower::HAS_SLICE_STRUCTURED_SUBMODE) {
-            Some(decode_sss(reader)?)
+            Some(traced!(reader, "SSS", decode_sss(reader)?))
         } else {
             None
         };
 
         let scalability_layer = if decoder_options.contains(DecoderOption::USE_SCALABILITY_MODE) {
-            Some(decode_elnum_rlnum(reader, followers)?)
+            Some(traced!(reader, "ELNUM", decode_elnum_rlnum(reader, followers)?))
         } else {
             None
         };
 
         let reference_picture_selection_mode =
             if followers.contains(PlusPTypeFollower::HAS_REFERENCE_PICTURE_SELECTION_MODE) {
-                Some(decode_rpsmf(reader)?)
+                Some(traced!(reader, "RPSMF", decode_rpsmf(reader)?))
             } else {
                 None
             };
 
         let prediction_reference = if options.contains(PictureOption::REFERENCE_PICTURE_SELECTION) {
-            decode_trpi(reader)?
+            traced!(reader, "TRPI", decode_trpi(reader)?)
         } else {
             None
         };
 
         let backchannel_message = if options.contains(PictureOption::REFERENCE_PICTURE_SELECTION) {
-            decode_bcm(reader)?
+            traced!(reader, "BCM", decode_bcm(reader)?)
         } else {
             None
         };
@@ -759,19 +781,20 @@ where
         //TODO: this should be checking against the reference picture to see if we need RPRP
         let reference_picture_resampling = if options
             .contains(PictureOption::REFERENCE_PICTURE_RESAMPLING)
-            || previous_picture
-                .map(|p| p.format != format)
-                .unwrap_or(false)
+            || (format.is_some()
+                && previous_picture
+                    .map(|p| p.format != format)
+                    .unwrap_or(false))
         {
-            decode_rprp(reader)?
+            traced!(reader, "RPRP", decode_rprp(reader)?)
         } else {
             None
         };
 
-        let quantizer: u8 = reader.read_bits(5)?;
+        let quantizer: u8 = traced!(reader, "PQUANT", reader.read_bits(5)?);
 
         if multiplex_bitstream.is_none() {
-            multiplex_bitstream = Some(decode_cpm_and_psbi(reader)?);
+            multiplex_bitstream = Some(traced!(reader, "CPM", decode_cpm_and_psbi(reader)?));
         }
         let multiplex_bitstream = multiplex_bitstream.unwrap();
 
@@ -782,14 +805,14 @@ where
             PictureTypeCode::PbFrame | PictureTypeCode::ImprovedPbFrame
         ) {
             (
-                Some(decode_trb(reader, picture_clock.is_some())?),
-                Some(decode_dbquant(reader)?),
+                Some(traced!(reader, "TRB", decode_trb(reader, picture_clock.is_some())?)),
+                Some(traced!(reader, "DBQUANT", decode_dbquant(reader)?)),
             )
         } else {
             (None, None)
         };
 
-        let extra = decode_pei(reader)?;
+        let extra = traced!(reader, "PEI", decode_pei(reader)?);
 
         Ok(Some(Picture {
             version: None,
@@ -798,6 +821,7 @@ where
             options,
             has_plusptype,
             has_opptype,
+            custom_pcf: picture_clock,
             picture_type,
             motion_vector_range,
             slice_submode,
@@ -814,3 +838,400 @@ where
         }))
     })
 }
+
+/// Skip forward to the start of the next picture, passing over any GOB start
+/// codes along the way.
+///
+/// This yields `false` if the bitstream ends before another picture starts.
+fn skip_to_picture_start_code<R>(
+    reader: &mut H263Reader<R>,
+    decoder_options: DecoderOption,
+) -> Result<bool>
+where
+    R: BitstreamSource,
+{
+    loop {
+        match reader.resync_to_next_start_code() {
+            Ok(_) => {}
+            Err(ref e) if e.is_eof_error() => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        //Sorenson bitstreams have no GOB start codes.
+        if decoder_options.contains(DecoderOption::SORENSON_SPARK_BITSTREAM) {
+            return Ok(true);
+        }
+
+        match reader.peek_bits::<u32>(22) {
+            Ok(start_code) if start_code & 0x1F == 0 => return Ok(true),
+            Ok(_) => reader.skip_bits(17)?,
+            Err(ref e) if e.is_eof_error() => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Read the header of the next picture in an H.263 bitstream, without
+/// decoding any of its contents.
+///
+/// Any data before the picture start code, such as the tail end of a
+/// previous picture, is skipped. Once the header has been read, the reader
+/// is moved on to the start of the following picture, and all data read so
+/// far is committed. This makes it possible to cheaply list the pictures in
+/// a bitstream, e.g. to build a seek index, by calling this repeatedly until
+/// it yields `None` at the end of the bitstream.
+///
+/// `previous_picture` should be the header yielded by the previous call, as
+/// with `decode_picture`; headers may depend on those before them.
+pub fn probe_picture_header<R>(
+    reader: &mut H263Reader<R>,
+    decoder_options: DecoderOption,
+    previous_picture: Option<&Picture>,
+) -> Result<Option<Picture>>
+where
+    R: BitstreamSource,
+{
+    Ok(probe_picture(reader, decoder_options, previous_picture)?.map(|(_, picture)| picture))
+}
+
+/// Read the header of the next picture, as with `probe_picture_header`,
+/// also yielding the bit position of its start code.
+fn probe_picture<R>(
+    reader: &mut H263Reader<R>,
+    decoder_options: DecoderOption,
+    previous_picture: Option<&Picture>,
+) -> Result<Option<(usize, Picture)>>
+where
+    R: BitstreamSource,
+{
+    if !skip_to_picture_start_code(reader, decoder_options)? {
+        return Ok(None);
+    }
+
+    let position = reader.bit_position();
+    let picture = decode_picture(reader, decoder_options, previous_picture)?
+        .ok_or(Error::InternalDecoderError)?;
+
+    skip_to_picture_start_code(reader, decoder_options)?;
+    reader.commit();
+
+    Ok(Some((position, picture)))
+}
+
+/// A picture that decoding may start from, as found by `build_seek_index`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SeekPoint {
+    /// The offset of the byte holding the first bit of the picture start
+    /// code, counted from the start of the bitstream.
+    ///
+    /// Start codes need not be byte-aligned, so up to seven bits before the
+    /// start code may belong to the previous picture. These are ignored when
+    /// decoding from this offset.
+    pub offset: usize,
+
+    /// The temporal reference of the picture.
+    pub temporal_reference: u16,
+
+    /// The type of the picture.
+    pub picture_type: PictureTypeCode,
+}
+
+/// Find every INTRA picture in an H.263 bitstream.
+///
+/// The bitstream is scanned with `probe_picture_header`, without decoding
+/// any picture contents, until it ends. A decoder given a fresh reader
+/// positioned at the `offset` of any of the yielded points can decode from
+/// there without the data before it.
+pub fn build_seek_index<R>(
+    reader: &mut H263Reader<R>,
+    decoder_options: DecoderOption,
+) -> Result<Vec<SeekPoint>>
+where
+    R: BitstreamSource,
+{
+    let mut seek_points = Vec::new();
+    let mut previous_picture = None;
+
+    while let Some((position, picture)) =
+        probe_picture(reader, decoder_options, previous_picture.as_ref())?
+    {
+        if matches!(picture.picture_type, PictureTypeCode::IFrame) {
+            seek_points.push(SeekPoint {
+                offset: position / 8,
+                temporal_reference: picture.temporal_reference,
+                picture_type: picture.picture_type,
+            });
+        }
+
+        previous_picture = Some(picture);
+    }
+
+    Ok(seek_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::state_tests::{
+        write_flat_iframe, write_standard_header, write_uncoded_pframe, BitWriter,
+    };
+    use crate::decoder::DecoderOption;
+    use crate::error::Error;
+    use crate::parser::picture::{
+        build_seek_index, decode_cpfmt, decode_picture, probe_picture_header, SeekPoint,
+    };
+    use crate::parser::reader::H263Reader;
+    use crate::types::{
+        CustomPictureClock, Picture, PictureOption, PictureTypeCode, PixelAspectRatio,
+    };
+
+    #[test]
+    fn sorenson_v0_header() {
+        // PSC, version 0, TR 7, 8-bit custom size 100x60, I-frame, PQUANT 1
+        let bitstream = [0x00, 0x00, 0x80, 0x1C, 0x32, 0x1E, 0x00, 0x80];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let picture = decode_picture(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(Some(0), picture.version);
+        assert_eq!(7, picture.temporal_reference);
+        assert_eq!(
+            Some((100, 60)),
+            picture.format.unwrap().into_width_and_height()
+        );
+        assert!(matches!(picture.picture_type, PictureTypeCode::IFrame));
+        assert_eq!(1, picture.quantizer);
+    }
+
+    #[test]
+    fn sorenson_v1_header() {
+        // PSC, version 1, TR 7, 16-bit custom size 400x300, P-frame, PQUANT 1
+        let bitstream = [0x00, 0x00, 0x84, 0x1C, 0x80, 0xC8, 0x00, 0x96, 0x20, 0x80];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let picture = decode_picture(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(Some(1), picture.version);
+        assert_eq!(
+            Some((400, 300)),
+            picture.format.unwrap().into_width_and_height()
+        );
+        assert!(matches!(picture.picture_type, PictureTypeCode::PFrame));
+    }
+
+    #[test]
+    fn sorenson_predefined_flash_size() {
+        // PSC, version 0, TR 0, 320x240, I-frame, deblocking flag, PQUANT 4
+        let bitstream = [0x00, 0x00, 0x80, 0x02, 0x92, 0x00];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let picture = decode_picture(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            Some((320, 240)),
+            picture.format.unwrap().into_width_and_height()
+        );
+        assert!(picture.options.contains(PictureOption::USE_DEBLOCKER));
+        assert_eq!(4, picture.quantizer);
+        assert!(picture.extra.is_empty());
+    }
+
+    #[test]
+    fn sorenson_unknown_version() {
+        // PSC, version 2, TR 7, Sub-QCIF, I-frame, PQUANT 1
+        let bitstream = [0x00, 0x00, 0x88, 0x1E, 0x00, 0x80];
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+
+        assert!(matches!(
+            decode_picture(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM, None),
+            Err(Error::InvalidSorensonVersion)
+        ));
+    }
+
+    #[test]
+    fn cpfmt_pixel_aspect_ratio() {
+        // PAR 12:11, width indication 43 (176 pixels), height indication 36
+        // (144 lines), then a padding bit
+        let cpfmt: u32 = (2 << 19) | (43 << 10) | (1 << 9) | 36;
+        let bitstream = (cpfmt << 9).to_be_bytes();
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let format = decode_cpfmt(&mut reader).unwrap();
+
+        assert_eq!(PixelAspectRatio::Par12_11, format.pixel_aspect_ratio);
+        assert_eq!(176, format.picture_width_indication);
+        assert_eq!(144, format.picture_height_indication);
+
+        // Extended PAR, followed by an explicit 8:9 ratio one bit into the
+        // last byte of CPFMT
+        let cpfmt: u32 = (15 << 19) | (43 << 10) | (1 << 9) | 36;
+        let mut bitstream = (cpfmt << 9).to_be_bytes().to_vec();
+        bitstream[3] = 0x10;
+        bitstream.push(0x12);
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let format = decode_cpfmt(&mut reader).unwrap();
+
+        assert_eq!(
+            PixelAspectRatio::Extended {
+                par_width: 8,
+                par_height: 9
+            },
+            format.pixel_aspect_ratio
+        );
+    }
+
+    #[test]
+    fn extended_temporal_reference() {
+        let mut w = BitWriter::default();
+
+        // An I-picture with an OPPTYPE signalling QCIF and a custom clock.
+        w.write(0b10_0000, 22);
+        w.write(0x2A, 8);
+        w.write(0b1000_0111, 8);
+        w.write(0b001, 3);
+        w.write(0b01_0100_0000_0000_1000, 18);
+        w.write(0b000_000_001, 9);
+        w.write(0, 1);
+        w.write(0x81, 8);
+        w.write(0b10, 2);
+        w.write(1, 5);
+        w.write(0, 1);
+        w.align();
+
+        // A P-picture without an OPPTYPE, which keeps the custom clock.
+        w.write(0b10_0000, 22);
+        w.write(0x2B, 8);
+        w.write(0b1000_0111, 8);
+        w.write(0b000, 3);
+        w.write(0b001_000_001, 9);
+        w.write(0, 1);
+        w.write(0b10, 2);
+        w.write(1, 5);
+        w.write(0, 1);
+        w.align();
+
+        let bitstream = w.into_bytes();
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let first = decode_picture(&mut reader, DecoderOption::empty(), None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(0x22A, first.temporal_reference);
+        assert_eq!(
+            Some(CustomPictureClock {
+                times_1001: true,
+                divisor: 1
+            }),
+            first.custom_pcf
+        );
+        assert_eq!(
+            Some((1_800_000, 1001)),
+            first.custom_pcf.unwrap().frequency()
+        );
+
+        let second = decode_picture(&mut reader, DecoderOption::empty(), Some(&first))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(0x22B, second.temporal_reference);
+        assert_eq!(first.custom_pcf, second.custom_pcf);
+        assert!(matches!(second.picture_type, PictureTypeCode::PFrame));
+    }
+
+    #[test]
+    fn probe_sorenson_pictures() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x80);
+        write_uncoded_pframe(&mut w, 1);
+        write_uncoded_pframe(&mut w, 2);
+
+        let bitstream = w.into_bytes();
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let options = DecoderOption::SORENSON_SPARK_BITSTREAM;
+        let mut pictures: Vec<Picture> = Vec::new();
+        while let Some(picture) =
+            probe_picture_header(&mut reader, options, pictures.last()).unwrap()
+        {
+            pictures.push(picture);
+        }
+
+        assert_eq!(3, pictures.len());
+        assert!(matches!(pictures[0].picture_type, PictureTypeCode::IFrame));
+        assert!(matches!(pictures[1].picture_type, PictureTypeCode::PFrame));
+        assert!(matches!(pictures[2].picture_type, PictureTypeCode::PFrame));
+        assert_eq!(
+            vec![0, 1, 2],
+            pictures
+                .iter()
+                .map(|p| p.temporal_reference)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn probe_skips_gob_start_codes() {
+        let mut w = BitWriter::default();
+        write_standard_header(&mut w, 0, 0b00000);
+        w.write(0xFFFF, 16);
+        w.align();
+        w.write(0x21, 22); // GBSC, GN 1
+        w.write(0xFFFF, 16);
+        write_standard_header(&mut w, 1, 0b10000);
+        w.write(0xFFFF, 16);
+
+        let bitstream = w.into_bytes();
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let first = probe_picture_header(&mut reader, DecoderOption::empty(), None)
+            .unwrap()
+            .unwrap();
+        let second = probe_picture_header(&mut reader, DecoderOption::empty(), Some(&first))
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(first.picture_type, PictureTypeCode::IFrame));
+        assert!(matches!(second.picture_type, PictureTypeCode::PFrame));
+        assert_eq!(
+            Some((128, 96)),
+            second.format.and_then(|f| f.into_width_and_height())
+        );
+        assert!(
+            probe_picture_header(&mut reader, DecoderOption::empty(), Some(&second))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn seek_index_of_intra_pictures() {
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 0, 0x80);
+        write_uncoded_pframe(&mut w, 1);
+        let mut bitstream = w.into_bytes();
+        let second_offset = bitstream.len();
+
+        let mut w = BitWriter::default();
+        write_flat_iframe(&mut w, 2, 0x80);
+        write_uncoded_pframe(&mut w, 3);
+        bitstream.extend(w.into_bytes());
+
+        let mut reader = H263Reader::from_source(&bitstream[..]);
+        let index = build_seek_index(&mut reader, DecoderOption::SORENSON_SPARK_BITSTREAM).unwrap();
+
+        assert_eq!(
+            vec![
+                SeekPoint {
+                    offset: 0,
+                    temporal_reference: 0,
+                    picture_type: PictureTypeCode::IFrame,
+                },
+                SeekPoint {
+                    offset: second_offset,
+                    temporal_reference: 2,
+                    picture_type: PictureTypeCode::IFrame,
+                },
+            ],
+            index
+        );
+    }
+}
@@ -6,7 +6,70 @@ use crate::traits::BitReadable;
 use crate::types::HalfPel;
 use std::cmp::min;
 use std::collections::VecDeque;
-use std::io::Read;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read};
+
+/// A syntax element read from a bitstream.
+#[cfg(feature = "trace")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The name of the element, as given in the H.263 specification.
+    pub name: &'static str,
+
+    /// The position of the first bit of the element, counted from the start
+    /// of the bitstream.
+    pub bit_offset: usize,
+
+    /// How many bits the element occupies.
+    pub bits: u32,
+
+    /// The bits of the element as coded, right-aligned.
+    ///
+    /// Elements longer than 32 bits only retain their last 32 bits.
+    pub value: u32,
+}
+
+/// A callback given each syntax element read from a bitstream.
+#[cfg(feature = "trace")]
+pub type Tracer = Box<dyn FnMut(&TraceEvent)>;
+
+/// A source of bitstream data for an `H263Reader`.
+///
+/// Every `Read` is a source, whose data the reader copies into its internal
+/// buffer as it is needed. A `SliceSource` is read in place instead.
+pub trait BitstreamSource {
+    /// Read exactly enough bytes to fill `buf`.
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()>;
+
+    /// Yield all of the data of the source, if it is already in memory.
+    ///
+    /// A reader over such a source never buffers any data.
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl<R> BitstreamSource for R
+where
+    R: Read,
+{
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        Read::read_exact(self, buf)
+    }
+}
+
+/// A bitstream that is already in memory, which `H263Reader` reads without
+/// copying it.
+pub struct SliceSource<'a>(&'a [u8]);
+
+impl BitstreamSource for SliceSource<'_> {
+    fn read_exact(&mut self, _buf: &mut [u8]) -> std::io::Result<()> {
+        Err(IoError::from(IoErrorKind::UnexpectedEof))
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self.0)
+    }
+}
 
 /// A reader that allows decoding an H.263 compliant bitstream.
 ///
@@ -14,7 +77,7 @@ use std::io::Read;
 /// of bits into a number of possible types.
 pub struct H263Reader<R>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     /// The data source to read bits from.
     source: R,
@@ -27,11 +90,39 @@ where
     /// If this value modulo eight is nonzero, then reads out of the internal
     /// buffer must read
     bits_read: usize,
+
+    /// How many bits were discarded from the start of the buffer so far.
+    bits_committed: usize,
+
+    /// The bit position at which the most recent failed read gave up.
+    error_position: Option<usize>,
+
+    /// Whether a failed read is currently being unwound out of nested
+    /// transactions, and thus has already had its position recorded.
+    unwinding_error: bool,
+
+    /// The callback to report read syntax elements to, if any.
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer>,
+
+    /// Syntax elements read since the last commit.
+    #[cfg(feature = "trace")]
+    pending_trace: Vec<TraceEvent>,
+}
+
+impl<'a> H263Reader<SliceSource<'a>> {
+    /// Wrap a bitstream that is already in memory in a reader.
+    ///
+    /// This behaves exactly like a reader from `from_source` over the same
+    /// data, except that the data is read in place rather than buffered.
+    pub fn from_slice(data: &'a [u8]) -> Self {
+        Self::from_source(SliceSource(data))
+    }
 }
 
 impl<R> H263Reader<R>
 where
-    R: Read,
+    R: BitstreamSource,
 {
     /// Wrap a source file in a reader.
     pub fn from_source(source: R) -> Self {
@@ -39,13 +130,106 @@ where
             source,
             buffer: VecDeque::new(),
             bits_read: 0,
+            bits_committed: 0,
+            error_position: None,
+            unwinding_error: false,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            #[cfg(feature = "trace")]
+            pending_trace: Vec::new(),
+        }
+    }
+
+    /// Set a callback to be given every syntax element parsed from this
+    /// reader.
+    ///
+    /// Elements are reported once the data they were read from is committed,
+    /// which the decoder does after each successfully decoded picture. Reads
+    /// that are rolled back, such as when probing for a start code or when a
+    /// picture fails to decode, are never reported.
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+        self.pending_trace.clear();
+    }
+
+    /// Yield the position of the next bit to be read, counted from the start
+    /// of the bitstream.
+    pub fn bit_position(&self) -> usize {
+        self.bits_committed + self.bits_read
+    }
+
+    /// Yield the position at which the most recent failed read gave up,
+    /// counted from the start of the bitstream.
+    ///
+    /// Failed reads roll back the reader to where they started, so after an
+    /// error `bit_position` no longer tells where the bitstream was found to
+    /// be invalid. This retains that position instead, for diagnostics.
+    ///
+    /// The position is forgotten whenever a decoder starts on the next
+    /// picture, so it never refers to an error from an earlier call.
+    pub fn error_position(&self) -> Option<usize> {
+        self.error_position
+    }
+
+    /// Forget the position of the most recent failed read.
+    pub(crate) fn clear_error_position(&mut self) {
+        self.error_position = None;
+    }
+
+    /// Yield the current buffer position, for later use with `trace`.
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace_position(&self) -> usize {
+        self.bits_read
+    }
+
+    /// Record the bits read since `start` as the syntax element `name`.
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace(&mut self, name: &'static str, start: usize) {
+        if self.tracer.is_none() {
+            return;
+        }
+
+        let value = (start..self.bits_read).fold(0u32, |value, bit| {
+            let (front, back) = self.buffered();
+            let byte = front
+                .get(bit / 8)
+                .unwrap_or_else(|| &back[bit / 8 - front.len()]);
+
+            (value << 1) | ((byte >> (7 - bit % 8)) & 1) as u32
+        });
+
+        self.pending_trace.push(TraceEvent {
+            name,
+            bit_offset: self.bits_committed + start,
+            bits: (self.bits_read - start) as u32,
+            value,
+        });
+    }
+
+    /// Yield the data after the last commit, in two parts.
+    ///
+    /// This is the internal buffer, or for sources already in memory, the
+    /// rest of the source.
+    fn buffered(&self) -> (&[u8], &[u8]) {
+        match self.source.as_slice() {
+            Some(data) => (&data[self.bits_committed / 8..], &[]),
+            None => self.buffer.as_slices(),
         }
     }
 
+    /// Count the bytes of data after the last commit.
+    fn buffered_len(&self) -> usize {
+        let (front, back) = self.buffered();
+
+        front.len() + back.len()
+    }
+
     /// Fill the internal read buffer with a given number of bytes.
     ///
-    /// This function will yield all I/O errors wrapped inside of the
-    /// `UnhandledIoError` variant type.
+    /// Running out of data yields `UnexpectedEof`; this function will yield
+    /// all other I/O errors wrapped inside of the `UnhandledIoError` variant
+    /// type.
     fn buffer_bytes(&mut self, bytes_needed: usize) -> Result<()> {
         let mut byte = [0];
         for _ in 0..bytes_needed {
@@ -60,10 +244,10 @@ where
     /// Given a certain number of needed bits, return how many bytes would need
     /// to be buffered to read it.
     fn needed_bytes_for_bits(&mut self, bits_needed: u32) -> usize {
-        let bits_available = (self.buffer.len() * 8).saturating_sub(self.bits_read);
+        let bits_available = (self.buffered_len() * 8).saturating_sub(self.bits_read);
         let bits_short = (bits_needed as usize).saturating_sub(bits_available);
 
-        (bits_short / 8) + if bits_short % 8 != 0 { 1 } else { 0 }
+        bits_short.div_ceil(8)
     }
 
     /// Ensure that at least a certain number of additional bits can be read
@@ -105,7 +289,8 @@ where
         let mut accum = T::zero();
         let bytes_read = self.bits_read / 8;
         let mut bits_read = self.bits_read % 8;
-        for byte in self.buffer.iter().skip(bytes_read) {
+        let (front, back) = self.buffered();
+        for byte in front.iter().chain(back).skip(bytes_read) {
             if bits_needed == 0 {
                 break;
             }
@@ -238,13 +423,53 @@ where
     /// that in this case, it is undefined whether or not the start code is a
     /// picture or GOB start code.
     pub fn recognize_start_code(&mut self, in_error: bool) -> Result<Option<u32>> {
+        let max_skip_bits = if in_error {
+            None
+        } else {
+            Some(self.realignment_bits() + 1)
+        };
+
+        self.scan_for_start_code(max_skip_bits)
+    }
+
+    /// Skip forward to the next picture or GOB start code.
+    ///
+    /// This scans the bitstream bit by bit for the 17-bit start code pattern,
+    /// with no alignment requirement, and leaves the reader positioned at the
+    /// start of it. If the reader is already at a start code, it does not
+    /// move. This yields the number of bits that were skipped.
+    ///
+    /// If no start code exists before the end of the bitstream, this yields
+    /// `UnexpectedEof` and the reader does not move.
+    pub fn resync_to_next_start_code(&mut self) -> Result<u32> {
+        let skipped_bits = self
+            .scan_for_start_code(None)?
+            .ok_or(Error::InternalDecoderError)?;
+        self.skip_bits(skipped_bits)?;
+
+        Ok(skipped_bits)
+    }
+
+    /// Look for a start code within a given number of bits of the current
+    /// position.
+    ///
+    /// This behaves like `recognize_start_code`, except that the start code
+    /// need not be aligned, and may be preceded by up to `max_skip_bits` bits
+    /// of arbitrary data. This is intended for skipping over junk at the start
+    /// of a bitstream without scanning all of it.
+    pub fn recognize_start_code_within(&mut self, max_skip_bits: u32) -> Result<Option<u32>> {
+        self.scan_for_start_code(Some(max_skip_bits))
+    }
+
+    /// Count the bits ahead of the next start code, giving up once more than
+    /// `max_skip_bits` have been skipped.
+    fn scan_for_start_code(&mut self, max_skip_bits: Option<u32>) -> Result<Option<u32>> {
         self.with_lookahead(|reader| {
-            let max_skip_bits = reader.realignment_bits();
             let mut skip_bits = 0;
             let mut maybe_code: u32 = reader.peek_bits(17)?;
 
             while maybe_code != 1 {
-                if !in_error && skip_bits > max_skip_bits {
+                if max_skip_bits.is_some_and(|max| skip_bits >= max) {
                     return Ok(None);
                 }
 
@@ -321,7 +546,7 @@ where
             }
         }
 
-        Err(Error::InvalidMvd)
+        Err(Error::InvalidVlcCode("MVD"))
     }
 
     /// Yield a checkpoint value that can be used to abort a complex read
@@ -346,12 +571,19 @@ where
     /// internal buffer must not have been cleared (e.g. via `commit`) between
     /// the creation and use of this checkpoint.
     fn rollback(&mut self, checkpoint: usize) -> Result<()> {
-        if checkpoint > (self.buffer.len() * 8) {
+        if checkpoint > (self.buffered_len() * 8) {
             return Err(Error::InternalDecoderError);
         }
 
         self.bits_read = checkpoint;
 
+        #[cfg(feature = "trace")]
+        {
+            let position = self.bits_committed + checkpoint;
+            self.pending_trace
+                .retain(|event| event.bit_offset < position);
+        }
+
         Ok(())
     }
 
@@ -361,7 +593,20 @@ where
     /// a user-facing object has been read. All existing checkpoints will be
     /// invalidated.
     pub fn commit(&mut self) {
-        self.buffer.drain(0..self.bits_read / 8);
+        #[cfg(feature = "trace")]
+        {
+            if let Some(tracer) = self.tracer.as_mut() {
+                for event in self.pending_trace.drain(..) {
+                    tracer(&event);
+                }
+            }
+        }
+
+        if self.source.as_slice().is_none() {
+            self.buffer.drain(0..self.bits_read / 8);
+        }
+
+        self.bits_committed += self.bits_read / 8 * 8;
         self.bits_read %= 8;
     }
 
@@ -383,7 +628,10 @@ where
         let result = f(self);
 
         if result.is_err() {
+            self.record_error();
             self.rollback(checkpoint)?;
+        } else {
+            self.unwinding_error = false;
         }
 
         result
@@ -411,8 +659,15 @@ where
         let result = f(self);
 
         match &result {
-            Ok(None) | Err(_) => self.rollback(checkpoint)?,
-            _ => {}
+            Ok(None) => {
+                self.unwinding_error = false;
+                self.rollback(checkpoint)?;
+            }
+            Err(_) => {
+                self.record_error();
+                self.rollback(checkpoint)?;
+            }
+            Ok(Some(_)) => self.unwinding_error = false,
         };
 
         result
@@ -436,14 +691,30 @@ where
 
         let result = f(self);
 
+        if result.is_err() {
+            self.record_error();
+        } else {
+            self.unwinding_error = false;
+        }
         self.rollback(checkpoint)?;
 
         result
     }
+
+    /// Note the current position as that of a failed read, unless an
+    /// enclosing transaction is merely passing on an error that was already
+    /// recorded.
+    fn record_error(&mut self) {
+        if !self.unwinding_error {
+            self.error_position = Some(self.bit_position());
+            self.unwinding_error = true;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::Error;
     use crate::parser::reader::H263Reader;
 
     #[test]
@@ -531,6 +802,27 @@ mod tests {
         assert_eq!(0xFE7350F3, reader.read_bits::<u32>(32).unwrap());
     }
 
+    #[test]
+    fn read_from_slice() {
+        let data = [0xFF, 0x72, 0x1C, 0x1F];
+        let mut reader = H263Reader::from_slice(&data);
+
+        assert_eq!(0x07, reader.read_bits(3).unwrap());
+        reader.commit();
+
+        let result = reader.with_transaction(|reader| {
+            reader.skip_bits(6)?;
+            Err::<(), _>(Error::InvalidVlcCode("TCOEF"))
+        });
+        assert!(result.is_err());
+
+        assert_eq!(0x3E, reader.read_bits(6).unwrap());
+        reader.commit();
+        assert_eq!(0x721C1F, reader.read_bits(23).unwrap());
+        assert_eq!(32, reader.bit_position());
+        reader.read_bits::<u8>(1).unwrap_err();
+    }
+
     #[test]
     fn aligned_start_code() {
         let data = [0x00, 0x00, 0x80, 0x00];
@@ -551,6 +843,15 @@ mod tests {
         assert_eq!(Some(3), reader.recognize_start_code(false).unwrap());
     }
 
+    #[test]
+    fn start_code_within() {
+        let data = [0x13, 0x80, 0x00, 0x40, 0x00];
+        let mut reader = H263Reader::from_source(&data[..]);
+
+        assert_eq!(None, reader.recognize_start_code_within(8).unwrap());
+        assert_eq!(Some(9), reader.recognize_start_code_within(9).unwrap());
+    }
+
     #[test]
     fn resynchronize_to_start_code() {
         let data = [0x13, 0x80, 0x00, 0x40, 0x00];
@@ -558,4 +859,33 @@ mod tests {
 
         assert_eq!(Some(9), reader.recognize_start_code(true).unwrap());
     }
+
+    #[test]
+    fn resync_after_corrupted_byte() {
+        // A start code, a byte of junk, then a second start code.
+        let data = [0x00, 0x00, 0x80, 0xFF, 0x12, 0x00, 0x00, 0x80, 0x02];
+        let mut reader = H263Reader::from_source(&data[..]);
+
+        assert_eq!(1, reader.read_bits::<u32>(17).unwrap());
+        assert_eq!(17, reader.bit_position());
+
+        let result = reader.with_transaction(|reader| {
+            reader.skip_bits(12)?;
+            Err::<(), _>(Error::InvalidVlcCode("TCOEF"))
+        });
+        assert!(matches!(result, Err(Error::InvalidVlcCode("TCOEF"))));
+        assert_eq!(17, reader.bit_position());
+        assert_eq!(Some(29), reader.error_position());
+
+        assert_eq!(23, reader.resync_to_next_start_code().unwrap());
+        assert_eq!(40, reader.bit_position());
+        assert_eq!(0, reader.resync_to_next_start_code().unwrap());
+        assert_eq!(1, reader.read_bits::<u32>(17).unwrap());
+
+        assert!(reader
+            .resync_to_next_start_code()
+            .unwrap_err()
+            .is_eof_error());
+        assert_eq!(57, reader.bit_position());
+    }
 }
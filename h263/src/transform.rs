@@ -0,0 +1,151 @@
+//! Transforms used by H.263, for use outside of the decoder.
+
+pub use crate::decoder::idct_8x8;
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::idct_8x8;
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    /// The pseudorandom number generator of IEEE 1180-1990, yielding
+    /// integers from `-low` to `high` inclusive.
+    struct Ieee1180Random(i64);
+
+    impl Ieee1180Random {
+        fn next(&mut self, low: i64, high: i64) -> i64 {
+            self.0 = (self.0.wrapping_mul(1103515245).wrapping_add(12345)) & 0xFFFF_FFFF;
+            let i = self.0 & 0x7FFF_FFFE;
+            let x = (i as f64 / 0x7FFF_FFFF as f64) * (low + high + 1) as f64;
+
+            x as i64 - low
+        }
+    }
+
+    /// The 1D DCT basis, scaled by the normalization factor of its frequency,
+    /// indexed by frequency and then position.
+    fn basis_table() -> [[f64; 8]; 8] {
+        let mut table = [[0.0; 8]; 8];
+        for (freq, row) in table.iter_mut().enumerate() {
+            let scale = if freq == 0 { FRAC_1_SQRT_2 } else { 1.0 };
+            for (x, basis) in row.iter_mut().enumerate() {
+                *basis = scale * (PI * (2 * x + 1) as f64 * freq as f64 / 16.0).cos() / 2.0;
+            }
+        }
+
+        table
+    }
+
+    /// Transform a block with double precision, into (`forward`) or out of
+    /// the frequency domain, one dimension at a time.
+    fn reference_transform(basis: &[[f64; 8]; 8], block: &[f64; 64], forward: bool) -> [f64; 64] {
+        let factor = |out: usize, input: usize| {
+            if forward {
+                basis[out][input]
+            } else {
+                basis[input][out]
+            }
+        };
+
+        let mut intermediate = [0.0; 64];
+        for row in 0..8 {
+            for out in 0..8 {
+                intermediate[row * 8 + out] =
+                    (0..8).map(|i| block[row * 8 + i] * factor(out, i)).sum();
+            }
+        }
+
+        let mut output = [0.0; 64];
+        for col in 0..8 {
+            for out in 0..8 {
+                output[out * 8 + col] = (0..8)
+                    .map(|i| intermediate[i * 8 + col] * factor(out, i))
+                    .sum();
+            }
+        }
+
+        output
+    }
+
+    /// Run one IEEE 1180-1990 accuracy test of 10000 blocks with samples
+    /// drawn from `-low` to `high`, negated if `sign` is `-1`.
+    fn ieee1180_run(low: i64, high: i64, sign: i64) {
+        let basis = basis_table();
+        let mut random = Ieee1180Random(1);
+        let mut error_sums = [0i64; 64];
+        let mut squared_error_sums = [0i64; 64];
+        let mut peak_error = 0;
+
+        for _ in 0..10000 {
+            let mut samples = [0.0; 64];
+            for sample in samples.iter_mut() {
+                *sample = (random.next(low, high) * sign) as f64;
+            }
+
+            let mut coefficients = [0i16; 64];
+            let mut rounded = [0.0; 64];
+            for ((coefficient, rounded), value) in coefficients
+                .iter_mut()
+                .zip(rounded.iter_mut())
+                .zip(reference_transform(&basis, &samples, true).iter())
+            {
+                *coefficient = (value.round() as i16).clamp(-2048, 2047);
+                *rounded = *coefficient as f64;
+            }
+
+            let expected = reference_transform(&basis, &rounded, false);
+            idct_8x8(&mut coefficients);
+
+            for (i, (actual, expected)) in coefficients.iter().zip(expected.iter()).enumerate() {
+                let expected = (expected.round() as i64).clamp(-256, 255);
+                let error = *actual as i64 - expected;
+
+                peak_error = peak_error.max(error.abs());
+                error_sums[i] += error;
+                squared_error_sums[i] += error * error;
+            }
+        }
+
+        let blocks = 10000.0;
+        let description = format!("L = {}, H = {}, sign = {}", low, high, sign);
+        assert!(peak_error <= 1, "peak error of {}", description);
+
+        for (error_sum, squared_error_sum) in error_sums.iter().zip(squared_error_sums.iter()) {
+            assert!(
+                (*error_sum as f64 / blocks).abs() <= 0.015,
+                "mean error of {}",
+                description
+            );
+            assert!(
+                *squared_error_sum as f64 / blocks <= 0.06,
+                "mean square error of {}",
+                description
+            );
+        }
+
+        let overall_error = error_sums.iter().sum::<i64>() as f64 / (blocks * 64.0);
+        let overall_squared_error = squared_error_sums.iter().sum::<i64>() as f64 / (blocks * 64.0);
+        assert!(
+            overall_error.abs() <= 0.0015,
+            "overall mean error of {}",
+            description
+        );
+        assert!(
+            overall_squared_error <= 0.02,
+            "overall mean square error of {}",
+            description
+        );
+    }
+
+    #[test]
+    fn ieee1180_accuracy() {
+        for &(low, high) in &[(256, 255), (5, 5), (300, 300)] {
+            for &sign in &[1, -1] {
+                ieee1180_run(low, high, sign);
+            }
+        }
+
+        let mut zeroes = [0; 64];
+        idct_8x8(&mut zeroes);
+        assert_eq!([0; 64], zeroes);
+    }
+}
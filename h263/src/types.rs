@@ -9,7 +9,7 @@ use std::ops::{Add, Div, Neg};
 /// The `Picture` configures the current displayed frame's various options,
 /// such as it's resolution, the use of any optional H.263 features, and the
 /// intra-prediction mode used.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Picture {
     /// The version code.
     ///
@@ -43,6 +43,15 @@ pub struct Picture {
     /// keeping track of options in force from previous pictures.
     pub has_opptype: bool,
 
+    /// ITU-T Recommendation H.263 (01/2005) 5.1.7 `CPCFC`
+    ///
+    /// The custom picture clock frequency in force for this picture. If
+    /// `None`, then the standard CIF picture clock of 30000/1001hz is used.
+    ///
+    /// A custom clock is only signalled in pictures with an `OPPTYPE`, and
+    /// remains in force for following pictures without one.
+    pub custom_pcf: Option<CustomPictureClock>,
+
     /// The intra-prediction mode in use, if any.
     pub picture_type: PictureTypeCode,
 
@@ -121,6 +130,21 @@ pub struct Picture {
     pub extra: Vec<u8>,
 }
 
+impl Picture {
+    /// The number of distinct temporal references, after which they wrap
+    /// around to zero.
+    ///
+    /// Temporal references are 10 bits wide while a custom picture clock is
+    /// in force, since `ETR` extends them, and 8 bits wide otherwise.
+    pub fn temporal_reference_modulus(&self) -> u16 {
+        if self.custom_pcf.is_some() {
+            1024
+        } else {
+            256
+        }
+    }
+}
+
 /// The default resolution options available in H.263.
 ///
 /// The `CIF` refers to "Common Interchange Format", a video teleconferencing
@@ -246,7 +270,7 @@ lazy_static! {
 ///
 /// Certain `PictureTypeCode`s will prohibit the use of particular
 /// `PictureOption`s.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PictureTypeCode {
     /// A full picture update that can be independently decoded.
     IFrame,
@@ -292,9 +316,34 @@ impl PictureTypeCode {
         matches!(self, Self::PbFrame) || matches!(self, Self::ImprovedPbFrame)
     }
 
+    /// Determine if pictures of this type can be dropped without affecting
+    /// the decoding of any later picture.
+    ///
+    /// Only `DisposablePFrame`, which Sorenson Spark signals with picture type
+    /// 2, is disposable. The decoder does not make such pictures the implicit
+    /// reference picture, so skipping one does not break the reference chain.
+    /// All other picture types may be referenced by later pictures.
     pub fn is_disposable(self) -> bool {
         matches!(self, Self::DisposablePFrame)
     }
+
+    /// Determine if pictures of this type are coded without prediction from
+    /// any other picture.
+    ///
+    /// This is true of I-pictures and of the EI-pictures of Annex O, which
+    /// are only ever predicted from the simultaneous picture of a lower
+    /// layer. Either may serve as a keyframe to start decoding from.
+    pub fn is_intra(self) -> bool {
+        matches!(self, Self::IFrame | Self::EiFrame)
+    }
+
+    /// Determine if pictures of this type are predicted from previously
+    /// decoded pictures.
+    ///
+    /// Reserved picture types are neither intra nor inter.
+    pub fn is_inter(self) -> bool {
+        !self.is_intra() && !matches!(self, Self::Reserved(_))
+    }
 }
 
 /// ITU-T Recommendation H.263 (01/2005) 5.1.5-5.1.6 `CPFMT`, `EPAR`
@@ -364,6 +413,7 @@ pub enum PixelAspectRatio {
 /// 1,800,000hz, and divide it by the effective divisor to produce a frame
 /// rate. The effective divisor is `divisor` times either 1000 or 1001,
 /// depending on the `times_1001` flag.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct CustomPictureClock {
     /// Whether or not the divisor is multiplied by 1000 or 1001.
     ///
@@ -375,11 +425,27 @@ pub struct CustomPictureClock {
     pub divisor: u8,
 }
 
+impl CustomPictureClock {
+    /// Yield the picture clock frequency as a fraction of numerator and
+    /// denominator, in hertz.
+    ///
+    /// A zero divisor is forbidden, and yields `None`.
+    pub fn frequency(&self) -> Option<(u32, u32)> {
+        if self.divisor == 0 {
+            return None;
+        }
+
+        let factor = if self.times_1001 { 1001 } else { 1000 };
+
+        Some((1_800_000, self.divisor as u32 * factor))
+    }
+}
+
 /// ITU-T Recommendation H.263 (01/2005) 5.1.9 `UUI`
 ///
 /// Indicates the new motion vector range limitations when
 /// `UNRESTRICTED_MOTION_VECTORS` are enabled.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum MotionVectorRange {
     /// Motion vector component ranges are extended to limits that are
     /// prescribed in ITU-T Recommendation H.263 (01/2005) D.1 and D.2.
@@ -405,7 +471,7 @@ bitflags! {
 /// ITU-T Recommendation H.263 (01/2005) 5.1.11-5.1.12 `ELNUM`, `RLNUM`
 ///
 /// Only present if Temporal, SNR, and Spatial Scalability is enabled.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ScalabilityLayer {
     /// The 4-bit enhancement layer index.
     pub enhancement: u8,
@@ -436,7 +502,7 @@ bitflags! {
 /// video stream is sending in response to an opposing video stream. It may be
 /// presented to the encoder with a separate logical channel, or it may be
 /// muxed into a video stream that the encoder is also expected to decode.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct BackchannelMessage {
     /// What message type is being back-channeled.
@@ -467,7 +533,7 @@ pub struct BackchannelMessage {
 /// ITU-T Recommendation H.263 (01/2005) N.4.2.1 `BT`
 ///
 /// Indicates the backchanneler's decoding status of the opposing video stream.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub enum BackchannelMessageType {
     /// Positive acknowledgement of correct decoding of the opposing video
@@ -486,7 +552,7 @@ pub enum BackchannelMessageType {
 ///
 /// Whether or not the backchanneling decoder has reliable values for temporal
 /// references, group-of-block numbers, or macroblock addresses.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub enum BackchannelReliability {
     Reliable,
@@ -496,7 +562,7 @@ pub enum BackchannelReliability {
 /// ITU-T Recommendation H.263 (01/2005) P.2 `RPRP`
 ///
 /// The parameters necessary for reference-picture resampling.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct ReferencePictureResampling {
     accuracy: WarpingDisplacementAccuracy,
@@ -510,7 +576,7 @@ pub struct ReferencePictureResampling {
 }
 
 /// ITU-T Recommendation H.263 (01/2005) P.2.1 `WDA`
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub enum WarpingDisplacementAccuracy {
     /// Warping parameters are quantized to half-pixel accuracy.
@@ -521,7 +587,7 @@ pub enum WarpingDisplacementAccuracy {
 }
 
 /// ITU-T Recommendation H.263 (01/2005), 5.1.23 `DBQUANT`
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum BPictureQuantizer {
     /// B-Quantizer is five-fourths
     Five,
@@ -536,12 +602,31 @@ pub enum BPictureQuantizer {
     Eight,
 }
 
+impl BPictureQuantizer {
+    /// Derive the quantizer of the B-blocks of a macroblock from that of its
+    /// P-blocks.
+    ///
+    /// This is H.263 (2005/01) Table G.2, clipped to the valid quantizer
+    /// range.
+    pub fn b_quantizer(&self, quantizer: u8) -> u8 {
+        let fourths = match self {
+            Self::Five => 5,
+            Self::Six => 6,
+            Self::Seven => 7,
+            Self::Eight => 8,
+        };
+
+        (fourths * quantizer as u16 / 4).clamp(1, 31) as u8
+    }
+}
+
 /// ITU-T Recommendation H.263 (01/2005), 5.2.x `GN`, `GSBI`, `GFID`, `GQUANT`
 ///
 /// In an H.264-compliant bitstream, each picture is composed of one or more
 /// groups of blocks. The first group of blocks is implied and *not*
 /// transmitted in a compliant bitstream. Sorenson bitstreams treat all
 /// pictures as a single group of blocks, and thus will not use this structure.
+#[derive(Clone, Debug)]
 pub struct GroupOfBlocks {
     /// The GOB number.
     ///
@@ -604,7 +689,7 @@ pub enum Macroblock {
         addl_motion_vectors: Option<[MotionVector; 3]>,
 
         /// ITU-T Recommendation H.263 (01/2005) 5.3.9 `MVDB`
-        motion_vectors_b: Option<[MotionVector; 4]>,
+        motion_vector_b: Option<MotionVector>,
     },
 }
 
@@ -704,6 +789,11 @@ impl HalfPel {
         HalfPel(unit)
     }
 
+    /// Get the value of this half-pel, in half-pixel units.
+    pub fn into_unit(self) -> i16 {
+        self.0
+    }
+
     pub fn zero() -> Self {
         Self(0)
     }
@@ -756,7 +846,7 @@ impl HalfPel {
         let frac = self.0 & 0x0F;
 
         match frac {
-            0 | 1 | 2 => Self(whole),
+            0..=2 => Self(whole),
             14 | 15 => Self(whole + 2),
             _ => Self(whole + 1),
         }
@@ -912,7 +1002,7 @@ impl IntraDc {
     /// This function yields `None` for out-of-range or otherwise
     /// unrepresentable level constants.
     pub fn from_level(value: u16) -> Option<Self> {
-        if (value & 0x07) != 0 || value > 2032 || value < 8 {
+        if (value & 0x07) != 0 || !(8..=2032).contains(&value) {
             return None;
         }
 
@@ -956,3 +1046,28 @@ pub struct TCoefficient {
     /// Sorenson Spark version 1 bitstreams, `LEVEL` is either 7 or 11 bits.
     pub level: i16,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::types::PictureTypeCode;
+
+    #[test]
+    fn picture_type_classification() {
+        let classes = [
+            (PictureTypeCode::IFrame, true, false),
+            (PictureTypeCode::PFrame, false, true),
+            (PictureTypeCode::PbFrame, false, true),
+            (PictureTypeCode::ImprovedPbFrame, false, true),
+            (PictureTypeCode::BFrame, false, true),
+            (PictureTypeCode::EiFrame, true, false),
+            (PictureTypeCode::EpFrame, false, true),
+            (PictureTypeCode::Reserved(7), false, false),
+            (PictureTypeCode::DisposablePFrame, false, true),
+        ];
+
+        for (picture_type, is_intra, is_inter) in classes {
+            assert_eq!(is_intra, picture_type.is_intra(), "{:?}", picture_type);
+            assert_eq!(is_inter, picture_type.is_inter(), "{:?}", picture_type);
+        }
+    }
+}
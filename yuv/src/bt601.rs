@@ -1,41 +1,185 @@
 //! YUV-to-RGB decode
 
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
 // TODO: Replace with `std::simd` when it's stable
 use wide::{i32x4, u8x16};
 
+/// A type of plane sample that can be converted to RGB.
+trait Sample: bytemuck::Pod + Default {
+    /// Expand a single sample, saturating it to the 0..255 range.
+    fn load(self) -> i32;
+
+    /// Expand 4 samples into a i32x4, saturating them to the 0..255 range.
+    fn load_4x(samples: &[Self; 4]) -> i32x4;
+
+    /// Expand 2 samples into a i32x4, duplicating each of them, and
+    /// saturating them to the 0..255 range.
+    fn load_2x_doubled(samples: &[Self; 2]) -> i32x4;
+}
+
+impl Sample for u8 {
+    #[inline]
+    fn load(self) -> i32 {
+        self as i32
+    }
+
+    #[inline]
+    fn load_4x(s: &[u8; 4]) -> i32x4 {
+        i32x4::from([s[0] as i32, s[1] as i32, s[2] as i32, s[3] as i32])
+    }
+
+    #[inline]
+    fn load_2x_doubled(s: &[u8; 2]) -> i32x4 {
+        i32x4::from([s[0] as i32, s[0] as i32, s[1] as i32, s[1] as i32])
+    }
+}
+
+impl Sample for i16 {
+    #[inline]
+    fn load(self) -> i32 {
+        (self as i32).clamp(0, 255)
+    }
+
+    #[inline]
+    fn load_4x(s: &[i16; 4]) -> i32x4 {
+        i32x4::from([s[0] as i32, s[1] as i32, s[2] as i32, s[3] as i32])
+            .max(i32x4::ZERO)
+            .min(i32x4::splat(255))
+    }
+
+    #[inline]
+    fn load_2x_doubled(s: &[i16; 2]) -> i32x4 {
+        i32x4::from([s[0] as i32, s[0] as i32, s[1] as i32, s[1] as i32])
+            .max(i32x4::ZERO)
+            .min(i32x4::splat(255))
+    }
+}
+
+/// The matrix used to derive the color difference signals of a YUV picture
+/// from its RGB primaries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMatrix {
+    /// ITU-R BT.601, as used by H.263 and standard-definition video.
+    Bt601,
+
+    /// ITU-R BT.709, as used by high-definition video.
+    Bt709,
+}
+
+/// The range of sample values that represent black through white (or full
+/// color difference) in a YUV picture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Range {
+    /// Studio ("TV") range, where luma runs from 16 to 235, and chroma from
+    /// 16 to 240. This is what H.263 uses.
+    Studio,
+
+    /// Full ("PC" or JPEG) range, where every sample runs from 0 to 255.
+    Full,
+}
+
+/// The constants of a YUV-to-RGB conversion, in 16.16 fixed point.
+struct Coefficients {
+    y_offset: i32,
+    gray: i32,
+    cr2r: i32,
+    cr2g: i32,
+    cb2g: i32,
+    cb2b: i32,
+}
+
+// The magic numbers are the coefficients converted to 16.16 fixed point, and rounded.
+// They also include the extension from reduced (16..235 and 16...240) to full-range (0..255).
+const BT601: Coefficients = Coefficients {
+    y_offset: 16,
+    gray: 76309,  // 76309 == round((255.0 / 219.0) * 65536.0)
+    cr2r: 104597, // 104597 == round((255.0 / 224.0) * 1.402 * 65536.0)
+    cr2g: -53279, // -53279 == round(-(255.0 / 224.0) * 1.402 * (0.299 / 0.587) * 65536.0)
+    cb2g: -25675, // -25675 == round(-(255.0 / 224.0) * 1.772 * (0.114 / 0.587) * 65536.0)
+    cb2b: 132201, // 132201 == round((255.0 / 224.0) * 1.772 * 65536.0)
+};
+
+// The same as above, with Kr = 0.2126 and Kb = 0.0722, so Kg = 0.7152.
+const BT709: Coefficients = Coefficients {
+    y_offset: 16,
+    gray: 76309,  // 76309 == round((255.0 / 219.0) * 65536.0)
+    cr2r: 117489, // 117489 == round((255.0 / 224.0) * 1.5748 * 65536.0)
+    cr2g: -34925, // -34925 == round(-(255.0 / 224.0) * 1.5748 * (0.2126 / 0.7152) * 65536.0)
+    cb2g: -13975, // -13975 == round(-(255.0 / 224.0) * 1.8556 * (0.0722 / 0.7152) * 65536.0)
+    cb2b: 138438, // 138438 == round((255.0 / 224.0) * 1.8556 * 65536.0)
+};
+
+// Full-range input needs no extension, so these are just the plain
+// coefficients, and luma has no offset to undo.
+const BT601_FULL: Coefficients = Coefficients {
+    y_offset: 0,
+    gray: 65536,  // 65536 == 1.0 * 65536.0
+    cr2r: 91881,  // 91881 == round(1.402 * 65536.0)
+    cr2g: -46802, // -46802 == round(-1.402 * (0.299 / 0.587) * 65536.0)
+    cb2g: -22553, // -22553 == round(-1.772 * (0.114 / 0.587) * 65536.0)
+    cb2b: 116130, // 116130 == round(1.772 * 65536.0)
+};
+
+const BT709_FULL: Coefficients = Coefficients {
+    y_offset: 0,
+    gray: 65536,  // 65536 == 1.0 * 65536.0
+    cr2r: 103206, // 103206 == round(1.5748 * 65536.0)
+    cr2g: -30679, // -30679 == round(-1.5748 * (0.2126 / 0.7152) * 65536.0)
+    cb2g: -12276, // -12276 == round(-1.8556 * (0.0722 / 0.7152) * 65536.0)
+    cb2b: 121609, // 121609 == round(1.8556 * 65536.0)
+};
+
+impl ColorMatrix {
+    fn coefficients(self, range: Range) -> &'static Coefficients {
+        match (self, range) {
+            (ColorMatrix::Bt601, Range::Studio) => &BT601,
+            (ColorMatrix::Bt709, Range::Studio) => &BT709,
+            (ColorMatrix::Bt601, Range::Full) => &BT601_FULL,
+            (ColorMatrix::Bt709, Range::Full) => &BT709_FULL,
+        }
+    }
+}
+
+/// This is 0.5 in 16.16 format, added to make the rightshift round correctly.
+const HALF: i32 = 32768;
+
 // Operates on 4 pixels at a time, one pixel per SIMD lane,
 // with 32 bits of intermediate per-component precision for
 // each, so as to fill the 128-bit SIMD registers on WASM.
-// And i32x4 also allows the neat transpose trick at the end.
-// The output is an interleaved array of 4 RGBA pixels.
+// The output is the R, G and B components of the 4 pixels in 16.16 fixed
+// point, not yet rounded or clamped.
 #[inline]
-fn yuv_to_rgba_4x(yuv: (&[u8; 4], &[u8; 2], &[u8; 2]), rgba: &mut [u8; 16]) {
+fn yuv_to_fixed_4x(yuv: (i32x4, i32x4, i32x4), coefficients: &Coefficients) -> [i32x4; 3] {
     let (y, cb, cr) = yuv;
 
-    // Expanding the 4 bytes into a i32x4, and duplicating chroma samples horizontally.
-    // The -16 and -128 are simply undoing the offsets in the input representation.
-    let y = i32x4::from([y[0] as i32, y[1] as i32, y[2] as i32, y[3] as i32]) - i32x4::splat(16);
-    let cb =
-        i32x4::from([cb[0] as i32, cb[0] as i32, cb[1] as i32, cb[1] as i32]) - i32x4::splat(128);
-    let cr =
-        i32x4::from([cr[0] as i32, cr[0] as i32, cr[1] as i32, cr[1] as i32]) - i32x4::splat(128);
-
-    // The rest of the magic numbers are the coefficients converted to 16.16 fixed point, and rounded.
-    // They also include the extension from reduced (16..235 and 16...240) to full-range (0..255).
-    let gray = y * i32x4::splat(76309); // 76309 == round((255.0 / 219.0) * 65536.0)
-    let cr2r = cr * i32x4::splat(104597); // 104597 == round((255.0 / 224.0) * 1.402 * 65536.0)
-    let cr2g = cr * i32x4::splat(-53279); // -53279 == round(-(255.0 / 224.0) * 1.402 * (0.299 / 0.587) * 65536.0)
-    let cb2g = cb * i32x4::splat(-25675); // -25675 == round(-(255.0 / 224.0) * 1.772 * (0.114 / 0.587) * 65536.0)
-    let cb2b = cb * i32x4::splat(132201); // 132201 == round((255.0 / 224.0) * 1.772 * 65536.0)
-
-    // This is 0.5 in 16.16 format, added to make the rightshift round correctly
-    let half = i32x4::splat(32768);
+    // The -16 (or 0) and -128 are simply undoing the offsets in the input representation.
+    let y = y - i32x4::splat(coefficients.y_offset);
+    let cb = cb - i32x4::splat(128);
+    let cr = cr - i32x4::splat(128);
+
+    let gray = y * i32x4::splat(coefficients.gray);
+    let cr2r = cr * i32x4::splat(coefficients.cr2r);
+    let cr2g = cr * i32x4::splat(coefficients.cr2g);
+    let cb2g = cb * i32x4::splat(coefficients.cb2g);
+    let cb2b = cb * i32x4::splat(coefficients.cb2b);
+
+    [gray + cr2r, gray + cr2g + cb2g, gray + cb2b]
+}
+
+// Rounds the output of `yuv_to_fixed_4x` to 8 bits, with `bias` added to
+// each fixed point component before the fraction is cut off. i32x4 also
+// allows the neat transpose trick at the end.
+// The output is an interleaved array of 4 RGBA pixels.
+#[inline]
+fn round_4x(rgb: [i32x4; 3], bias: i32x4) -> [u8; 16] {
+    let [r, g, b] = rgb;
 
     // We could skip the shift here, then simply cast the result into [u8; 16], and take
     // bytes 2, 4, 10, 14 instead (after clamping), but it's not any faster, it seems.
-    let r: i32x4 = (gray + cr2r + half) >> 16;
-    let g: i32x4 = (gray + cr2g + cb2g + half) >> 16;
-    let b: i32x4 = (gray + cb2b + half) >> 16;
+    let r: i32x4 = (r + bias) >> 16;
+    let g: i32x4 = (g + bias) >> 16;
+    let b: i32x4 = (b + bias) >> 16;
 
     // Clamping to the valid output range
     // A simple clamp(x, 0, 255) doesn't work, because it seems to
@@ -55,22 +199,45 @@ fn yuv_to_rgba_4x(yuv: (&[u8; 4], &[u8; 2], &[u8; 2]), rgba: &mut [u8; 16]) {
     #[cfg(target_endian = "big")] // I haven't tested this, but should work
     let rgba_4x = ((r << 24) | (g << 16)) | ((b << 8) | (a));
 
-    rgba.copy_from_slice(bytemuck::cast::<i32x4, u8x16>(rgba_4x).as_array_ref())
+    *bytemuck::cast::<i32x4, u8x16>(rgba_4x).as_array_ref()
+}
+
+// The single-pixel versions of the above, for the scalar conversion loop.
+#[inline]
+fn yuv_to_fixed(yuv: (i32, i32, i32), coefficients: &Coefficients) -> [i32; 3] {
+    let (y, cb, cr) = yuv;
+    let y = y - coefficients.y_offset;
+    let cb = cb - 128;
+    let cr = cr - 128;
+
+    let gray = y * coefficients.gray;
+    [
+        gray + cr * coefficients.cr2r,
+        gray + cr * coefficients.cr2g + cb * coefficients.cb2g,
+        gray + cb * coefficients.cb2b,
+    ]
+}
+
+#[inline]
+fn round(rgb: [i32; 3], bias: i32) -> [u8; 4] {
+    let round = |channel: i32| ((channel + bias) >> 16).clamp(0, 255) as u8;
+
+    [round(rgb[0]), round(rgb[1]), round(rgb[2]), 255]
 }
 
 // A single-pixel version, only for testing.
 #[cfg(test)]
 #[inline]
 fn yuv_to_rgb(yuv: (u8, u8, u8)) -> (u8, u8, u8) {
-    let mut rgba_4x = [0u8; 16];
-    yuv_to_rgba_4x(
+    let fixed = yuv_to_fixed_4x(
         (
-            &[yuv.0, yuv.0, yuv.0, yuv.0],
-            &[yuv.1, yuv.1],
-            &[yuv.2, yuv.2],
+            u8::load_4x(&[yuv.0, yuv.0, yuv.0, yuv.0]),
+            u8::load_2x_doubled(&[yuv.1, yuv.1]),
+            u8::load_2x_doubled(&[yuv.2, yuv.2]),
         ),
-        &mut rgba_4x,
+        ColorMatrix::Bt601.coefficients(Range::Studio),
     );
+    let rgba_4x = round_4x(fixed, i32x4::splat(HALF));
 
     // all output pixels should be the same
     assert!(rgba_4x[3] == 255);
@@ -87,87 +254,1472 @@ fn yuv_to_rgb(yuv: (u8, u8, u8)) -> (u8, u8, u8) {
     assert!(rgba_4x[14] == rgba_4x[2]);
     assert!(rgba_4x[15] == 255);
 
-    (rgba_4x[0] as u8, rgba_4x[1] as u8, rgba_4x[2] as u8)
+    (rgba_4x[0], rgba_4x[1], rgba_4x[2])
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data.
+///
+/// This function yields an RGBA picture with the same number of pixels as were
+/// provided in the `y` picture. The `chroma_b` and `chroma_r` samples are
+/// simply reused without any interpolation for all four corresponding pixels.
+/// This is not the most correct, or nicest, but it's what Flash Player does.
+///
+/// Preconditions:
+///  - `y.len()` must be an integer multiple of `y_width`
+///  - `chroma_b.len()` and `chroma_r.len()` must both be integer multiples of `br_width`
+///  - `chroma_b` and `chroma_r` must be the same size
+///  - `br_width` must be half of `y_width`, rounded up
+///  - With `y_height` computed as `y.len() / y_width`, and `br_height` as `chroma_b.len() / br_width`:
+///    `br_height` must be half of `y_height`, rounded up
+///
+/// The other converters of planar YUV 4:2:0 data in this module take their
+/// planes in the same way, and share these preconditions.
+pub fn yuv420_to_rgba(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv420_to_rgba_into(y, chroma_b, chroma_r, y_width, br_width, &mut rgba)
+        .expect("buffer was allocated with the right size");
+
+    rgba
+}
+
+/// An output buffer that cannot hold the converted picture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SizeError {
+    /// How many bytes the output buffer needed to hold.
+    pub expected: usize,
+
+    /// How many bytes the output buffer actually held.
+    pub actual: usize,
+}
+
+impl core::fmt::Display for SizeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "output buffer holds {} bytes, but {} are needed",
+            self.actual, self.expected
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SizeError {}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, writing
+/// into a caller-provided buffer.
+///
+/// This allows the same buffer to be reused for every picture of a video.
+/// `rgba` must be exactly `y.len() * 4` bytes long; otherwise, nothing is
+/// written, and an error is yielded. In all other respects, this behaves
+/// exactly like [`yuv420_to_rgba`].
+pub fn yuv420_to_rgba_into(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+    rgba: &mut [u8],
+) -> Result<(), SizeError> {
+    if rgba.len() != y.len() * 4 {
+        return Err(SizeError {
+            expected: y.len() * 4,
+            actual: rgba.len(),
+        });
+    }
+
+    yuv420_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        rgba,
+        y_width * 4,
+    );
+
+    Ok(())
+}
+
+/// How many rows of luma each task of `yuv420_to_rgba_parallel` converts.
+///
+/// This must be even, so that every band starts on a new row of chroma.
+#[cfg(feature = "rayon")]
+const PARALLEL_BAND_ROWS: usize = 16;
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, spreading
+/// the work across the rayon thread pool.
+///
+/// The picture is split into horizontal bands, which are converted
+/// independently of one another. The output is exactly the same as that of
+/// [`yuv420_to_rgba`].
+#[cfg(feature = "rayon")]
+pub fn yuv420_to_rgba_parallel(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    use rayon::prelude::*;
+
+    let mut rgba = vec![0; y.len() * 4];
+    if y.is_empty() {
+        return rgba;
+    }
+
+    rgba.par_chunks_mut(PARALLEL_BAND_ROWS * y_width * 4)
+        .zip(y.par_chunks(PARALLEL_BAND_ROWS * y_width))
+        .zip(chroma_b.par_chunks(PARALLEL_BAND_ROWS / 2 * br_width))
+        .zip(chroma_r.par_chunks(PARALLEL_BAND_ROWS / 2 * br_width))
+        .for_each(|(((rgba, y), chroma_b), chroma_r)| {
+            yuv420_to_rgba_with_stride(
+                &Rgba,
+                ColorMatrix::Bt601,
+                Range::Studio,
+                y,
+                chroma_b,
+                chroma_r,
+                y_width,
+                br_width,
+                rgba,
+                y_width * 4,
+            );
+        });
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:0 data with padded rows into interleaved RGBA 8888
+/// data.
+///
+/// Rather than being tightly packed, each row of `y` starts `y_stride`
+/// samples after the previous one, and each row of `chroma_b` and `chroma_r`
+/// starts `chroma_stride` samples after the previous one. Only the first
+/// `width` samples of each of the `height` luma rows are converted, along with
+/// the first `width / 2` samples (rounded up) of each of the `height / 2`
+/// chroma rows (also rounded up), so padding is never read. The result is
+/// tightly packed, and exactly the same as that of `yuv420_to_rgba` on the
+/// same planes without padding.
+///
+/// # Panics
+///
+/// If `width` is more than `y_stride`, half of `width` (rounded up) is more
+/// than `chroma_stride`, or any of the planes is too short to hold all of its
+/// rows.
+pub fn yuv420_to_rgba_strided(
+    y: &[u8],
+    y_stride: usize,
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    chroma_stride: usize,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let br_width = width.div_ceil(2);
+    let br_height = height.div_ceil(2);
+    assert!(width <= y_stride, "luma rows are longer than their stride");
+    assert!(
+        br_width <= chroma_stride,
+        "chroma rows are longer than their stride"
+    );
+
+    let mut rgba = vec![0; width * height * 4];
+    if width == 0 || height == 0 {
+        return rgba;
+    }
+
+    assert!(
+        y.len() >= y_stride * (height - 1) + width,
+        "luma plane is too short"
+    );
+    for chroma in [chroma_b, chroma_r] {
+        assert!(
+            chroma.len() >= chroma_stride * (br_height - 1) + br_width,
+            "chroma plane is too short"
+        );
+    }
+
+    // Each row is converted on its own, against the single row of chroma it
+    // uses, so that the planes never have to be repacked.
+    for (rowindex, rgba_row) in rgba.chunks_mut(width * 4).enumerate() {
+        let chroma_start = rowindex / 2 * chroma_stride;
+        yuv_to_rgba_with_stride(
+            &Rgba,
+            ColorMatrix::Bt601,
+            Range::Studio,
+            &Planes {
+                y: &y[rowindex * y_stride..][..width],
+                chroma_b: &chroma_b[chroma_start..][..br_width],
+                chroma_r: &chroma_r[chroma_start..][..br_width],
+                y_width: width,
+                br_width,
+                horizontal_subsampling: true,
+                vertical_subsampling: false,
+                chroma_interleaved: false,
+                luma_lut: None,
+            },
+            rgba_row,
+            width * 4,
+        );
+    }
+
+    rgba
+}
+
+/// Convert semi-planar YUV 4:2:0 data, as in NV12, into interleaved RGBA 8888
+/// data.
+///
+/// This behaves like `yuv420_to_rgba`, except that the chroma samples are
+/// given in a single `uv` plane, where each Cb sample is immediately followed
+/// by the Cr sample at the same position.
+///
+/// Preconditions:
+///  - `y.len()` must be an integer multiple of `y_width`
+///  - `uv.len()` must be an integer multiple of the `uv` row length, which is
+///    `y_width` rounded up to an even number
+///  - With `y_height` computed as `y.len() / y_width`, and `uv_height` as
+///    `uv.len()` divided by the `uv` row length: `uv_height` must be half of
+///    `y_height`, rounded up
+pub fn nv12_to_rgba(y: &[u8], uv: &[u8], y_width: usize) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+
+    yuv_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        &Planes {
+            y,
+            chroma_b: uv,
+            chroma_r: uv,
+            y_width,
+            br_width: y_width.div_ceil(2),
+            horizontal_subsampling: true,
+            vertical_subsampling: true,
+            chroma_interleaved: true,
+            luma_lut: None,
+        },
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:2 data into interleaved RGBA 8888 data.
+///
+/// This behaves like `yuv420_to_rgba`, except that the chroma planes are the
+/// full height of the `y` picture, so each chroma sample is only reused for
+/// the two horizontally adjacent pixels of its own row.
+///
+/// Preconditions:
+///  - `y.len()` must be an integer multiple of `y_width`
+///  - `chroma_b.len()` and `chroma_r.len()` must both be integer multiples of `br_width`
+///  - `chroma_b` and `chroma_r` must be the same size
+///  - `br_width` must be half of `y_width`, rounded up
+///  - With `y_height` computed as `y.len() / y_width`, and `br_height` as `chroma_b.len() / br_width`:
+///    `br_height` must be equal to `y_height`
+pub fn yuv422_to_rgba(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        &Planes {
+            y,
+            chroma_b,
+            chroma_r,
+            y_width,
+            br_width,
+            horizontal_subsampling: true,
+            vertical_subsampling: false,
+            chroma_interleaved: false,
+            luma_lut: None,
+        },
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Convert planar YUV 4:4:4 data into interleaved RGBA 8888 data.
+///
+/// Every pixel has its own chroma samples, so none are reused.
+///
+/// Preconditions:
+///  - `y.len()` must be an integer multiple of `y_width`
+///  - `chroma_b` and `chroma_r` must both be the same size as `y`
+///  - `br_width` must be equal to `y_width`
+pub fn yuv444_to_rgba(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    debug_assert_eq!(chroma_b.len(), y.len());
+    debug_assert_eq!(chroma_r.len(), y.len());
+
+    let mut rgba = vec![0; y.len() * 4];
+    yuv_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        &Planes {
+            y,
+            chroma_b,
+            chroma_r,
+            y_width,
+            br_width,
+            horizontal_subsampling: false,
+            vertical_subsampling: false,
+            chroma_interleaved: false,
+            luma_lut: None,
+        },
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved BGRA 8888 data.
+///
+/// This stores blue first and red third in each pixel, as some texture
+/// formats expect, with alpha still fixed at 255. In all other respects, this
+/// behaves exactly like [`yuv420_to_rgba`].
+pub fn yuv420_to_bgra(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut bgra = vec![0; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &Bgra,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut bgra,
+        y_width * 4,
+    );
+
+    bgra
+}
+
+/// Convert planar YUV 4:2:0 data into tightly packed RGB 888 data.
+///
+/// This is [`yuv420_to_rgba`] with 3 bytes per pixel, without the fixed alpha
+/// channel, for consumers that have no use for it.
+pub fn yuv420_to_rgb24(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgb = vec![0; y.len() * 3];
+    yuv420_to_rgba_with_stride(
+        &Rgb,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgb,
+        y_width * 3,
+    );
+
+    rgb
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, with a
+/// constant alpha.
+///
+/// This is [`yuv420_to_rgba`] with every pixel given `alpha` instead of being
+/// opaque, and with the color channels also scaled by it if `premultiply` is
+/// set.
+pub fn yuv420_to_rgba_with_alpha(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+    alpha: u8,
+    premultiply: bool,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &RgbaWithAlpha { alpha, premultiply },
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, using the
+/// given color matrix and sample range.
+///
+/// This is [`yuv420_to_rgba`] with samples interpreted according to `matrix`
+/// and `range`, instead of always as studio range BT.601.
+pub fn yuv420_to_rgba_with(
+    matrix: ColorMatrix,
+    range: Range,
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &Rgba,
+        matrix,
+        range,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Convert the luma plane of a picture with neutral chroma into interleaved
+/// RGBA 8888 data.
+///
+/// This yields exactly the same output as `yuv420_to_rgba` would if both
+/// chroma planes held nothing but 128, which is the case for grayscale video.
+/// Without any color difference to apply, each luma sample is only looked up
+/// once in a table, and copied to the red, green and blue channels.
+///
+/// Preconditions:
+///  - `y.len()` must be an integer multiple of `y_width`
+pub fn yuv_gray_to_rgba(y: &[u8], y_width: usize) -> Vec<u8> {
+    debug_assert!(y.is_empty() || y.len().is_multiple_of(y_width));
+
+    let coefficients = ColorMatrix::Bt601.coefficients(Range::Studio);
+    let mut lut = [0; 256];
+    for (luma, gray) in lut.iter_mut().enumerate() {
+        let value = ((luma as i32 - coefficients.y_offset) * coefficients.gray + 32768) >> 16;
+        *gray = value.clamp(0, 255) as u8;
+    }
+
+    let mut rgba = vec![255; y.len() * 4];
+    for (&luma, out) in y.iter().zip(rgba.chunks_exact_mut(4)) {
+        let gray = lut[luma as usize];
+        out[..3].copy_from_slice(&[gray, gray, gray]);
+    }
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA data with 16 bits per
+/// channel.
+///
+/// This is [`yuv420_to_rgba`] keeping the fractional bits of each channel,
+/// which is scaled so that 0 and 65535 correspond to 0 and 255 of the 8-bit
+/// conversion, with alpha always 65535.
+pub fn yuv420_to_rgba16(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u16> {
+    let mut rgba = vec![0u16; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &Rgba16,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        bytemuck::cast_slice_mut(&mut rgba),
+        y_width * Rgba16::BYTES_PER_PIXEL,
+    );
+
+    rgba
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA data, with an ordered
+/// dither.
+///
+/// This is [`yuv420_to_rgba`] rounding each channel with a threshold from a
+/// 4x4 Bayer matrix tiled over the picture, which breaks up the banding of
+/// smooth gradients with a fixed, reproducible pattern.
+///
+/// Every output channel is within one of what `yuv420_to_rgba` returns.
+pub fn yuv420_to_rgba_dithered(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &Dithered,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// A tone curve to apply to luma while converting, with
+/// `yuv420_to_rgba_adjusted`.
+///
+/// Luma samples are normalized to the range 0 to 1 over the full 8-bit
+/// range, then raised to the power of `gamma`, then scaled by `contrast`
+/// around the midpoint, and finally offset by `brightness` 8-bit steps.
+/// The default leaves all samples unchanged.
+///
+/// This needs the `std` feature, for floating-point exponentiation.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneAdjust {
+    /// The exponent to apply; values above 1 darken the midtones, and values
+    /// below 1 brighten them.
+    pub gamma: f32,
+
+    /// The offset to add to each adjusted sample.
+    pub brightness: i16,
+
+    /// The factor to scale each sample's distance from mid-gray by.
+    pub contrast: f32,
+}
+
+#[cfg(feature = "std")]
+impl Default for ToneAdjust {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 0,
+            contrast: 1.0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ToneAdjust {
+    /// Tabulate the adjusted value of every 8-bit luma sample.
+    fn lookup_table(&self) -> [u8; 256] {
+        let mut lut = [0; 256];
+
+        for (sample, adjusted) in lut.iter_mut().enumerate() {
+            let value = (sample as f32 / 255.0).powf(self.gamma);
+            let value = (value - 0.5) * self.contrast + 0.5;
+            let value = (value * 255.0).round() + self.brightness as f32;
+
+            *adjusted = value.clamp(0.0, 255.0) as u8;
+        }
+
+        lut
+    }
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, adjusting
+/// the tone of luma beforehand.
+///
+/// This is [`yuv420_to_rgba`] with every luma sample first mapped through the
+/// tone curve given by `adjust`, if any.
+///
+/// The curve is tabulated once per call, so the luma plane is never copied.
+#[cfg(feature = "std")]
+pub fn yuv420_to_rgba_adjusted(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+    adjust: Option<ToneAdjust>,
+) -> Vec<u8> {
+    let adjust = match adjust {
+        Some(adjust) => adjust,
+        None => return yuv420_to_rgba(y, chroma_b, chroma_r, y_width, br_width),
+    };
+
+    let lut = adjust.lookup_table();
+    let mut rgba = vec![0; y.len() * 4];
+    yuv_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        &Planes {
+            y,
+            chroma_b,
+            chroma_r,
+            y_width,
+            br_width,
+            horizontal_subsampling: true,
+            vertical_subsampling: true,
+            chroma_interleaved: false,
+            luma_lut: Some(&lut),
+        },
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// Upsample a row of chroma samples to the resolution of luma, blending
+/// each one into its neighbors.
+///
+/// Chroma samples sit between their four luma samples, so each luma sample
+/// takes 3/4 of its own chroma sample and 1/4 of the next one over, both
+/// horizontally and vertically. Samples past the edges of the plane are
+/// clamped to the nearest one.
+fn interpolate_chroma_row(chroma: &[u8], br_width: usize, luma_rowindex: usize, row: &mut [u8]) {
+    let br_height = chroma.len() / br_width;
+    let near_rowindex = luma_rowindex / 2;
+    let far_rowindex = if luma_rowindex.is_multiple_of(2) {
+        near_rowindex.saturating_sub(1)
+    } else {
+        (near_rowindex + 1).min(br_height - 1)
+    };
+
+    let near_row = &chroma[near_rowindex * br_width..(near_rowindex + 1) * br_width];
+    let far_row = &chroma[far_rowindex * br_width..(far_rowindex + 1) * br_width];
+    let blended = |x: usize| 3 * near_row[x] as u16 + far_row[x] as u16;
+
+    for (x, sample) in row.iter_mut().enumerate() {
+        let near_x = x / 2;
+        let far_x = if x.is_multiple_of(2) {
+            near_x.saturating_sub(1)
+        } else {
+            (near_x + 1).min(br_width - 1)
+        };
+
+        *sample = ((3 * blended(near_x) + blended(far_x) + 8) / 16) as u8;
+    }
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data,
+/// interpolating chroma bilinearly.
+///
+/// This is [`yuv420_to_rgba`] with chroma smoothly upsampled across each 2x2
+/// group of pixels instead of being reused for all four. This reduces chroma
+/// blockiness on gradients, but does not match what Flash Player displays.
+pub fn yuv420_to_rgba_interpolated(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    if y.is_empty() {
+        return rgba;
+    }
+
+    debug_assert_eq!(y.len() % y_width, 0);
+    debug_assert_eq!(chroma_b.len(), chroma_r.len());
+    debug_assert_eq!(y_width.div_ceil(2), br_width);
+
+    // Each row of chroma is interpolated up to the full resolution of luma,
+    // then converted along with its row of luma as 4:4:4.
+    let mut cb_row = vec![0; y_width];
+    let mut cr_row = vec![0; y_width];
+
+    for (luma_rowindex, (y_row, rgba_row)) in y
+        .chunks_exact(y_width)
+        .zip(rgba.chunks_exact_mut(y_width * 4))
+        .enumerate()
+    {
+        interpolate_chroma_row(chroma_b, br_width, luma_rowindex, &mut cb_row);
+        interpolate_chroma_row(chroma_r, br_width, luma_rowindex, &mut cr_row);
+
+        yuv_to_rgba_with_stride(
+            &Rgba,
+            ColorMatrix::Bt601,
+            Range::Studio,
+            &Planes {
+                y: y_row,
+                chroma_b: &cb_row,
+                chroma_r: &cr_row,
+                y_width,
+                br_width: y_width,
+                horizontal_subsampling: false,
+                vertical_subsampling: false,
+                chroma_interleaved: false,
+                luma_lut: None,
+            },
+            rgba_row,
+            y_width * 4,
+        );
+    }
+
+    rgba
+}
+
+/// A plane of a YUV picture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Plane {
+    Luma,
+    ChromaB,
+    ChromaR,
+}
+
+/// A sample that holds one of the values BT.601 reserves for timing
+/// references, and which therefore cannot be a valid picture sample.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReservedValue {
+    /// The plane containing the sample.
+    pub plane: Plane,
+
+    /// The index of the sample within its plane.
+    pub index: usize,
+
+    /// The value of the sample: either 0 or 255.
+    pub value: u8,
+}
+
+impl core::fmt::Display for ReservedValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "reserved value {} in {:?} sample {}",
+            self.value, self.plane, self.index
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReservedValue {}
+
+/// Determine if a sample holds a value BT.601 reserves for timing references.
+fn is_reserved(value: u8) -> bool {
+    value == 0 || value == 255
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, checking
+/// for reserved sample values.
+///
+/// BT.601 reserves the values 0 and 255 in every plane. If `validate_range`
+/// is true, the first sample found holding one of them is yielded as an
+/// error. Otherwise, such samples are clamped to the nearest valid value (1
+/// or 254) before conversion. In all other respects, this behaves exactly
+/// like [`yuv420_to_rgba`].
+pub fn yuv420_to_rgba_checked(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+    validate_range: bool,
+) -> Result<Vec<u8>, ReservedValue> {
+    let planes = [
+        (Plane::Luma, y),
+        (Plane::ChromaB, chroma_b),
+        (Plane::ChromaR, chroma_r),
+    ];
+
+    if validate_range {
+        for (plane, samples) in planes.iter() {
+            if let Some(index) = samples.iter().position(|&v| is_reserved(v)) {
+                return Err(ReservedValue {
+                    plane: *plane,
+                    index,
+                    value: samples[index],
+                });
+            }
+        }
+    } else if planes
+        .iter()
+        .any(|(_, samples)| samples.iter().any(|&v| is_reserved(v)))
+    {
+        let clamp =
+            |samples: &[u8]| -> Vec<u8> { samples.iter().map(|&v| v.clamp(1, 254)).collect() };
+
+        return Ok(yuv420_to_rgba(
+            &clamp(y),
+            &clamp(chroma_b),
+            &clamp(chroma_r),
+            y_width,
+            br_width,
+        ));
+    }
+
+    Ok(yuv420_to_rgba(y, chroma_b, chroma_r, y_width, br_width))
+}
+
+/// Convert planar YUV 4:2:0 data with 16-bit samples into interleaved RGBA
+/// 8888 data.
+///
+/// This is intended for reconstruction kept at a higher precision, such as
+/// before an in-loop filter. Each sample is saturated to the 0..255 range as
+/// it is loaded, so this yields the same result as clamping every sample to
+/// `u8` and calling [`yuv420_to_rgba`], without a separate clamping pass.
+pub fn yuv420_i16_to_rgba(
+    y: &[i16],
+    chroma_b: &[i16],
+    chroma_r: &[i16],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    let mut rgba = vec![0; y.len() * 4];
+    yuv420_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgba,
+        y_width * 4,
+    );
+
+    rgba
+}
+
+/// The row alignment, in bytes, that `wgpu` requires of texture uploads.
+const WGPU_ROW_ALIGNMENT: usize = 256;
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, with rows
+/// padded for uploading with `wgpu`'s `Queue::write_texture`.
+///
+/// This yields the RGBA picture along with the number of bytes per row, which
+/// is the unpadded row size rounded up to a multiple of 256. The padding at
+/// the end of each row is zero-filled. Otherwise, this behaves exactly like
+/// [`yuv420_to_rgba`].
+pub fn yuv420_to_rgba_wgpu(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> (Vec<u8>, u32) {
+    let bytes_per_row = (y_width * 4).div_ceil(WGPU_ROW_ALIGNMENT) * WGPU_ROW_ALIGNMENT;
+    let y_height = y.len().checked_div(y_width).unwrap_or(0);
+
+    let mut rgba = vec![0; bytes_per_row * y_height];
+    yuv420_to_rgba_with_stride(
+        &Rgba,
+        ColorMatrix::Bt601,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        &mut rgba,
+        bytes_per_row,
+    );
+
+    (rgba, bytes_per_row as u32)
+}
+
+/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data, writing
+/// each row `rgba_stride` bytes after the previous one.
+///
+/// The pixels are written in the byte order given by `L`. `rgba` must hold at
+/// least `y_height` rows of `rgba_stride` bytes each, and `rgba_stride` must be
+/// at least `y_width` pixels long. Bytes between the end of each converted row
+/// and the start of the next are left untouched.
+#[allow(clippy::too_many_arguments)]
+fn yuv420_to_rgba_with_stride<T: Sample, L: Layout>(
+    layout: &L,
+    matrix: ColorMatrix,
+    range: Range,
+    y: &[T],
+    chroma_b: &[T],
+    chroma_r: &[T],
+    y_width: usize,
+    br_width: usize,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+) {
+    yuv_to_rgba_with_stride(
+        layout,
+        matrix,
+        range,
+        &Planes {
+            y,
+            chroma_b,
+            chroma_r,
+            y_width,
+            br_width,
+            horizontal_subsampling: true,
+            vertical_subsampling: true,
+            chroma_interleaved: false,
+            luma_lut: None,
+        },
+        rgba,
+        rgba_stride,
+    )
+}
+
+/// Convert planar YUV data with any supported subsampling into interleaved
+/// RGBA data, writing each row `rgba_stride` bytes after the previous one.
+///
+/// This has the same requirements as `yuv420_to_rgba_with_stride`, except
+/// that chroma planes are only half the width or height of luma if the planes
+/// are subsampled in that direction.
+fn yuv_to_rgba_with_stride<T: Sample, L: Layout>(
+    layout: &L,
+    matrix: ColorMatrix,
+    range: Range,
+    planes: &Planes<'_, T>,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+) {
+    let Planes {
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        horizontal_subsampling,
+        vertical_subsampling,
+        chroma_interleaved,
+        ..
+    } = *planes;
+
+    // Shortcut for the no-op case to avoid all kinds of overflows below
+    if y.is_empty() {
+        debug_assert_eq!(chroma_b.len(), 0);
+        debug_assert_eq!(chroma_r.len(), 0);
+        debug_assert_eq!(y_width, 0);
+        debug_assert_eq!(br_width, 0);
+        return;
+    }
+
+    let chroma_row_len = if chroma_interleaved {
+        br_width * 2
+    } else {
+        br_width
+    };
+
+    debug_assert_eq!(y.len() % y_width, 0);
+    debug_assert_eq!(chroma_b.len() % chroma_row_len, 0);
+    debug_assert_eq!(chroma_r.len() % chroma_row_len, 0);
+    debug_assert_eq!(chroma_b.len(), chroma_r.len());
+
+    let y_height = y.len() / y_width;
+    let br_height = chroma_b.len() / chroma_row_len;
+
+    // the + 1 is for rounding odd numbers up
+    if horizontal_subsampling {
+        debug_assert_eq!(y_width.div_ceil(2), br_width);
+    } else {
+        debug_assert_eq!(y_width, br_width);
+        debug_assert!(!chroma_interleaved);
+    }
+    if vertical_subsampling {
+        debug_assert_eq!(y_height.div_ceil(2), br_height);
+    } else {
+        debug_assert_eq!(y_height, br_height);
+    }
+
+    let coefficients = matrix.coefficients(range);
+    let rgba_width = y_width * L::BYTES_PER_PIXEL; // 3 or 4 bytes per pixel, interleaved
+    debug_assert!(rgba_stride >= rgba_width);
+    debug_assert!(rgba.len() >= rgba_stride * (y_height - 1) + rgba_width);
+
+    match conversion_path() {
+        ConversionPath::Scalar => {
+            convert_rows_scalar(layout, planes, coefficients, rgba, rgba_stride)
+        }
+        ConversionPath::Simd => convert_rows_simd(layout, planes, coefficients, rgba, rgba_stride),
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        ConversionPath::Avx2 => unsafe {
+            // SAFETY: `conversion_path` only chooses this if the CPU has AVX2.
+            convert_rows_avx2(layout, planes, coefficients, rgba, rgba_stride)
+        },
+    }
+}
+
+/// The planes of a YUV picture, and their row lengths.
+///
+/// The chroma planes are half the width of luma if `horizontal_subsampling`
+/// is set, and the same width otherwise. They are also half its height if
+/// `vertical_subsampling` is set (4:2:0), and the same height otherwise
+/// (4:2:2, or 4:4:4 without either).
+///
+/// If `chroma_interleaved` is set, then both chroma planes are stored in a
+/// single plane of alternating Cb and Cr samples (as in NV12), which is given
+/// as both `chroma_b` and `chroma_r`. Its rows are `2 * br_width` samples
+/// long.
+///
+/// If there is a `luma_lut`, every luma sample is mapped through it as it is
+/// loaded.
+struct Planes<'a, T> {
+    y: &'a [T],
+    chroma_b: &'a [T],
+    chroma_r: &'a [T],
+    y_width: usize,
+    br_width: usize,
+    horizontal_subsampling: bool,
+    vertical_subsampling: bool,
+    chroma_interleaved: bool,
+    luma_lut: Option<&'a [u8; 256]>,
+}
+
+impl<T: Copy> Planes<'_, T> {
+    /// Get the Cb and Cr samples used by the luma sample in column `luma_x`
+    /// of a given row of the chroma planes.
+    #[inline]
+    fn chroma_at(&self, chroma_rowindex: usize, luma_x: usize) -> (T, T) {
+        let x = if self.horizontal_subsampling {
+            luma_x / 2
+        } else {
+            luma_x
+        };
+
+        if self.chroma_interleaved {
+            let index = (chroma_rowindex * self.br_width + x) * 2;
+            (self.chroma_b[index], self.chroma_b[index + 1])
+        } else {
+            let index = chroma_rowindex * self.br_width + x;
+            (self.chroma_b[index], self.chroma_r[index])
+        }
+    }
+
+    /// Get the row of the chroma planes used by a given row of luma.
+    #[inline]
+    fn chroma_rowindex(&self, luma_rowindex: usize) -> usize {
+        if self.vertical_subsampling {
+            luma_rowindex / 2
+        } else {
+            luma_rowindex
+        }
+    }
+}
+
+impl<T: Sample> Planes<'_, T> {
+    /// Expand a single luma sample, mapping it through `luma_lut` if any.
+    #[inline]
+    fn load_luma(&self, sample: T) -> i32 {
+        match self.luma_lut {
+            Some(lut) => lut[sample.load() as usize] as i32,
+            None => sample.load(),
+        }
+    }
+
+    /// Expand 4 luma samples into a i32x4, mapping them through `luma_lut`
+    /// if any.
+    #[inline]
+    fn load_luma_4x(&self, samples: &[T; 4]) -> i32x4 {
+        match self.luma_lut {
+            Some(lut) => i32x4::from(samples.map(|sample| lut[sample.load() as usize] as i32)),
+            None => T::load_4x(samples),
+        }
+    }
+}
+
+/// The most bytes any `Layout` stores each pixel in.
+const MAX_BYTES_PER_PIXEL: usize = 8;
+
+/// A byte order to store converted pixels in.
+///
+/// Pixels are handed over while their components are still in 16.16 fixed
+/// point, along with the position of the pixel in the picture, so that a
+/// layout may also choose how they are rounded. Unless it does, they are
+/// rounded to the nearest 8-bit value, and then stored with `store` or
+/// `store_4x`.
+trait Layout {
+    /// How many bytes each pixel takes up, at most `MAX_BYTES_PER_PIXEL`.
+    const BYTES_PER_PIXEL: usize;
+
+    /// Store a single RGBA pixel into `BYTES_PER_PIXEL` bytes of `out`.
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]);
+
+    /// Store 4 interleaved RGBA pixels into `4 * BYTES_PER_PIXEL` bytes of
+    /// `out`.
+    #[inline]
+    fn store_4x(&self, rgba_4x: &[u8; 16], out: &mut [u8]) {
+        let pixels = bytemuck::cast_slice::<u8, [u8; 4]>(rgba_4x);
+        for (rgba, out) in pixels
+            .iter()
+            .zip(out.chunks_exact_mut(Self::BYTES_PER_PIXEL))
+        {
+            self.store(rgba, out);
+        }
+    }
+
+    /// Round and store the fixed point components of the pixel at `position`,
+    /// given as `(x, y)`, into `BYTES_PER_PIXEL` bytes of `out`.
+    #[inline]
+    fn store_fixed(&self, rgb: [i32; 3], _position: (usize, usize), out: &mut [u8]) {
+        self.store(&round(rgb, HALF), out);
+    }
+
+    /// Round and store the fixed point components of 4 pixels, the first of
+    /// which is at `position`, into `4 * BYTES_PER_PIXEL` bytes of `out`.
+    #[inline]
+    fn store_fixed_4x(&self, rgb: [i32x4; 3], _position: (usize, usize), out: &mut [u8]) {
+        self.store_4x(&round_4x(rgb, i32x4::splat(HALF)), out);
+    }
+}
+
+/// Interleaved RGBA 8888, as converted.
+struct Rgba;
+
+impl Layout for Rgba {
+    const BYTES_PER_PIXEL: usize = 4;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(rgba);
+    }
+
+    #[inline]
+    fn store_4x(&self, rgba_4x: &[u8; 16], out: &mut [u8]) {
+        out.copy_from_slice(rgba_4x);
+    }
+}
+
+/// Interleaved RGBA 8888, with a constant alpha.
+///
+/// If `premultiply` is set, then the color channels are also scaled by the
+/// alpha.
+struct RgbaWithAlpha {
+    alpha: u8,
+    premultiply: bool,
+}
+
+impl Layout for RgbaWithAlpha {
+    const BYTES_PER_PIXEL: usize = 4;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        let alpha = self.alpha as u32;
+        let scale = |channel: u8| {
+            if self.premultiply {
+                ((channel as u32 * alpha + 127) / 255) as u8
+            } else {
+                channel
+            }
+        };
+
+        out.copy_from_slice(&[scale(rgba[0]), scale(rgba[1]), scale(rgba[2]), self.alpha]);
+    }
+}
+
+/// Interleaved BGRA 8888, with red and blue swapped.
+struct Bgra;
+
+impl Layout for Bgra {
+    const BYTES_PER_PIXEL: usize = 4;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+    }
+}
+
+/// Tightly packed RGB 888, dropping alpha.
+struct Rgb;
+
+impl Layout for Rgb {
+    const BYTES_PER_PIXEL: usize = 3;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(&rgba[..3]);
+    }
+}
+
+/// The 4x4 Bayer matrix, giving each position in a tile a distinct threshold.
+const BAYER_4X4: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Interleaved RGBA, with each channel rounded by an ordered dither.
+struct Dithered;
+
+impl Dithered {
+    /// The threshold of the pixel at `(x, y)` in 16.16 format, which takes the
+    /// place of the 0.5 added for plain rounding. Thresholds lie between 1/32
+    /// and 31/32, and average to 0.5 over a tile.
+    #[inline]
+    fn bias(x: usize, y: usize) -> i32 {
+        (2 * BAYER_4X4[y % 4][x % 4] + 1) << 11
+    }
+}
+
+impl Layout for Dithered {
+    const BYTES_PER_PIXEL: usize = 4;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(rgba);
+    }
+
+    #[inline]
+    fn store_fixed(&self, rgb: [i32; 3], position: (usize, usize), out: &mut [u8]) {
+        out.copy_from_slice(&round(rgb, Self::bias(position.0, position.1)));
+    }
+
+    #[inline]
+    fn store_fixed_4x(&self, rgb: [i32x4; 3], position: (usize, usize), out: &mut [u8]) {
+        let (x, y) = position;
+        let bias = i32x4::from([
+            Self::bias(x, y),
+            Self::bias(x + 1, y),
+            Self::bias(x + 2, y),
+            Self::bias(x + 3, y),
+        ]);
+        self.store_4x(&round_4x(rgb, bias), out);
+    }
+}
+
+/// Interleaved RGBA with 16 bits per channel, in native byte order.
+///
+/// The fractional bits of each component are kept instead of being rounded
+/// off, scaled so that 0 and 65535 correspond to 0 and 255 in 8 bits.
+struct Rgba16;
+
+impl Layout for Rgba16 {
+    const BYTES_PER_PIXEL: usize = 8;
+
+    #[inline]
+    fn store(&self, rgba: &[u8; 4], out: &mut [u8]) {
+        for (&channel, out) in rgba.iter().zip(out.chunks_exact_mut(2)) {
+            out.copy_from_slice(&(channel as u16 * 257).to_ne_bytes());
+        }
+    }
+
+    #[inline]
+    fn store_fixed(&self, rgb: [i32; 3], _position: (usize, usize), out: &mut [u8]) {
+        // Scaling the 16.16 fixed point result by 257 maps 255 onto 65535.
+        let scale = |channel: i32| ((channel as i64 * 257 + 32768) >> 16).clamp(0, 65535) as u16;
+        let rgba = [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), 65535];
+
+        for (channel, out) in rgba.iter().zip(out.chunks_exact_mut(2)) {
+            out.copy_from_slice(&channel.to_ne_bytes());
+        }
+    }
+
+    // The scaling overflows 32 bits, so this takes one lane at a time.
+    #[inline]
+    fn store_fixed_4x(&self, rgb: [i32x4; 3], position: (usize, usize), out: &mut [u8]) {
+        let [r, g, b] = rgb.map(i32x4::to_array);
+        for (lane, out) in out.chunks_exact_mut(Self::BYTES_PER_PIXEL).enumerate() {
+            let rgb = [r[lane], g[lane], b[lane]];
+            self.store_fixed(rgb, (position.0 + lane, position.1), out);
+        }
+    }
 }
 
-/// Convert planar YUV 4:2:0 data into interleaved RGBA 8888 data.
-///
-/// This function yields an RGBA picture with the same number of pixels as were
-/// provided in the `y` picture. The `chroma_b` and `chroma_r` samples are
-/// simply reused without any interpolation for all four corresponding pixels.
-/// This is not the most correct, or nicest, but it's what Flash Player does.
+/// The implementations of the conversion loop to choose from at runtime.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ConversionPath {
+    /// One pixel at a time, with plain integer arithmetic.
+    Scalar,
+
+    /// Four pixels at a time, with whatever vector instructions the target
+    /// guarantees at build time.
+    Simd,
+
+    /// The same as `Simd`, but compiled to also make use of AVX2.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Avx2,
+}
+
+/// Whether the scalar conversion loop has been forced with `force_scalar`.
+static FORCE_SCALAR: AtomicBool = AtomicBool::new(false);
+
+/// Force all later conversions to use the scalar fallback, instead of the
+/// fastest vectorized code the CPU supports.
 ///
-/// Preconditions:
-///  - `y.len()` must be an integer multiple of `y_width`
-///  - `chroma_b.len()` and `chroma_r.len()` must both be integer multiples of `br_width`
-///  - `chroma_b` and `chroma_r` must be the same size
-///  - `br_width` must be half of `y_width`, rounded up
-///  - With `y_height` computed as `y.len() / y_width`, and `br_height` as `chroma_b.len() / br_width`:
-///    `br_height` must be half of `y_height`, rounded up
+/// The fallback produces exactly the same output, only more slowly. This is
+/// intended for testing and benchmarking, and applies to the whole process.
+pub fn force_scalar(force: bool) {
+    FORCE_SCALAR.store(force, Ordering::Relaxed);
+}
+
+/// Choose the conversion loop to use, as configured by `force_scalar`.
+fn conversion_path() -> ConversionPath {
+    if FORCE_SCALAR.load(Ordering::Relaxed) {
+        ConversionPath::Scalar
+    } else {
+        fastest_conversion_path()
+    }
+}
+
+/// Choose the fastest conversion loop this CPU can run.
 ///
-pub fn yuv420_to_rgba(
-    y: &[u8],
-    chroma_b: &[u8],
-    chroma_r: &[u8],
-    y_width: usize,
-    br_width: usize,
-) -> Vec<u8> {
-    // Shortcut for the no-op case to avoid all kinds of overflows below
-    if y.is_empty() {
-        debug_assert_eq!(chroma_b.len(), 0);
-        debug_assert_eq!(chroma_r.len(), 0);
-        debug_assert_eq!(y_width, 0);
-        debug_assert_eq!(br_width, 0);
-        return vec![];
+/// Without `std`, the CPU cannot be queried at runtime, so AVX2 is only used
+/// if it is enabled at compile time.
+fn fastest_conversion_path() -> ConversionPath {
+    #[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+    if is_x86_feature_detected!("avx2") {
+        return ConversionPath::Avx2;
     }
 
-    debug_assert_eq!(y.len() % y_width, 0);
-    debug_assert_eq!(chroma_b.len() % br_width, 0);
-    debug_assert_eq!(chroma_r.len() % br_width, 0);
-    debug_assert_eq!(chroma_b.len(), chroma_r.len());
+    #[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+    if cfg!(target_feature = "avx2") {
+        return ConversionPath::Avx2;
+    }
 
-    let y_height = y.len() / y_width;
-    let br_height = chroma_b.len() / br_width;
+    ConversionPath::Simd
+}
 
-    // the + 1 is for rounding odd numbers up
-    debug_assert_eq!((y_width + 1) / 2, br_width);
-    debug_assert_eq!((y_height + 1) / 2, br_height);
+/// Run the vectorized conversion loop with AVX2 enabled.
+///
+/// The caller must ensure that the CPU supports AVX2.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn convert_rows_avx2<T: Sample, L: Layout>(
+    layout: &L,
+    planes: &Planes<'_, T>,
+    coefficients: &Coefficients,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+) {
+    convert_rows_simd(layout, planes, coefficients, rgba, rgba_stride)
+}
 
-    let mut rgba = vec![0; y.len() * 4];
-    let rgba_stride = y_width * 4; // 4 bytes per pixel, interleaved
+/// Convert every row of a picture, four pixels at a time.
+#[inline(always)]
+fn convert_rows_simd<T: Sample, L: Layout>(
+    layout: &L,
+    planes: &Planes<'_, T>,
+    coefficients: &Coefficients,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+) {
+    let Planes {
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+        horizontal_subsampling,
+        chroma_interleaved,
+        ..
+    } = *planes;
+    let y_height = y.len() / y_width;
+    let rgba_width = y_width * L::BYTES_PER_PIXEL;
 
     // Iteration is done in a row-major order to fit the slice layouts.
     for luma_rowindex in 0..y_height {
-        let chroma_rowindex = luma_rowindex / 2;
+        let chroma_rowindex = planes.chroma_rowindex(luma_rowindex);
 
         let y_remainder = y_width % 4;
-        let br_remainder = br_width % 2;
-        let rgba_remainder = y_remainder * 4;
+        let br_remainder = if horizontal_subsampling {
+            br_width % 2
+        } else {
+            y_remainder
+        };
+        let rgba_remainder = y_remainder * L::BYTES_PER_PIXEL;
 
         // This block is here just so the mutable borrow of rgba_row expires sooner.
         {
-            // These borrows only include whole chunks of lengths 4 and 2.
+            // These borrows only include whole chunks of 4 luma samples, and
+            // the 2 or 4 chroma samples they use.
             let y_row = &y[luma_rowindex * y_width..(luma_rowindex + 1) * y_width - y_remainder];
-            let cb_row = &chroma_b
-                [chroma_rowindex * br_width..(chroma_rowindex + 1) * br_width - br_remainder];
-            let cr_row = &chroma_r
-                [chroma_rowindex * br_width..(chroma_rowindex + 1) * br_width - br_remainder];
-            let rgba_row = &mut rgba
-                [luma_rowindex * rgba_stride..(luma_rowindex + 1) * rgba_stride - rgba_remainder];
+            let chroma_range =
+                chroma_rowindex * br_width..(chroma_rowindex + 1) * br_width - br_remainder;
+            let rgba_row = &mut rgba[luma_rowindex * rgba_stride
+                ..luma_rowindex * rgba_stride + rgba_width - rgba_remainder];
 
             // TODO: Replace `bytemuck::cast_slice` with `std::slice::array_chunks` when it's stable.
 
             // Iterating on 4 pixels (in a horizontal row arrangement) at a time,
             // leaving off the last few on the right if width is not divisible by 4.
-            let y_iter = bytemuck::cast_slice::<u8, [u8; 4]>(y_row).iter();
-            // We need half as many chroma samples for each iteration
-            let cb_iter = bytemuck::cast_slice::<u8, [u8; 2]>(cb_row).iter();
-            let cr_iter = bytemuck::cast_slice::<u8, [u8; 2]>(cr_row).iter();
-            // Similar to how Y is iterated on, but with 4 channels per pixel
-            let rgba_iter = bytemuck::cast_slice_mut::<u8, [u8; 16]>(rgba_row).iter_mut();
-
-            for (((y, cb), cr), rgba) in y_iter.zip(cb_iter).zip(cr_iter).zip(rgba_iter) {
-                yuv_to_rgba_4x((y, cb, cr), rgba);
+            let y_iter = bytemuck::cast_slice::<T, [T; 4]>(y_row).iter();
+            // Similar to how Y is iterated on, but with 3 or 4 channels per pixel
+            let rgba_iter = rgba_row.chunks_exact_mut(4 * L::BYTES_PER_PIXEL);
+
+            if !horizontal_subsampling {
+                // Every pixel has its own chroma samples.
+                let cb_iter =
+                    bytemuck::cast_slice::<T, [T; 4]>(&chroma_b[chroma_range.clone()]).iter();
+                let cr_iter = bytemuck::cast_slice::<T, [T; 4]>(&chroma_r[chroma_range]).iter();
+
+                let pixels = y_iter.zip(cb_iter).zip(cr_iter).zip(rgba_iter);
+                for (chunk, (((y, cb), cr), out)) in pixels.enumerate() {
+                    let rgb = yuv_to_fixed_4x(
+                        (planes.load_luma_4x(y), T::load_4x(cb), T::load_4x(cr)),
+                        coefficients,
+                    );
+                    layout.store_fixed_4x(rgb, (chunk * 4, luma_rowindex), out);
+                }
+            } else if chroma_interleaved {
+                // Both chroma samples of each pair are next to each other, so
+                // the two pairs needed for an iteration take up 4 samples.
+                let uv_row = &chroma_b[chroma_range.start * 2..chroma_range.end * 2];
+                let uv_iter = bytemuck::cast_slice::<T, [T; 4]>(uv_row).iter();
+
+                for (chunk, ((y, uv), out)) in y_iter.zip(uv_iter).zip(rgba_iter).enumerate() {
+                    let cb = [uv[0], uv[2]];
+                    let cr = [uv[1], uv[3]];
+                    let rgb = yuv_to_fixed_4x(
+                        (
+                            planes.load_luma_4x(y),
+                            T::load_2x_doubled(&cb),
+                            T::load_2x_doubled(&cr),
+                        ),
+                        coefficients,
+                    );
+                    layout.store_fixed_4x(rgb, (chunk * 4, luma_rowindex), out);
+                }
+            } else {
+                // We need half as many chroma samples for each iteration
+                let cb_iter =
+                    bytemuck::cast_slice::<T, [T; 2]>(&chroma_b[chroma_range.clone()]).iter();
+                let cr_iter = bytemuck::cast_slice::<T, [T; 2]>(&chroma_r[chroma_range]).iter();
+
+                let pixels = y_iter.zip(cb_iter).zip(cr_iter).zip(rgba_iter);
+                for (chunk, (((y, cb), cr), out)) in pixels.enumerate() {
+                    // Expanding the 4 samples into a i32x4, and duplicating
+                    // chroma samples horizontally.
+                    let rgb = yuv_to_fixed_4x(
+                        (
+                            planes.load_luma_4x(y),
+                            T::load_2x_doubled(cb),
+                            T::load_2x_doubled(cr),
+                        ),
+                        coefficients,
+                    );
+                    layout.store_fixed_4x(rgb, (chunk * 4, luma_rowindex), out);
+                }
             }
         }
 
@@ -176,31 +1728,62 @@ pub fn yuv420_to_rgba(
         if y_remainder != 0 {
             // These are the same borrows as above, but with the whole row, not rounded down to multiples of 4 or 2.
             let y_row = &y[luma_rowindex * y_width..(luma_rowindex + 1) * y_width];
-            let cb_row = &chroma_b[chroma_rowindex * br_width..(chroma_rowindex + 1) * br_width];
-            let cr_row = &chroma_r[chroma_rowindex * br_width..(chroma_rowindex + 1) * br_width];
             let rgba_row =
-                &mut rgba[luma_rowindex * rgba_stride..(luma_rowindex + 1) * rgba_stride];
+                &mut rgba[luma_rowindex * rgba_stride..luma_rowindex * rgba_stride + rgba_width];
 
-            let mut y = [0u8; 4];
-            let mut cb = [0u8; 2];
-            let mut cr = [0u8; 2];
+            // Each pixel is given its own copy of the chroma samples it uses,
+            // whether or not they are shared with its neighbor.
+            let mut y = [T::default(); 4];
+            let mut cb = [T::default(); 4];
+            let mut cr = [T::default(); 4];
 
             for x in y_width - y_remainder..y_width {
                 y[x % 4] = y_row[x];
-                cb[(x % 4) / 2] = cb_row[x / 2];
-                cr[(x % 4) / 2] = cr_row[x / 2];
+                (cb[x % 4], cr[x % 4]) = planes.chroma_at(chroma_rowindex, x);
             }
 
-            let mut rgba_4x = [0u8; 16];
-            yuv_to_rgba_4x((&y, &cb, &cr), &mut rgba_4x);
+            let rgb = yuv_to_fixed_4x(
+                (planes.load_luma_4x(&y), T::load_4x(&cb), T::load_4x(&cr)),
+                coefficients,
+            );
+            let tail_x = y_width - y_remainder;
+            let mut packed_4x = [0u8; 4 * MAX_BYTES_PER_PIXEL];
+            layout.store_fixed_4x(
+                rgb,
+                (tail_x, luma_rowindex),
+                &mut packed_4x[..4 * L::BYTES_PER_PIXEL],
+            );
 
-            for i in rgba_stride - rgba_remainder..rgba_stride {
-                rgba_row[i] = rgba_4x[i % 16];
-            }
+            let tail_start = rgba_width - rgba_remainder;
+            rgba_row[tail_start..].copy_from_slice(&packed_4x[..rgba_remainder]);
         }
     }
+}
 
-    rgba
+/// Convert every row of a picture, one pixel at a time.
+///
+/// This yields exactly the same output as `convert_rows_simd`.
+fn convert_rows_scalar<T: Sample, L: Layout>(
+    layout: &L,
+    planes: &Planes<'_, T>,
+    coefficients: &Coefficients,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+) {
+    let Planes { y, y_width, .. } = *planes;
+
+    for (luma_rowindex, y_row) in y.chunks_exact(y_width).enumerate() {
+        let chroma_rowindex = planes.chroma_rowindex(luma_rowindex);
+        let rgba_row = &mut rgba[luma_rowindex * rgba_stride
+            ..luma_rowindex * rgba_stride + y_width * L::BYTES_PER_PIXEL];
+        let rgba_iter = rgba_row.chunks_exact_mut(L::BYTES_PER_PIXEL);
+
+        for (x, (&y, out)) in y_row.iter().zip(rgba_iter).enumerate() {
+            let (cb, cr) = planes.chroma_at(chroma_rowindex, x);
+            let rgb = yuv_to_fixed((planes.load_luma(y), cb.load(), cr.load()), coefficients);
+            layout.store_fixed(rgb, (x, luma_rowindex), out);
+        }
+    }
 }
 
 #[test]
@@ -230,6 +1813,20 @@ fn test_yuv_to_rgb() {
     // (16 + 235) / 2 = 125.5, for middle grays
     assert_eq!(yuv_to_rgb((125, 128, 128)), (127, 127, 127));
     assert_eq!(yuv_to_rgb((126, 128, 128)), (128, 128, 128));
+
+    // In full range, black and white are at the extremes instead
+    let full = |y: u8, cb: u8, cr: u8| {
+        yuv420_to_rgba_with(ColorMatrix::Bt601, Range::Full, &[y], &[cb], &[cr], 1, 1)
+    };
+    assert_eq!(full(0, 128, 128), vec![0, 0, 0, 255]);
+    assert_eq!(full(1, 128, 128), vec![1, 1, 1, 255]);
+    assert_eq!(full(16, 128, 128), vec![16, 16, 16, 255]);
+    assert_eq!(full(235, 128, 128), vec![235, 235, 235, 255]);
+    assert_eq!(full(255, 128, 128), vec![255, 255, 255, 255]);
+
+    // and the full color difference reaches the primaries
+    assert_eq!(full(76, 85, 255), vec![254, 0, 0, 255]);
+    assert_eq!(full(29, 255, 107), vec![0, 0, 254, 255]);
 }
 
 // Inverse conversion, for testing purposes only
@@ -489,3 +2086,652 @@ fn test_yuv420_to_rgba_medium() {
         ]
     );
 }
+
+// Luma and chroma planes of `y_len` and `chroma_len` samples, filled with
+// values that wrap around at different rates, so that neighbouring pixels
+// differ in every plane.
+#[cfg(test)]
+fn wrapping_planes(y_len: usize, chroma_len: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let y = (0..y_len).map(|i| (16 + i * 13) as u8).collect();
+    let cb = (0..chroma_len).map(|i| (40 + i * 29) as u8).collect();
+    let cr = (0..chroma_len).map(|i| (230 + i * 239) as u8).collect();
+
+    (y, cb, cr)
+}
+
+// Picture sizes for `wrapping_planes`, including odd ones with partial chroma
+// pairs, and ones that leave a few pixels after the last whole SIMD chunk of
+// a row.
+#[cfg(test)]
+const WRAPPING_SIZES: [(usize, usize); 7] =
+    [(1, 1), (3, 2), (4, 2), (5, 3), (7, 5), (16, 8), (17, 6)];
+
+#[test]
+fn test_yuv420_to_rgba_odd_sizes() {
+    // Every pixel of pictures with odd widths and heights, including those
+    // in the remainder of each row, should match the single-pixel conversion.
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+
+        let rgba = yuv420_to_rgba(&y, &cb, &cr, y_width, br_width);
+        assert_eq!(rgba.len(), y_width * y_height * 4);
+
+        for row in 0..y_height {
+            for column in 0..y_width {
+                let chroma = (row / 2) * br_width + column / 2;
+                let (r, g, b) = yuv_to_rgb((y[row * y_width + column], cb[chroma], cr[chroma]));
+                let i = (row * y_width + column) * 4;
+
+                assert_eq!(
+                    &rgba[i..i + 4],
+                    &[r, g, b, 255],
+                    "{}x{} at {}, {}",
+                    y_width,
+                    y_height,
+                    column,
+                    row
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_yuv420_to_rgba_into() {
+    let (y, cb, cr) = wrapping_planes(5 * 3, 3 * 2);
+
+    // The same buffer can be reused, and fully overwritten, for every picture.
+    let mut rgba = vec![0xAA; 60];
+    for _ in 0..2 {
+        assert_eq!(yuv420_to_rgba_into(&y, &cb, &cr, 5, 3, &mut rgba), Ok(()));
+        assert_eq!(rgba, yuv420_to_rgba(&y, &cb, &cr, 5, 3));
+    }
+
+    let mut short = vec![0xAA; 59];
+    assert_eq!(
+        yuv420_to_rgba_into(&y, &cb, &cr, 5, 3, &mut short),
+        Err(SizeError {
+            expected: 60,
+            actual: 59
+        })
+    );
+    assert!(short.iter().all(|&b| b == 0xAA));
+}
+
+#[test]
+fn test_yuv420_to_rgba_wgpu() {
+    // A 5x4 picture with a different value in every sample.
+    let (y, cb, cr) = wrapping_planes(5 * 4, 3 * 2);
+
+    let unpadded = yuv420_to_rgba(&y, &cb, &cr, 5, 3);
+    let (padded, bytes_per_row) = yuv420_to_rgba_wgpu(&y, &cb, &cr, 5, 3);
+
+    assert_eq!(bytes_per_row, 256);
+    assert_eq!(padded.len(), 256 * 4);
+
+    for (row, unpadded_row) in unpadded.chunks(5 * 4).enumerate() {
+        let padded_row = &padded[row * 256..(row + 1) * 256];
+        assert_eq!(&padded_row[..5 * 4], unpadded_row);
+        assert!(padded_row[5 * 4..].iter().all(|&b| b == 0));
+    }
+
+    // Rows that are already a multiple of 256 bytes long are not padded.
+    let y = vec![128u8; 64 * 2];
+    let cbcr = vec![128u8; 32];
+    let (rgba, bytes_per_row) = yuv420_to_rgba_wgpu(&y, &cbcr, &cbcr, 64, 32);
+    assert_eq!(bytes_per_row, 256);
+    assert_eq!(rgba, yuv420_to_rgba(&y, &cbcr, &cbcr, 64, 32));
+
+    // One more pixel needs another 256 bytes.
+    let y = vec![128u8; 65 * 2];
+    let cbcr = vec![128u8; 33];
+    let (rgba, bytes_per_row) = yuv420_to_rgba_wgpu(&y, &cbcr, &cbcr, 65, 33);
+    assert_eq!(bytes_per_row, 512);
+    assert_eq!(rgba.len(), 512 * 2);
+}
+
+#[test]
+fn test_yuv420_to_rgba_checked() {
+    let y = [1u8, 16, 235, 254];
+    let cbcr = [128u8];
+
+    // The lowest and highest valid values pass strict validation unchanged.
+    assert_eq!(
+        yuv420_to_rgba_checked(&y, &cbcr, &cbcr, 2, 1, true),
+        Ok(yuv420_to_rgba(&y, &cbcr, &cbcr, 2, 1))
+    );
+
+    let y = [1u8, 16, 235, 255];
+    let cb = [0u8];
+    assert_eq!(
+        yuv420_to_rgba_checked(&y, &cb, &cbcr, 2, 1, true),
+        Err(ReservedValue {
+            plane: Plane::Luma,
+            index: 3,
+            value: 255
+        })
+    );
+    assert_eq!(
+        yuv420_to_rgba_checked(&[16; 4], &cb, &cbcr, 2, 1, true),
+        Err(ReservedValue {
+            plane: Plane::ChromaB,
+            index: 0,
+            value: 0
+        })
+    );
+
+    // Without validation, reserved values are clamped instead.
+    assert_eq!(
+        yuv420_to_rgba_checked(&y, &cb, &cbcr, 2, 1, false),
+        Ok(yuv420_to_rgba(&[1, 16, 235, 254], &[1], &cbcr, 2, 1))
+    );
+}
+
+#[test]
+fn test_yuv420_i16_to_rgba() {
+    // A 5x3 picture, so the odd-width tail is covered too.
+    let y: Vec<i16> = (0..15).map(|i| i * 40 - 120).collect();
+    let cb: Vec<i16> = (0..6).map(|i| 300 - i * 90).collect();
+    let cr: Vec<i16> = (0..6).map(|i| i * 70 - 50).collect();
+
+    let clamp =
+        |samples: &[i16]| -> Vec<u8> { samples.iter().map(|&s| s.clamp(0, 255) as u8).collect() };
+
+    assert_eq!(
+        yuv420_i16_to_rgba(&y, &cb, &cr, 5, 3),
+        yuv420_to_rgba(&clamp(&y), &clamp(&cb), &clamp(&cr), 5, 3)
+    );
+}
+
+#[test]
+fn test_yuv420_to_rgba_interpolated() {
+    // A horizontal ramp in chroma-blue, and a vertical one in chroma-red,
+    // over a 5x3 picture of middle gray.
+    let y = [126u8; 15];
+    let cb = [100u8, 160, 220, 100, 160, 220];
+    let cr = [60u8, 60, 60, 180, 180, 180];
+
+    let rgba = yuv420_to_rgba_interpolated(&y, &cb, &cr, 5, 3);
+
+    // Each luma sample takes 3/4 of the nearest chroma sample, and 1/4 of
+    // the next nearest, clamped at the edges.
+    let expected_cb = [100, 115, 145, 175, 205];
+    let expected_cr = [60, 90, 150];
+
+    for (row, &cr) in expected_cr.iter().enumerate() {
+        for (column, &cb) in expected_cb.iter().enumerate() {
+            let (r, g, b) = yuv_to_rgb((126, cb, cr));
+            let i = (row * 5 + column) * 4;
+            assert_eq!(&rgba[i..i + 4], &[r, g, b, 255], "{}, {}", column, row);
+        }
+    }
+
+    // Flat chroma interpolates to itself.
+    let flat = yuv420_to_rgba_interpolated(&y, &[90; 6], &[200; 6], 5, 3);
+    assert_eq!(flat, yuv420_to_rgba(&y, &[90; 6], &[200; 6], 5, 3));
+}
+
+#[test]
+fn test_conversion_paths_match() {
+    // A small xorshift generator, so that the inputs are the same every run.
+    let mut state = 0x2545_F491_u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    fn convert<T: Sample>(
+        path: ConversionPath,
+        planes: &Planes<'_, T>,
+        coefficients: &Coefficients,
+    ) -> Vec<u8> {
+        let mut rgba = vec![0; planes.y.len() * 4];
+        let stride = planes.y_width * 4;
+        let layout = &Rgba;
+
+        match path {
+            ConversionPath::Scalar => {
+                convert_rows_scalar(layout, planes, coefficients, &mut rgba, stride)
+            }
+            ConversionPath::Simd => {
+                convert_rows_simd(layout, planes, coefficients, &mut rgba, stride)
+            }
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            // SAFETY: `Avx2` is only ever passed in when
+            // `fastest_conversion_path` found that the CPU supports AVX2.
+            ConversionPath::Avx2 => unsafe {
+                convert_rows_avx2(layout, planes, coefficients, &mut rgba, stride)
+            },
+        }
+
+        rgba
+    }
+
+    for _ in 0..200 {
+        let y_width = 1 + random() as usize % 37;
+        let y_height = 1 + random() as usize % 19;
+        // Either 4:2:0, or 4:4:4 without any subsampling.
+        let is_subsampled = random() % 2 == 0;
+        let (br_width, br_len) = if is_subsampled {
+            let br_width = y_width.div_ceil(2);
+            (br_width, br_width * y_height.div_ceil(2))
+        } else {
+            (y_width, y_width * y_height)
+        };
+        let matrix = if random() % 2 == 0 {
+            ColorMatrix::Bt601
+        } else {
+            ColorMatrix::Bt709
+        };
+        let range = if random() % 2 == 0 {
+            Range::Studio
+        } else {
+            Range::Full
+        };
+        let coefficients = matrix.coefficients(range);
+
+        // The baseline vector code, and whatever the CPU can do beyond it.
+        let paths = [ConversionPath::Simd, fastest_conversion_path()];
+
+        let y: Vec<u8> = (0..y_width * y_height).map(|_| random() as u8).collect();
+        let cb: Vec<u8> = (0..br_len).map(|_| random() as u8).collect();
+        let cr: Vec<u8> = (0..br_len).map(|_| random() as u8).collect();
+        let planes = Planes {
+            y: &y[..],
+            chroma_b: &cb[..],
+            chroma_r: &cr[..],
+            y_width,
+            br_width,
+            horizontal_subsampling: is_subsampled,
+            vertical_subsampling: is_subsampled,
+            chroma_interleaved: false,
+            luma_lut: None,
+        };
+        let expected = convert(ConversionPath::Scalar, &planes, coefficients);
+        for &path in paths.iter() {
+            assert_eq!(convert(path, &planes, coefficients), expected, "{:?}", path);
+        }
+
+        // Out-of-range 16-bit samples have to saturate the same way, too.
+        let y: Vec<i16> = (0..y_width * y_height)
+            .map(|_| random() as i16 >> 6)
+            .collect();
+        let cb: Vec<i16> = (0..br_len).map(|_| random() as i16 >> 6).collect();
+        let cr: Vec<i16> = (0..br_len).map(|_| random() as i16 >> 6).collect();
+        let planes = Planes {
+            y: &y[..],
+            chroma_b: &cb[..],
+            chroma_r: &cr[..],
+            y_width,
+            br_width,
+            horizontal_subsampling: is_subsampled,
+            vertical_subsampling: is_subsampled,
+            chroma_interleaved: false,
+            luma_lut: None,
+        };
+        let expected = convert(ConversionPath::Scalar, &planes, coefficients);
+        for &path in paths.iter() {
+            assert_eq!(convert(path, &planes, coefficients), expected, "{:?}", path);
+        }
+    }
+}
+
+#[test]
+fn test_force_scalar() {
+    // Other tests may run while the scalar loop is forced, which only slows
+    // them down, as the output is the same. The guard still undoes it if the
+    // conversion panics, so that it doesn't outlive this test.
+    struct ForcedScalar;
+    impl Drop for ForcedScalar {
+        fn drop(&mut self) {
+            force_scalar(false);
+        }
+    }
+
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+
+        let fast = yuv420_to_rgba(&y, &cb, &cr, y_width, br_width);
+        let slow = {
+            force_scalar(true);
+            let _forced = ForcedScalar;
+            yuv420_to_rgba(&y, &cb, &cr, y_width, br_width)
+        };
+        assert!(!FORCE_SCALAR.load(Ordering::Relaxed));
+
+        assert_eq!(fast, slow, "{}x{}", y_width, y_height);
+    }
+}
+
+#[test]
+fn test_yuv420_to_rgb24() {
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+
+        let rgba = yuv420_to_rgba(&y, &cb, &cr, y_width, br_width);
+        let rgb = yuv420_to_rgb24(&y, &cb, &cr, y_width, br_width);
+
+        assert_eq!(rgb.len(), y_width * y_height * 3);
+        for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+            assert_eq!(&rgba[..3], rgb);
+        }
+    }
+}
+
+#[test]
+fn test_yuv420_to_bgra() {
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+
+        let mut swapped = yuv420_to_rgba(&y, &cb, &cr, y_width, br_width);
+        for pixel in swapped.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+
+        assert_eq!(yuv420_to_bgra(&y, &cb, &cr, y_width, br_width), swapped);
+    }
+}
+
+#[test]
+fn test_yuv422_to_rgba() {
+    // Each row of a 4:2:2 picture should convert like a 4:2:0 picture of a
+    // single row, with its own row of chroma.
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_width * y_height);
+
+        let rgba = yuv422_to_rgba(&y, &cb, &cr, y_width, br_width);
+        assert_eq!(rgba.len(), y_width * y_height * 4);
+
+        for row in 0..y_height {
+            let expected = yuv420_to_rgba(
+                &y[row * y_width..(row + 1) * y_width],
+                &cb[row * br_width..(row + 1) * br_width],
+                &cr[row * br_width..(row + 1) * br_width],
+                y_width,
+                br_width,
+            );
+
+            assert_eq!(
+                &rgba[row * y_width * 4..(row + 1) * y_width * 4],
+                &expected[..]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_yuv444_to_rgba() {
+    // Every pixel of a 5x3 picture has distinct chroma, none of it shared.
+    let (y, cb, cr) = wrapping_planes(15, 15);
+
+    let rgba = yuv444_to_rgba(&y, &cb, &cr, 5, 5);
+    assert_eq!(rgba.len(), 60);
+
+    for i in 0..15 {
+        let (r, g, b) = yuv_to_rgb((y[i], cb[i], cr[i]));
+        assert_eq!(&rgba[i * 4..i * 4 + 4], &[r, g, b, 255], "pixel {}", i);
+    }
+
+    assert_eq!(yuv444_to_rgba(&[], &[], &[], 0, 0), vec![0u8; 0]);
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_yuv420_to_rgba_parallel() {
+    // A 720p picture, plus one with an odd size that leaves a partial band.
+    for &(width, height) in &[(1280usize, 720usize), (67, 41)] {
+        let br_width = width.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(width * height, br_width * height.div_ceil(2));
+
+        assert_eq!(
+            yuv420_to_rgba(&y, &cb, &cr, width, br_width),
+            yuv420_to_rgba_parallel(&y, &cb, &cr, width, br_width)
+        );
+    }
+
+    assert_eq!(yuv420_to_rgba_parallel(&[], &[], &[], 0, 0), vec![0u8; 0]);
+}
+
+#[test]
+fn test_yuv420_to_rgba_strided() {
+    for (width, height) in WRAPPING_SIZES {
+        let br_width = width.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(width * height, br_width * height.div_ceil(2));
+
+        // Pad every row out to a multiple of 32 samples with junk.
+        let pad = |plane: &[u8], row_len: usize| -> (Vec<u8>, usize) {
+            let stride = (row_len + 1).div_ceil(32) * 32;
+            let mut padded = vec![0xEE; plane.len() / row_len * stride];
+            for (row, padded_row) in plane.chunks(row_len).zip(padded.chunks_mut(stride)) {
+                padded_row[..row_len].copy_from_slice(row);
+            }
+
+            (padded, stride)
+        };
+        let (padded_y, y_stride) = pad(&y, width);
+        let (padded_cb, chroma_stride) = pad(&cb, br_width);
+        let (padded_cr, _) = pad(&cr, br_width);
+
+        assert_eq!(
+            yuv420_to_rgba(&y, &cb, &cr, width, br_width),
+            yuv420_to_rgba_strided(
+                &padded_y,
+                y_stride,
+                &padded_cb,
+                &padded_cr,
+                chroma_stride,
+                width,
+                height
+            ),
+            "{}x{}",
+            width,
+            height
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "luma rows are longer than their stride")]
+fn test_yuv420_to_rgba_strided_rejects_short_stride() {
+    yuv420_to_rgba_strided(&[0; 16], 4, &[0; 4], &[0; 4], 2, 8, 2);
+}
+
+#[test]
+fn test_nv12_to_rgba() {
+    for (width, height) in WRAPPING_SIZES {
+        let br_width = width.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(width * height, br_width * height.div_ceil(2));
+        let uv: Vec<u8> = cb.iter().zip(&cr).flat_map(|(&b, &r)| vec![b, r]).collect();
+
+        let expected = yuv420_to_rgba(&y, &cb, &cr, width, br_width);
+        assert_eq!(
+            expected,
+            nv12_to_rgba(&y, &uv, width),
+            "{}x{}",
+            width,
+            height
+        );
+
+        // Both conversion loops have to read the interleaved plane the same way.
+        let planes = Planes {
+            y: &y[..],
+            chroma_b: &uv[..],
+            chroma_r: &uv[..],
+            y_width: width,
+            br_width,
+            horizontal_subsampling: true,
+            vertical_subsampling: true,
+            chroma_interleaved: true,
+            luma_lut: None,
+        };
+        let coefficients = ColorMatrix::Bt601.coefficients(Range::Studio);
+        let mut scalar = vec![0; y.len() * 4];
+        convert_rows_scalar(&Rgba, &planes, coefficients, &mut scalar, width * 4);
+        let mut simd = vec![0; y.len() * 4];
+        convert_rows_simd(&Rgba, &planes, coefficients, &mut simd, width * 4);
+        assert_eq!(expected, scalar, "{}x{}", width, height);
+        assert_eq!(expected, simd, "{}x{}", width, height);
+    }
+
+    assert_eq!(nv12_to_rgba(&[], &[], 0), vec![0u8; 0]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_yuv420_to_rgba_adjusted() {
+    let y: Vec<u8> = (0..=255).collect();
+    let cb: Vec<u8> = (0..64).map(|i| 40 + i * 3).collect();
+    let cr: Vec<u8> = (0..64).map(|i| 220 - i * 2).collect();
+    let plain = yuv420_to_rgba(&y, &cb, &cr, 16, 8);
+
+    // Identity adjustments have to change nothing at all.
+    assert_eq!(plain, yuv420_to_rgba_adjusted(&y, &cb, &cr, 16, 8, None));
+    assert_eq!(
+        plain,
+        yuv420_to_rgba_adjusted(&y, &cb, &cr, 16, 8, Some(ToneAdjust::default()))
+    );
+
+    // With a gamma of 2.2, mid-gray darkens to (128 / 255) ^ 2.2 * 255 = 56,
+    // while black and white stay put.
+    let gamma = ToneAdjust {
+        gamma: 2.2,
+        ..Default::default()
+    };
+    let lut = gamma.lookup_table();
+    assert_eq!((0, 56, 255), (lut[0], lut[128], lut[255]));
+    assert_eq!(
+        yuv420_to_rgba(&[56; 4], &[128], &[128], 2, 1),
+        yuv420_to_rgba_adjusted(&[128; 4], &[128], &[128], 2, 1, Some(gamma))
+    );
+
+    // Brightness saturates rather than wrapping around.
+    let brighter = ToneAdjust {
+        brightness: 20,
+        ..Default::default()
+    };
+    let lut = brighter.lookup_table();
+    assert_eq!((20, 148, 255), (lut[0], lut[128], lut[250]));
+}
+
+#[test]
+fn test_yuv420_to_rgba16() {
+    let gray = |luma: u8| yuv420_to_rgba16(&[luma; 4], &[128], &[128], 2, 1);
+
+    assert_eq!(gray(16), [0, 0, 0, 65535].repeat(4));
+    assert_eq!(gray(235), [65535, 65535, 65535, 65535].repeat(4));
+
+    // (128 - 16) * 255 / 219 = 130.41 in 8 bits, which rounds to 33516 in 16.
+    assert_eq!(gray(128), [33516, 33516, 33516, 65535].repeat(4));
+
+    // Rounding the 16-bit values down to 8 bits has to agree with the 8-bit
+    // conversion, give or take the rounding error.
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+
+        for (wide, narrow) in yuv420_to_rgba16(&y, &cb, &cr, y_width, br_width)
+            .iter()
+            .zip(yuv420_to_rgba(&y, &cb, &cr, y_width, br_width))
+        {
+            assert!((*wide as i32 - narrow as i32 * 257).abs() <= 128);
+        }
+    }
+
+    assert_eq!(yuv420_to_rgba16(&[], &[], &[], 0, 0), vec![0u16; 0]);
+}
+
+#[test]
+fn test_yuv420_to_rgba_dithered() {
+    // A smooth gradient in all three planes, with a size that doesn't tile.
+    let y: Vec<u8> = (0..90).map(|i| 20 + i * 2).collect();
+    let cb: Vec<u8> = (0..25).map(|i| 60 + i * 5).collect();
+    let cr: Vec<u8> = (0..25).map(|i| 200 - i * 4).collect();
+
+    let plain = yuv420_to_rgba(&y, &cb, &cr, 9, 5);
+    let dithered = yuv420_to_rgba_dithered(&y, &cb, &cr, 9, 5);
+    assert_eq!(plain.len(), dithered.len());
+    for (p, d) in plain.iter().zip(&dithered) {
+        assert!((*p as i32 - *d as i32).abs() <= 1);
+    }
+
+    let mean = |rgba: &[u8]| rgba.iter().map(|&c| c as f64).sum::<f64>() / rgba.len() as f64;
+    assert!((mean(&plain) - mean(&dithered)).abs() < 0.5);
+
+    // On a flat 4x4 tile, the dither averages to the exact value, 130.41,
+    // which plain rounding can only approximate as 130.
+    let dithered = yuv420_to_rgba_dithered(&[128; 16], &[128; 4], &[128; 4], 4, 2);
+    let gray: Vec<u8> = dithered.chunks_exact(4).map(|p| p[0]).collect();
+    assert!(gray.iter().all(|&g| g == 130 || g == 131));
+    assert!((mean(&gray) - 130.41).abs() < 1.0 / 16.0);
+
+    // The pattern is fixed, so converting again gives the same output.
+    assert_eq!(
+        dithered,
+        yuv420_to_rgba_dithered(&[128; 16], &[128; 4], &[128; 4], 4, 2)
+    );
+
+    assert_eq!(yuv420_to_rgba_dithered(&[], &[], &[], 0, 0), vec![0u8; 0]);
+}
+
+#[test]
+fn test_yuv_gray_to_rgba() {
+    // Every luma value, in a picture with an odd size.
+    let y: Vec<u8> = (0..=255).chain(0..10).collect();
+    let chroma = vec![128; 10 * 7];
+
+    assert_eq!(
+        yuv420_to_rgba(&y, &chroma, &chroma, 19, 10),
+        yuv_gray_to_rgba(&y, 19)
+    );
+
+    assert_eq!(yuv_gray_to_rgba(&[], 0), vec![0u8; 0]);
+}
+
+#[test]
+fn test_yuv420_to_rgba_with_alpha() {
+    for (y_width, y_height) in WRAPPING_SIZES {
+        let br_width = y_width.div_ceil(2);
+        let br_len = br_width * y_height.div_ceil(2);
+        let (y, cb, cr) = wrapping_planes(y_width * y_height, br_len);
+        let convert = |alpha, premultiply| {
+            yuv420_to_rgba_with_alpha(&y, &cb, &cr, y_width, br_width, alpha, premultiply)
+        };
+        let opaque = yuv420_to_rgba(&y, &cb, &cr, y_width, br_width);
+
+        for &alpha in &[0, 128, 255] {
+            let straight = convert(alpha, false);
+            let premultiplied = convert(alpha, true);
+            assert_eq!(straight.len(), opaque.len());
+
+            for ((opaque, straight), premultiplied) in opaque
+                .chunks(4)
+                .zip(straight.chunks(4))
+                .zip(premultiplied.chunks(4))
+            {
+                assert_eq!(&opaque[..3], &straight[..3]);
+                assert_eq!(alpha, straight[3]);
+                assert_eq!(alpha, premultiplied[3]);
+
+                for (color, premultiplied) in opaque[..3].iter().zip(&premultiplied[..3]) {
+                    let expected = (*color as f32 * alpha as f32 / 255.0).round() as u8;
+                    assert_eq!(expected, *premultiplied);
+                }
+            }
+        }
+
+        // Opaque pixels are unaffected by premultiplication.
+        assert_eq!(opaque, convert(255, true));
+    }
+}
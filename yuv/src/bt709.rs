@@ -0,0 +1,81 @@
+//! YUV-to-RGB decode with the BT.709 color matrix
+
+use crate::bt601::{yuv420_to_rgba_with, ColorMatrix, Range};
+use alloc::vec::Vec;
+
+/// Convert planar YUV 4:2:0 data in the BT.709 color space into interleaved
+/// RGBA 8888 data.
+///
+/// This is the BT.709 counterpart of `bt601::yuv420_to_rgba`, and has the
+/// same preconditions. Chroma samples are likewise reused without any
+/// interpolation for all four corresponding pixels.
+pub fn yuv420_to_rgba(
+    y: &[u8],
+    chroma_b: &[u8],
+    chroma_r: &[u8],
+    y_width: usize,
+    br_width: usize,
+) -> Vec<u8> {
+    yuv420_to_rgba_with(
+        ColorMatrix::Bt709,
+        Range::Studio,
+        y,
+        chroma_b,
+        chroma_r,
+        y_width,
+        br_width,
+    )
+}
+
+#[test]
+fn test_bt709_primaries() {
+    // Studio-range BT.709 encodings of the primaries and secondaries,
+    // with the RGB colors they should decode back into (give or take the
+    // rounding in each direction).
+    for (yuv, rgb) in [
+        ((16, 128, 128), (0, 0, 0)),
+        ((235, 128, 128), (255, 255, 255)),
+        ((63, 102, 240), (255, 0, 0)),
+        ((173, 42, 26), (0, 255, 0)),
+        ((32, 240, 118), (0, 0, 255)),
+        ((188, 154, 16), (0, 255, 255)),
+        ((78, 214, 230), (255, 0, 255)),
+        ((219, 16, 138), (255, 255, 0)),
+    ] {
+        let rgba = yuv420_to_rgba(&[yuv.0], &[yuv.1], &[yuv.2], 1, 1);
+
+        assert!((rgba[0] as i32 - rgb.0).abs() <= 1, "{:?}", yuv);
+        assert!((rgba[1] as i32 - rgb.1).abs() <= 1, "{:?}", yuv);
+        assert!((rgba[2] as i32 - rgb.2).abs() <= 1, "{:?}", yuv);
+        assert_eq!(rgba[3], 255);
+    }
+}
+
+#[test]
+fn test_bt709_differs_from_bt601() {
+    use crate::bt601;
+
+    // Gray is the same in both.
+    assert_eq!(
+        yuv420_to_rgba(&[126], &[128], &[128], 1, 1),
+        bt601::yuv420_to_rgba(&[126], &[128], &[128], 1, 1)
+    );
+
+    // Colors are not: BT.709 red decodes with BT.601 as a darker red.
+    assert_eq!(
+        bt601::yuv420_to_rgba(&[63], &[102], &[240], 1, 1),
+        alloc::vec![233, 0, 2, 255]
+    );
+    assert_eq!(
+        yuv420_to_rgba_with(
+            ColorMatrix::Bt601,
+            Range::Studio,
+            &[63],
+            &[102],
+            &[240],
+            1,
+            1
+        ),
+        bt601::yuv420_to_rgba(&[63], &[102], &[240], 1, 1)
+    );
+}
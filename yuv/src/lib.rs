@@ -1,3 +1,12 @@
-//! Pure-rust BT.601 YUV color space support
+//! Pure-rust BT.601 and BT.709 YUV color space support
+//!
+//! Disabling the default `std` feature makes this crate `no_std`, needing
+//! only `alloc`. The `_into` conversions write into caller-provided buffers,
+//! and so do not allocate at all.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 pub mod bt601;
+pub mod bt709;